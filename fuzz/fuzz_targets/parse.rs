@@ -0,0 +1,29 @@
+#![no_main]
+
+//! Parses arbitrary input and reads the result back in every way
+//! a user would, none of which should panic.
+//!
+//! Run with `cargo fuzz run parse`, inputs which crashed before
+//! live in `fuzz/crashers` and are checked by the unit tests.
+
+use libfuzzer_sys::fuzz_target;
+use noumea::block_parser::Parser;
+use noumea::render::html::push_html;
+
+fuzz_target!(|data: &[u8]| {
+    let src = String::from_utf8_lossy(data);
+    let document = Parser::new().parse_document(&src);
+
+    for id in document.tree().nodes() {
+        let node = &document.tree().get(id).unwrap().data;
+        assert!(node.pos.start <= node.pos.end && node.pos.end <= src.len());
+        let _ = node.as_str(&document);
+    }
+
+    let mut html = String::new();
+    push_html(&mut html, document.events());
+
+    for diagnostic in document.diagnostics() {
+        let _ = diagnostic.pretty(&document);
+    }
+});
@@ -1,8 +1,9 @@
 use crate::lib::{Box, String, ToString};
+use crate::source::Sources;
 use core::fmt::Debug;
 use core::num::NonZero;
 
-#[derive(Debug, PartialEq, PartialOrd, Ord, Eq)]
+#[derive(Debug, PartialEq, PartialOrd, Ord, Eq, Clone)]
 pub struct CodeMeta {
     lang: Lang,
     info: Option<String>,
@@ -37,6 +38,16 @@ impl Lang {
         matches!(self, Self::None | Self::NotSupported(_))
     }
 
+    /// Returns the name of the language
+    /// as it would appear after a code fence.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Self::None => None,
+            Self::Rust => Some("rust"),
+            Self::NotSupported(name) => Some(name),
+        }
+    }
+
     pub fn recognize(name: &str) -> Lang {
         match name {
             "rust" => Lang::Rust,
@@ -49,7 +60,7 @@ impl Lang {
 }
 
 /// Value of a Markdown AST node.
-#[derive(Debug, PartialEq, PartialOrd, Ord, Eq)]
+#[derive(Debug, PartialEq, PartialOrd, Ord, Eq, Clone)]
 pub enum Value {
     // Block elements
     /// The root of the document
@@ -159,29 +170,179 @@ pub enum Value {
     Text,
 }
 
+impl Value {
+    /// Returns the name of this kind of node, in kebab-case.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Root => "root",
+            Self::Macro { .. } => "macro",
+            Self::Paragraph => "paragraph",
+            Self::Blockquote => "blockquote",
+            Self::HtmlBlock => "html-block",
+            Self::StyleBreak => "style-break",
+            Self::Heading { .. } => "heading",
+            Self::BulletList { .. } => "bullet-list",
+            Self::OrderedList { .. } => "ordered-list",
+            Self::ListItem => "list-item",
+            Self::Code { .. } => "code",
+            Self::Emphasis { .. } => "emphasis",
+            Self::Link => "link",
+            Self::Image => "image",
+            Self::Strikethrough => "strikethrough",
+            Self::Emoji => "emoji",
+            Self::HardBreak => "hard-break",
+            Self::SoftBreak => "soft-break",
+            Self::EscapeChar(_) => "escape-char",
+            Self::Text => "text",
+        }
+    }
+
+    /// Returns a value of every kind of node, in declaration order.
+    pub(crate) fn kinds() -> [Value; 20] {
+        [
+            Self::Root,
+            Self::Macro {
+                name: Box::default(),
+            },
+            Self::Paragraph,
+            Self::Blockquote,
+            Self::HtmlBlock,
+            Self::StyleBreak,
+            Self::Heading {
+                level: NonZero::<u8>::MIN,
+            },
+            Self::BulletList { tight: false },
+            Self::OrderedList {
+                tight: false,
+                start_index: 1,
+            },
+            Self::ListItem,
+            Self::Code {
+                lang: None,
+                meta: None,
+            },
+            Self::Emphasis { strong: false },
+            Self::Link,
+            Self::Image,
+            Self::Strikethrough,
+            Self::Emoji,
+            Self::HardBreak,
+            Self::SoftBreak,
+            Self::EscapeChar('\\'),
+            Self::Text,
+        ]
+    }
+
+    /// Returns `true` for the kinds of inline nodes, found inside
+    /// of paragraphs and headings.
+    pub fn is_inline(&self) -> bool {
+        matches!(
+            self,
+            Self::Emphasis { .. }
+                | Self::Link
+                | Self::Image
+                | Self::Strikethrough
+                | Self::Emoji
+                | Self::HardBreak
+                | Self::SoftBreak
+                | Self::EscapeChar(_)
+                | Self::Text
+        )
+    }
+
+    /// Returns the language of a `Code` node, if it has one.
+    pub fn lang(&self) -> Option<&Lang> {
+        match self {
+            Self::Code { lang, meta } => lang.as_ref().or(meta.as_ref().map(CodeMeta::lang)),
+
+            _ => None,
+        }
+    }
+}
+
+/// Identifies the source data a `Position` points into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct SourceId(u32);
+
+impl SourceId {
+    /// The data handed to the parser.
+    pub const MAIN: SourceId = SourceId(0);
+
+    /// Marks nodes which were synthesized
+    /// instead of coming out of any source.
+    pub const GENERATED: SourceId = SourceId(u32::MAX);
+
+    /// Creates a new `SourceId`.
+    pub const fn new(index: u32) -> Self {
+        Self(index)
+    }
+
+    /// Returns the index of the source.
+    pub const fn index(self) -> u32 {
+        self.0
+    }
+}
+
 /// Position of the AST node in the
 /// source data.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Position {
     pub start: usize,
     pub end: usize,
+
+    /// Source data the range points into.
+    pub source: SourceId,
 }
 
 impl Debug for Position {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(
-            f,
-            "Position: {{ start: {}, end: {} }}",
-            self.start, self.end
-        )
+        if self.is_generated() {
+            write!(f, "Position: generated")
+        } else if self.source == SourceId::MAIN {
+            write!(
+                f,
+                "Position: {{ start: {}, end: {} }}",
+                self.start, self.end
+            )
+        } else {
+            write!(
+                f,
+                "Position: {{ start: {}, end: {}, source: {} }}",
+                self.start,
+                self.end,
+                self.source.index()
+            )
+        }
     }
 }
 
 impl Position {
-    pub const ZERO_ZERO: Position = Position { start: 0, end: 0 };
+    pub const ZERO_ZERO: Position = Position::new(0, 0);
     /// Creates a new `Position`
-    pub fn new(start: usize, end: usize) -> Self {
-        Self { start, end }
+    /// in the main source.
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end,
+            source: SourceId::MAIN,
+        }
+    }
+
+    /// Creates the position of a synthesized node,
+    /// which doesn't point into any source.
+    pub const fn generated() -> Self {
+        Self::new(0, 0).with_source(SourceId::GENERATED)
+    }
+
+    /// Returns `true` if the node was synthesized.
+    pub fn is_generated(&self) -> bool {
+        self.source == SourceId::GENERATED
+    }
+
+    /// Returns the same range in the given `source`.
+    #[must_use]
+    pub const fn with_source(self, source: SourceId) -> Self {
+        Self { source, ..self }
     }
 
     /// Cuts out a subslice representing the position.
@@ -197,10 +358,36 @@ impl Position {
     }
 }
 
+/// Where the text of an `AstNode` comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum TextSource {
+    /// The text is the range of the source under the node's `Position`.
+    #[default]
+    Span,
+
+    /// The node carries its own text,
+    /// e.g. because it was produced by a transform.
+    Owned(Box<str>),
+}
+
+impl From<&str> for TextSource {
+    fn from(text: &str) -> Self {
+        Self::Owned(text.into())
+    }
+}
+
+impl From<String> for TextSource {
+    fn from(text: String) -> Self {
+        Self::Owned(text.into_boxed_str())
+    }
+}
+
 /// AST Node of a Markdown document
+#[derive(Clone)]
 pub struct AstNode {
     pub value: Value,
     pub pos: Position,
+    pub text: TextSource,
     id: usize,
 }
 
@@ -209,6 +396,7 @@ impl Debug for AstNode {
         f.debug_struct("AstNode")
             .field("value", &self.value)
             .field("pos", &self.pos)
+            .field("text", &self.text)
             .field("id", &self.id)
             .finish()
     }
@@ -216,17 +404,52 @@ impl Debug for AstNode {
 
 impl AstNode {
     pub fn new(value: Value, pos: Position, id: usize) -> Self {
-        Self { value, pos, id }
+        Self {
+            value,
+            pos,
+            text: TextSource::Span,
+            id,
+        }
     }
 
-    pub fn as_str<'a, 'b>(&'b self, data: &'a str) -> &'a str
+    /// Creates a generated node carrying its own `text`.
+    pub fn with_text(value: Value, text: impl Into<TextSource>) -> Self {
+        Self {
+            value,
+            pos: Position::generated(),
+            text: text.into(),
+            id: 0,
+        }
+    }
+
+    /// Replaces the text of this node with an owned `text`,
+    /// keeping its position.
+    pub fn set_text(&mut self, text: impl Into<TextSource>) {
+        self.text = text.into();
+    }
+
+    /// Returns the text of this node.
+    ///
+    /// Owned text is returned as is, otherwise the text is
+    /// looked up in `sources`, which is either the original string
+    /// the node was parsed from, or a `SourceMap` holding every
+    /// source of the document.
+    /// Generated nodes without owned text, as well as nodes
+    /// whose source is missing from `sources` or too short
+    /// for their position, have no text.
+    pub fn as_str<'a, S>(&'a self, sources: &'a S) -> &'a str
     where
-        'a: 'b,
+        S: Sources + ?Sized,
     {
-        // dbg!(self.pos);
-        self.pos
-            .view_substring(data)
-            .unwrap_or_else(|| unreachable!("the range was out-of-bounds in `as_str`"))
+        if let TextSource::Owned(text) = &self.text {
+            return text;
+        }
+
+        let Some(data) = sources.text(self.pos.source) else {
+            return "";
+        };
+
+        self.pos.view_substring(data).unwrap_or("")
     }
 
     pub fn value_mut(&mut self) -> &mut Value {
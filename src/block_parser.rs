@@ -2,13 +2,165 @@
 #![allow(dead_code)]
 
 use crate::{
-    ast::{AstNode, Position, Value},
-    scan::{Input, MacroSpan},
-    tree::{NodeId, TreeArena},
+    ast::{AstNode, CodeMeta, Lang, Position, SourceId, Value},
+    diagnostic::{self, Diagnostic},
+    document::Document,
+    lib::{Box, ToString, Vec},
+    scan::{FenceSpan, Input, MacroSpan},
+    syntax::{Syntax, SyntaxToken, TokenKind},
+    tree::{CapacityError, NodeId, TreeArena},
 };
 
 use core::num::NonZero;
 
+/// Parses `src` into a tree of `AstNode`s
+/// using the default `Parser` configuration.
+#[must_use]
+pub fn parse(src: &str) -> TreeArena<AstNode> {
+    Parser::new().parse(src)
+}
+
+/// Configurable entry point of the parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parser {
+    max_depth: usize,
+    source: SourceId,
+    known_macros: Option<Vec<Box<str>>>,
+    lossless: bool,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    /// Default limit on the amount of nested containers.
+    pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+    /// Creates a `Parser` with the default configuration.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+            source: SourceId::MAIN,
+            known_macros: None,
+            lossless: false,
+        }
+    }
+
+    /// Sets the maximum amount of nested containers
+    /// (blockquotes, lists, list items and macros).
+    ///
+    /// Container markers past this limit are kept
+    /// as text of the innermost block instead.
+    #[must_use]
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the source recorded in the positions of the parsed nodes,
+    /// `SourceId::MAIN` by default.
+    ///
+    /// Useful when the nodes are later grafted into
+    /// a document parsed from a different source.
+    #[must_use]
+    pub fn source(mut self, source: SourceId) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Sets the names of the macros which can be invoked,
+    /// other macro invocations are reported as `unknown-macro`.
+    ///
+    /// Macro names aren't checked by default.
+    #[must_use]
+    pub fn known_macros<I>(mut self, names: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Box<str>>,
+    {
+        self.known_macros = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Makes `Parser::parse_document` and `Parser::parse_into`
+    /// record the `Syntax` of the document, with every marker
+    /// and piece of whitespace of the source.
+    ///
+    /// Off by default.
+    #[must_use]
+    pub fn lossless(mut self, lossless: bool) -> Self {
+        self.lossless = lossless;
+        self
+    }
+
+    /// Parses `src` into a tree of `AstNode`s.
+    #[must_use]
+    pub fn parse(&self, src: &str) -> TreeArena<AstNode> {
+        let mut cx = CompileCx::new();
+        cx.max_depth = self.max_depth;
+        cx.source = self.source;
+
+        cx.run(Input::new(src))
+    }
+
+    /// Parses `src` into a new `Document`,
+    /// along with the `Diagnostic`s found in it.
+    #[must_use]
+    pub fn parse_document(&self, src: &str) -> Document {
+        let mut document = Document::new();
+        self.parse_into(&mut document, src);
+
+        document
+    }
+
+    /// Parses `src` into `document`, replacing its previous contents
+    /// and diagnostics.
+    ///
+    /// A copy of `src` is kept in the sources of the document
+    /// as `SourceId::MAIN`, regardless of `Parser::source`.
+    ///
+    /// The memory of the previous contents is reused,
+    /// so parsing many sources into the same `Document`
+    /// allocates a lot less than calling `Parser::parse` for each of them.
+    pub fn parse_into(&self, document: &mut Document, src: &str) {
+        document.sources_mut().clear();
+        let source = document.sources_mut().add("", src);
+
+        let mut tree = core::mem::take(document.tree_mut());
+        tree.clear();
+
+        let mut cx = CompileCx::with_tree(tree);
+        cx.max_depth = self.max_depth;
+        cx.source = source;
+        cx.diagnostics = core::mem::take(document.diagnostics_mut());
+        cx.diagnostics.clear();
+
+        let tokens = document.syntax_mut().take().map(Syntax::into_tokens);
+        if self.lossless {
+            cx.markers = Some(Vec::new());
+        }
+
+        let (tree, mut diagnostics, markers) = cx.compile(Input::new(src));
+
+        *document.syntax_mut() = markers
+            .map(|markers| Syntax::build(tokens.unwrap_or_default(), &markers, &tree, src, source));
+
+        diagnostic::check(
+            &tree,
+            document.sources(),
+            self.known_macros.as_deref(),
+            &mut diagnostics,
+        );
+
+        *document.tree_mut() = tree;
+        *document.diagnostics_mut() = diagnostics;
+    }
+}
+
 pub(crate) struct CompileCx {
     /// Tree structure for the AST
     tree: TreeArena<AstNode>,
@@ -25,18 +177,111 @@ pub(crate) struct CompileCx {
     /// Points to a `BulletList` or `OrderedList` node.
     list_origin: Option<NodeId>,
 
-    /// Is the list currently processed a tight one
-    is_list_tight: bool,
-
     /// Are we currently in a macro invocation
     inside_macro_invc: bool,
+
+    /// Maximum amount of nested containers.
+    max_depth: usize,
+
+    /// Source recorded in the positions of the nodes.
+    source: SourceId,
+
+    /// Problems found so far.
+    diagnostics: Vec<Diagnostic>,
+
+    /// Markers recorded for the `Syntax`, if it is wanted.
+    markers: Option<Vec<SyntaxToken>>,
 }
 
 impl CompileCx {
+    pub(crate) fn new() -> Self {
+        Self::with_tree(TreeArena::new())
+    }
+
+    // parses into an existing, empty `tree`
+    fn with_tree(tree: TreeArena<AstNode>) -> Self {
+        Self {
+            tree,
+            ordered_list_index: None,
+            bullet_list_marker: None,
+            ordered_list_char: None,
+            list_origin: None,
+            inside_macro_invc: false,
+            max_depth: Parser::DEFAULT_MAX_DEPTH,
+            source: SourceId::MAIN,
+            diagnostics: Vec::new(),
+            markers: None,
+        }
+    }
+
+    // records the marker between `start` and `end` of the node `id`
+    fn marker(&mut self, kind: TokenKind, start: usize, end: usize, id: NodeId) {
+        if let Some(markers) = &mut self.markers {
+            markers.push(SyntaxToken {
+                kind,
+                pos: Position::new(start, end).with_source(self.source),
+                node: Some(id),
+            });
+        }
+    }
+
+    // checks if `amount` more containers fit under the depth limit,
+    // reporting the first container which doesn't
+    fn can_nest(&mut self, amount: usize, input: &Input<'_>) -> bool {
+        if self.tree.right_edge().len() + amount <= self.max_depth {
+            return true;
+        }
+
+        if !self
+            .diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.code == Diagnostic::NESTING_TOO_DEEP)
+        {
+            let position = self.line_at(input, input.consumed);
+            self.diagnostics
+                .push(Diagnostic::nesting_too_deep(self.max_depth, position));
+        }
+
+        false
+    }
+
+    // position of the rest of the line starting at `start`
+    fn line_at(&self, input: &Input<'_>, start: usize) -> Position {
+        let line = input.bytes.get(start..).unwrap_or_default();
+        let len = line
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .unwrap_or(line.len());
+
+        Position::new(start, start + len).with_source(self.source)
+    }
+
     // "compiles" the input to an AST
-    fn run(mut self, mut input: Input<'_>) -> TreeArena<AstNode> {
+    fn run(self, input: Input<'_>) -> TreeArena<AstNode> {
+        self.compile(input).0
+    }
+
+    // "compiles" the input to an AST,
+    // along with the problems found on the way
+    // and the markers, if they were recorded
+    fn compile(
+        mut self,
+        mut input: Input<'_>,
+    ) -> (
+        TreeArena<AstNode>,
+        Vec<Diagnostic>,
+        Option<Vec<SyntaxToken>>,
+    ) {
         while !input.eof() {
-            self.parse(&mut input);
+            if self.parse(&mut input).is_err() {
+                let position = self.line_at(&input, input.consumed);
+                self.diagnostics.push(Diagnostic::too_many_nodes(position));
+                break;
+            }
+        }
+
+        if self.inside_macro_invc {
+            self.report_unclosed_macro(&input);
         }
 
         self.pop_containers(&input);
@@ -46,7 +291,111 @@ impl CompileCx {
             let _ix = self.tree.go_up();
         }
 
-        self.tree
+        self.close_positions(input.bytes.len());
+        self.mark_tight_lists(input.bytes);
+
+        if self.source != SourceId::MAIN {
+            for node in self.tree.data_mut() {
+                node.pos.source = self.source;
+            }
+        }
+
+        (self.tree, self.diagnostics, self.markers)
+    }
+
+    // a list is tight when no blank line separates its items,
+    // or the blocks inside of them
+    fn mark_tight_lists(&mut self, bytes: &[u8]) {
+        let lists = self
+            .tree
+            .nodes()
+            .filter(|&id| {
+                self.tree.get(id).is_some_and(|node| {
+                    matches!(
+                        node.data.value,
+                        Value::BulletList { .. } | Value::OrderedList { .. }
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for id in lists {
+            let mut items = self
+                .tree
+                .children(id)
+                .filter_map(|child| self.tree.get(child))
+                .filter(|child| matches!(child.data.value, Value::ListItem))
+                .map(|child| child.data.pos);
+
+            let Some(first) = items.next() else { continue };
+            let last = items.last().unwrap_or(first);
+
+            let quotes = self
+                .tree
+                .ancestors(id)
+                .filter_map(|ancestor| self.tree.get(ancestor))
+                .filter(|ancestor| matches!(ancestor.data.value, Value::Blockquote))
+                .count();
+
+            let items = bytes.get(first.start..last.end).unwrap_or_default();
+            let items = items.trim_ascii_end();
+            let loose = items
+                .split(|&byte| byte == b'\n')
+                .any(|line| is_blank_line(line, quotes));
+
+            if let Some(node) = self.tree.get_mut(id)
+                && let Value::BulletList { tight } | Value::OrderedList { tight, .. } =
+                    &mut node.data.value
+            {
+                *tight = !loose;
+            }
+        }
+    }
+
+    // containers left without an end, e.g. because the document ended,
+    // end with their last descendant
+    fn close_positions(&mut self, len: usize) {
+        let nodes = self.tree.nodes().collect::<Vec<_>>();
+
+        for &id in nodes.iter().rev() {
+            let Some(pos) = self.tree.get(id).map(|node| node.data.pos) else {
+                continue;
+            };
+
+            if pos.start <= pos.end && pos.end <= len {
+                continue;
+            }
+
+            let end = self
+                .tree
+                .children(id)
+                .filter_map(|child| self.tree.get(child))
+                .map(|child| child.data.pos.end)
+                .fold(pos.start, usize::max)
+                .min(len);
+
+            if let Some(node) = self.tree.get_mut(id) {
+                node.data.pos.start = pos.start.min(end);
+                node.data.pos.end = end;
+            }
+        }
+    }
+
+    fn report_unclosed_macro(&mut self, input: &Input<'_>) {
+        let open = self.tree.right_edge().iter().rev().find_map(|&id| {
+            let node = &self.tree.get(id)?.data;
+
+            match &node.value {
+                Value::Macro { name } => Some((name.clone(), node.pos.start)),
+                _ => None,
+            }
+        });
+
+        if let Some((name, start)) = open {
+            let position = self.line_at(input, start);
+            self.diagnostics
+                .push(Diagnostic::unclosed_macro(&name, position));
+        }
     }
 
     // pops any containers on the road to the current node
@@ -92,109 +441,138 @@ impl CompileCx {
     }
 
     // parses one block at a time
-    fn parse(&mut self, input: &mut Input<'_>) {
+    fn parse(&mut self, input: &mut Input<'_>) -> Result<(), CapacityError> {
         self.pop_containers(input);
 
-        if self.inside_macro_invc && input.scan_macro_end() {
-            input.consumed += 1; // skip the ')'
-            self.tree.go_up();
-            self.inside_macro_invc = false;
+        if self.inside_macro_invc
+            && let Some(end) = input.scan_macro_end()
+        {
+            input.consumed += end; // skip the ')'
+            self.close_macro(input.consumed);
         }
 
         loop {
             // Blockquotes.
-            if let Some(blockquote_ix) = input.scan_blockquote() {
+            if let Some(blockquote_ix) = input.scan_blockquote()
+                && self.can_nest(1, input)
+            {
                 let node = AstNode::new(Value::Blockquote, Position::new(input.consumed, 0), 0);
-                input.consumed += blockquote_ix;
+                let id = self.tree.attach_node(node)?;
+                self.marker(
+                    TokenKind::BlockquoteMarker,
+                    input.consumed,
+                    input.consumed + blockquote_ix,
+                    id,
+                );
 
-                self.tree.attach_node(node);
+                input.consumed += blockquote_ix;
                 self.tree.go_down();
 
             // Bullet lists
-            } else if let Some((list_start, list_char, tight)) = input.scan_bullet_list() {
-                if !self.is_list_tight {
-                    self.is_list_tight = tight;
+            } else if let Some((list_start, list_char, blank)) = input.scan_bullet_list()
+                && self.can_nest(1 + usize::from(self.list_origin.is_none()), input)
+            {
+                if self.bullet_list_marker != Some(list_char) {
+                    self.close_buried_list(input.consumed);
                 }
 
                 if self.list_origin.is_none() {
-                    self.start_bullet_list(input, list_char);
+                    self.start_bullet_list(input, list_char)?;
                 } else if self.bullet_list_marker != Some(list_char) {
                     self.end_list(input.consumed);
-                    return;
+                    return Ok(());
                 }
 
-                self.insert_list_item(input.consumed + (usize::from(tight) << 1));
+                let id = self.insert_list_item(input.consumed + (usize::from(blank) << 1))?;
+                self.marker(
+                    TokenKind::BulletMarker(list_char),
+                    input.consumed,
+                    input.consumed + list_start,
+                    id,
+                );
                 input.consumed += list_start;
 
                 if let Some(empty_line_ix) = input.scan_empty_line() {
                     input.consumed += empty_line_ix;
-                    return;
+                    return Ok(());
                 }
 
             // Ordered lists
-            } else if let Some((list_start, list_char, start_index, tight)) =
+            } else if let Some((list_start, list_char, start_index, blank)) =
                 input.scan_ordered_list()
+                && self.can_nest(1 + usize::from(self.list_origin.is_none()), input)
             {
-                if !self.is_list_tight {
-                    self.is_list_tight = tight;
+                if self.ordered_list_char != Some(list_char) {
+                    self.close_buried_list(input.consumed);
                 }
 
                 if self.list_origin.is_none() {
-                    self.start_ordered_list(input, start_index, list_char);
+                    self.start_ordered_list(input, start_index, list_char)?;
                 } else if self.ordered_list_char != Some(list_char) {
                     self.end_list(input.consumed);
-                    return;
+                    return Ok(());
                 }
 
-                let offset = if tight { 2 } else { 0 };
-                self.insert_list_item(input.consumed + offset);
+                let offset = if blank { 2 } else { 0 };
+                let id = self.insert_list_item(input.consumed + offset)?;
+                self.marker(
+                    TokenKind::OrderedMarker(list_char),
+                    input.consumed,
+                    input.consumed + list_start + offset,
+                    id,
+                );
                 input.consumed += list_start + offset;
 
                 if let Some(empty_line_ix) = input.scan_empty_line() {
                     input.consumed += empty_line_ix;
-                    return;
+                    return Ok(());
                 }
 
             // Macros
             } else if let Some((span, end)) = input.scan_macro()
                 && !self.inside_macro_invc
+                && self.can_nest(1, input)
             {
                 // for now i forbid nested macros
                 // might be funny later
-                self.parse_macro(span, end, input);
+                self.parse_macro(span, end, input)?;
             } else {
                 break;
             }
         }
 
-        if let Some(heading_end) = input.scan_atx_heading() {
+        if let Some((span, end)) = input.scan_code_fence() {
+            return self.parse_code_block(input, span, end);
+        }
+
+        if let Some((level, heading_end)) = input.scan_atx_heading() {
             self.end_list(input.consumed);
-            self.parse_atx_heading(input, heading_end);
+            self.parse_atx_heading(input, level, heading_end)?;
             self.tree.go_up();
 
-            return;
+            return Ok(());
         }
 
         if let Some(ix) = input.scan_style_break() {
-            self.parse_style_break(input, ix);
+            self.parse_style_break(input, ix)?;
         }
 
-        self.parse_paragraph(input);
+        self.parse_paragraph(input)
     }
 
-    fn parse_paragraph(&mut self, input: &mut Input<'_>) {
+    fn parse_paragraph(&mut self, input: &mut Input<'_>) -> Result<(), CapacityError> {
         let old = input.consumed;
 
         if input.eof() {
-            return;
+            return Ok(());
         }
 
         while !input.eof() {
             input.consumed += 1;
 
             if let Some((level, ix)) = input.scan_setext_heading() {
-                self.parse_setext_heading(level, input, old, ix);
-                return;
+                self.parse_setext_heading(level, input, old, ix)?;
+                return Ok(());
             }
 
             if input.scan_interrupt_paragraph() {
@@ -203,63 +581,232 @@ impl CompileCx {
         }
 
         // prevents a '\n\n' paragraph.
-        //
-        // Safety:
-        //
-        // The loop above guarantees that the range
-        // `old..input.consumed` is in bounds of the data slice.
-        unsafe {
-            if input.bytes.get_unchecked(old..input.consumed) == b"\n\n" {
-                return;
-            }
+        if input.bytes.get(old..input.consumed) == Some(b"\n\n") {
+            return Ok(());
         }
 
         let pos = Position::new(old, input.consumed);
         let node = AstNode::new(crate::ast::Value::Paragraph, pos, 0);
 
-        self.tree.attach_node(node);
+        self.tree.attach_node(node)?;
         self.tree.go_down();
-        self.tree.attach_node(AstNode::new(Value::Text, pos, 0));
+        self.tree.attach_node(AstNode::new(Value::Text, pos, 0))?;
         self.tree.go_up();
+
+        Ok(())
     }
 
-    fn parse_macro(&mut self, span: MacroSpan, end: usize, input: &mut Input<'_>) {
-        use core::str::from_utf8_unchecked;
+    fn parse_code_block(
+        &mut self,
+        input: &mut Input<'_>,
+        span: FenceSpan,
+        end: usize,
+    ) -> Result<(), CapacityError> {
+        let start = input.consumed;
+        let (info_start, info_end) = span.info;
+
+        // the scanner only hands out info strings on character boundaries
+        let info = input
+            .leftover()
+            .get(info_start..info_end)
+            .and_then(|bytes| core::str::from_utf8(bytes).ok())
+            .unwrap_or_default();
+
+        // the first word of the info string names the language,
+        // the rest is kept as it is
+        let value = match info.split_once(char::is_whitespace) {
+            Some((lang, rest)) => Value::Code {
+                lang: None,
+                meta: Some(CodeMeta::new(
+                    Lang::recognize(lang),
+                    rest.trim().to_string(),
+                )),
+            },
+            None if info.is_empty() => Value::Code {
+                lang: None,
+                meta: None,
+            },
+            None => Value::Code {
+                lang: Some(Lang::recognize(info)),
+                meta: None,
+            },
+        };
+
+        let node = AstNode::new(value, Position::new(start, start), 0);
+        let id = self.tree.attach_node(node)?;
+        let fence_char = char::from(span.fence_char);
+
+        self.marker(
+            TokenKind::FenceOpen(fence_char),
+            start + span.fence.0,
+            start + span.fence.1,
+            id,
+        );
+        self.marker(
+            TokenKind::FenceInfo,
+            start + info_start,
+            start + info_end,
+            id,
+        );
+
+        input.consumed += end;
+        let content_start = input.consumed;
+        let fence_len = span.fence.1 - span.fence.0;
+
+        // a code block without a closing fence goes on to the end
+        let mut close = None;
+        while !input.eof() {
+            if let Some(fence) = input.scan_closing_fence(span.fence_char, fence_len) {
+                close = Some(fence);
+                break;
+            }
+
+            let line = input.leftover();
+            input.consumed += line
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(line.len(), |ix| ix + 1);
+        }
 
+        let content_end = input.consumed;
+        if content_end > content_start {
+            self.tree.go_down();
+            let pos = Position::new(content_start, content_end);
+            self.tree.attach_node(AstNode::new(Value::Text, pos, 0))?;
+            self.tree.go_up();
+        }
+
+        let mut code_end = content_end;
+        if let Some((len, line_end)) = close {
+            self.marker(
+                TokenKind::FenceClose(fence_char),
+                content_end,
+                content_end + len,
+                id,
+            );
+
+            code_end = content_end + len;
+            input.consumed += line_end;
+        }
+
+        if let Some(node) = self.tree.get_mut(id) {
+            node.data.pos.end = code_end;
+        }
+
+        Ok(())
+    }
+
+    fn parse_macro(
+        &mut self,
+        span: MacroSpan,
+        end: usize,
+        input: &mut Input<'_>,
+    ) -> Result<(), CapacityError> {
         let (name_start, name_end) = span.name;
-        let bytes = &input
+
+        // the scanner only accepts names which are valid UTF-8
+        let name = input
             .leftover()
-            .get(name_start..name_end - 1)
-            .expect("must be present due to earlier scan");
+            .get(name_start..name_end)
+            .and_then(|bytes| core::str::from_utf8(bytes).ok())
+            .unwrap_or_default();
 
         let node = AstNode::new(
-            Value::Macro {
-                name: unsafe { String::from(from_utf8_unchecked(bytes)).into_boxed_str() },
-            },
+            Value::Macro { name: name.into() },
             Position::new(input.consumed, input.consumed),
             0,
         );
 
+        let id = self.tree.attach_node(node)?;
+        let start = input.consumed;
+        let (args_start, args_end) = span.args;
+
+        self.marker(
+            TokenKind::MacroOperator,
+            start + span.operator.0,
+            start + span.operator.1,
+            id,
+        );
+        self.marker(
+            TokenKind::MacroName,
+            start + name_start,
+            start + name_end,
+            id,
+        );
+        self.marker(
+            TokenKind::MacroParen,
+            start + args_start - 1,
+            start + args_start,
+            id,
+        );
+        self.marker(
+            TokenKind::MacroArguments,
+            start + args_start,
+            start + args_end,
+            id,
+        );
+        self.marker(
+            TokenKind::MacroParen,
+            start + args_end,
+            start + args_end + 1,
+            id,
+        );
+        self.marker(TokenKind::MacroParen, start + end - 1, start + end, id);
+
         input.consumed += end;
-        self.tree.attach_node(node);
         self.tree.go_down();
+        self.inside_macro_invc = true;
+
+        Ok(())
+    }
+
+    // leaves the macro invocation and anything still open inside of it
+    fn close_macro(&mut self, end: usize) {
+        while let Some(id) = self.tree.right_edge().last().copied() {
+            self.end_list(end);
+            self.tree.go_up();
+
+            if let Some(node) = self.tree.get_mut(id)
+                && matches!(
+                    node.data.value,
+                    Value::Blockquote | Value::ListItem | Value::Macro { .. }
+                )
+            {
+                node.data.pos.end = end;
+
+                if let Value::Macro { .. } = node.data.value {
+                    self.marker(TokenKind::MacroClose, end - 1, end, id);
+                    break;
+                }
+            }
+        }
+
+        self.inside_macro_invc = false;
     }
 
-    fn parse_atx_heading(&mut self, input: &mut Input<'_>, heading_end: usize) {
+    fn parse_atx_heading(
+        &mut self,
+        input: &mut Input<'_>,
+        level: NonZero<u8>,
+        heading_end: usize,
+    ) -> Result<(), CapacityError> {
         let node = AstNode::new(
-            Value::Heading {
-                #[allow(clippy::cast_possible_truncation)]
-                level: NonZero::new(heading_end as u8 - 1).expect("value was 0"),
-            },
+            Value::Heading { level },
             Position::new(input.consumed, input.consumed + heading_end),
             0,
         );
 
-        self.tree.attach_node(node);
+        let id = self.tree.attach_node(node)?;
+        self.marker(
+            TokenKind::AtxMarker,
+            input.consumed,
+            input.consumed + heading_end,
+            id,
+        );
         self.tree.go_down();
 
         input.consumed += heading_end;
-        let mut ix = heading_end;
+        let mut ix = 0;
         let bytes = input.leftover();
 
         while bytes.get(ix).copied().is_some_and(|byte| byte != b'\n') {
@@ -277,10 +824,13 @@ impl CompileCx {
 
         let node = AstNode::new(Value::Text, Position::new(input.consumed, end), 0);
 
-        input.consumed += ix + 1; // +1 to skip potential newline
+        // +1 to skip potential newline
+        input.consumed = (input.consumed + ix + 1).min(input.bytes.len());
 
+        self.tree.attach_node(node)?;
         self.tree.go_up();
-        self.tree.attach_node(node);
+
+        Ok(())
     }
 
     fn parse_setext_heading(
@@ -289,40 +839,58 @@ impl CompileCx {
         input: &mut Input<'_>,
         old_pos: usize,
         ix: usize,
-    ) {
+    ) -> Result<(), CapacityError> {
         let mut pos = Position::new(old_pos, input.consumed);
         let text = AstNode::new(Value::Text, pos, 0);
 
         pos.end += ix;
-        input.consumed += ix;
 
         let node = AstNode::new(crate::ast::Value::Heading { level }, pos, 0);
+        let id = self.tree.attach_node(node)?;
+
+        let underline = if level.get() == 1 { '=' } else { '-' };
+        self.marker(
+            TokenKind::SetextUnderline(underline),
+            input.consumed,
+            input.consumed + ix,
+            id,
+        );
 
-        self.tree.attach_node(node);
+        input.consumed += ix;
         self.tree.go_down();
-        self.tree.attach_node(text);
+        self.tree.attach_node(text)?;
         self.tree.go_up();
+
+        Ok(())
     }
 
-    fn parse_style_break(&mut self, input: &mut Input<'_>, ix: usize) {
+    fn parse_style_break(&mut self, input: &mut Input<'_>, ix: usize) -> Result<(), CapacityError> {
         let old = input.consumed;
         input.consumed += ix;
 
         let node = AstNode::new(Value::StyleBreak, Position::new(old, input.consumed), 0);
-        self.tree.attach_node(node);
+        self.tree.attach_node(node)?;
+
+        Ok(())
     }
 
-    fn insert_list_item(&mut self, start: usize) {
-        self.tree.attach_node(AstNode::new(
+    fn insert_list_item(&mut self, start: usize) -> Result<NodeId, CapacityError> {
+        let id = self.tree.attach_node(AstNode::new(
             Value::ListItem,
             Position::new(start, start),
             0,
-        ));
+        ))?;
 
         self.tree.go_down();
+        Ok(id)
     }
 
-    fn start_ordered_list(&mut self, input: &mut Input<'_>, start_index: u64, list_char: char) {
+    fn start_ordered_list(
+        &mut self,
+        input: &mut Input<'_>,
+        start_index: u64,
+        list_char: char,
+    ) -> Result<(), CapacityError> {
         let node = AstNode::new(
             Value::OrderedList {
                 tight: false,
@@ -333,11 +901,17 @@ impl CompileCx {
         );
 
         self.ordered_list_char.replace(list_char);
-        self.list_origin.replace(self.tree.attach_node(node));
+        self.list_origin.replace(self.tree.attach_node(node)?);
         self.tree.go_down();
+
+        Ok(())
     }
 
-    fn start_bullet_list(&mut self, input: &mut Input<'_>, list_char: char) {
+    fn start_bullet_list(
+        &mut self,
+        input: &mut Input<'_>,
+        list_char: char,
+    ) -> Result<(), CapacityError> {
         let node = AstNode::new(
             Value::BulletList { tight: false },
             Position::new(input.consumed, 0),
@@ -345,41 +919,65 @@ impl CompileCx {
         );
 
         self.bullet_list_marker = Some(list_char);
-        self.list_origin.replace(self.tree.attach_node(node));
+        self.list_origin.replace(self.tree.attach_node(node)?);
         self.tree.go_down();
+
+        Ok(())
+    }
+
+    // ends the current list if it isn't the innermost container,
+    // as `end_list` can't reach it and the parser would make no progress
+    fn close_buried_list(&mut self, end: usize) {
+        let Some(id) = self.list_origin else { return };
+
+        if self.tree.right_edge().last() == Some(&id) {
+            return;
+        }
+
+        if let Some(node) = self.tree.get_mut(id) {
+            node.data.pos.end = end;
+        }
+
+        self.list_origin = None;
     }
 
     fn end_list(&mut self, end: usize) {
         let id = self.tree.right_edge().last().copied();
 
         if let Some(parent_id) = id
-            && let Some(parent) = self.tree.get_mut(parent_id)
+            && self.tree.get(parent_id).is_some_and(|parent| {
+                matches!(
+                    parent.data.value,
+                    Value::BulletList { .. } | Value::OrderedList { .. }
+                )
+            })
+            && let Some(id) = self.list_origin.take()
+            && let Some(node) = self.tree.get_mut(id)
         {
-            match parent.data.value {
-                Value::BulletList { ref mut tight } | Value::OrderedList { ref mut tight, .. } => {
-                    *tight = self.is_list_tight;
-
-                    if let Some(id) = self.list_origin.take()
-                        && let Some(node) = self.tree.get_mut(id)
-                    {
-                        node.data.pos.end = end;
-                    }
-
-                    self.is_list_tight = false;
-                }
-
-                _ => (),
-            }
+            node.data.pos.end = end;
         }
     }
 }
 
+// a line with nothing but whitespace,
+// after up to `quotes` blockquote markers
+fn is_blank_line(mut line: &[u8], quotes: usize) -> bool {
+    for _ in 0..quotes {
+        line = line.trim_ascii_start();
+        line = line.strip_prefix(b">").unwrap_or(line);
+    }
+
+    line.iter().all(u8::is_ascii_whitespace)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::CompileCx;
-    use crate::ast::{AstNode, Value};
+    use super::{CompileCx, Parser};
+    use crate::ast::{AstNode, Lang, Value};
+    use crate::events::Event;
+    use crate::lib::{String, Vec};
     use crate::scan::Input;
-    use crate::tree::TreeArena;
+    use crate::tree::{Control, NodeId, TreeVisitor};
 
     macro_rules! test_ast {
         ($src:expr, Limit: $lim:expr, Strict: $strict:expr, $($rules: tt)+) => {{
@@ -445,15 +1043,7 @@ mod tests {
 
             let input = Input::new($src);
 
-            let c = CompileCx {
-                tree: TreeArena::new(),
-                bullet_list_marker: None,
-                list_origin: None,
-                ordered_list_char: None,
-                ordered_list_index: None,
-                is_list_tight: false,
-                inside_macro_invc: false,
-            };
+            let c = CompileCx::new();
 
             let mut visitor = __TestVisitor {
                 src: $src,
@@ -488,15 +1078,7 @@ mod tests {
 
             let input = Input::new($text);
 
-            let c = CompileCx {
-                tree: TreeArena::new(),
-                bullet_list_marker: None,
-                list_origin: None,
-                ordered_list_char: None,
-                ordered_list_index: None,
-                is_list_tight: false,
-                inside_macro_invc: false,
-            };
+            let c = CompileCx::new();
 
             let mut visitor = __Visitor($text, 0);
 
@@ -546,7 +1128,7 @@ mod tests {
 
         test_ast!(
             TEST, Limit: 13, Strict: true,
-            (Value::OrderedList { tight: false, start_index: 1 }, TEST),
+            (Value::OrderedList { tight: true, start_index: 1 }, TEST),
 
             (Value::ListItem, "1. This is a ordered list >:3\n"),
             (Value::Paragraph, "This is a ordered list >:3\n"),
@@ -567,7 +1149,7 @@ mod tests {
     }
 
     #[test]
-    fn ordered_list_tight() {
+    fn ordered_list_loose() {
         const TEST: &str = "1. This is a ordered list >:3\n\n\
             2. This is again a ordered list\n\
             3. Now the fuss is over...!\n\
@@ -577,7 +1159,7 @@ mod tests {
 
         test_ast!(
             TEST, Limit: 13, Strict: true,
-            (Value::OrderedList { tight: true, start_index: 1 }, TEST),
+            (Value::OrderedList { tight: false, start_index: 1 }, TEST),
 
             (Value::ListItem, "1. This is a ordered list >:3"),
             (Value::Paragraph, "This is a ordered list >:3"),
@@ -604,7 +1186,7 @@ mod tests {
             - Salt water.\n";
 
         test_ast!(TEST, Limit: 10, Strict: true,
-            (Value::BulletList { tight: false }, TEST),
+            (Value::BulletList { tight: true }, TEST),
 
             (Value::ListItem, "- This is a bullet list!\n"),
             (Value::Paragraph, "This is a bullet list!\n"),
@@ -621,13 +1203,13 @@ mod tests {
     }
 
     #[test]
-    fn bullet_list_tight() {
+    fn bullet_list_loose() {
         const TEST: &str = "- This is a bullet list!\n\n\
             - Once again a cruel moment\n\
             - Salt water.\n";
 
         test_ast!(TEST, Limit: 10, Strict: true,
-            (Value::BulletList { tight: true }, TEST),
+            (Value::BulletList { tight: false }, TEST),
 
             (Value::ListItem, "- This is a bullet list!"),
             (Value::Paragraph, "This is a bullet list!"),
@@ -643,6 +1225,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn list_tightness() {
+        fn tight(src: &str) -> Vec<bool> {
+            let tree = super::parse(src);
+
+            tree.nodes()
+                .filter_map(|id| match tree.get(id)?.data.value {
+                    Value::BulletList { tight } | Value::OrderedList { tight, .. } => Some(tight),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        // blank lines before the list don't matter
+        assert_eq!(tight("Salt.\n\n- one\n- two\n"), [true]);
+        assert_eq!(tight("- one\n- two\n\n\n"), [true]);
+
+        assert_eq!(tight("- one\n  more\n\n  text\n- two\n"), [false]);
+        assert_eq!(tight("> - one\n> - two\n"), [true]);
+        assert_eq!(tight("> - one\n>\n> - two\n"), [false]);
+    }
+
     #[test]
     fn blockquote() {
         test_ast!("> > > Blockquote", Limit: 5, Strict: true,
@@ -668,6 +1272,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn code_block() {
+        test_ast!("```rust\nfn x() {}\n```\nAfter", Limit: 4, Strict: true,
+            (Value::Code { lang: Some(Lang::Rust), meta: None }, "```rust\nfn x() {}\n```"),
+            (Value::Text, "fn x() {}\n"),
+            (Value::Paragraph, "After"),
+            (Value::Text, "After")
+        );
+    }
+
     #[test]
     fn setext_heading() {
         test_ast!(
@@ -679,4 +1293,141 @@ mod tests {
             (Value::Text, "This is a setext heading!\n")
         );
     }
+
+    #[test]
+    fn max_depth() {
+        struct Depth(usize);
+
+        impl TreeVisitor for Depth {
+            fn enter(&mut self, _: NodeId, _: &AstNode, depth: usize) -> Control {
+                self.0 = self.0.max(depth);
+
+                Control::Continue
+            }
+        }
+
+        let src = "> > > > > > Blockquote";
+        let tree = Parser::new().max_depth(3).parse(src);
+
+        let mut depth = Depth(0);
+        tree.walk(&mut depth);
+
+        // three blockquotes, then a paragraph and its text
+        assert_eq!(depth.0, 4);
+
+        let text = tree.events(src).find_map(|event| match event {
+            Event::Text(text) => Some(text),
+            _ => None,
+        });
+
+        assert_eq!(text, Some("> > > Blockquote"));
+    }
+
+    // parses `src` and reads the result back, which must not panic
+    fn parse_and_read(src: &str) {
+        let document = Parser::new().lossless(true).parse_document(src);
+
+        for id in document.tree().nodes() {
+            let node = &document.tree().get(id).unwrap().data;
+
+            assert!(
+                node.pos.start <= node.pos.end && node.pos.end <= src.len(),
+                "{:?} out of bounds in {src:?}",
+                node.pos
+            );
+
+            let _ = node.as_str(&document);
+        }
+
+        let mut html = String::new();
+        crate::render::html::push_html(&mut html, document.events());
+
+        for diagnostic in document.diagnostics() {
+            let _ = diagnostic.pretty(&document);
+        }
+
+        let syntax = document.syntax().unwrap();
+        assert_eq!(syntax.to_source(&document), src);
+    }
+
+    #[test]
+    fn crashers() {
+        const CRASHERS: &[&str] = &[
+            include_str!("../fuzz/crashers/atx-heading-at-eof.md"),
+            include_str!("../fuzz/crashers/atx-heading-long-whitespace.md"),
+            include_str!("../fuzz/crashers/heading-in-nested-blockquote.md"),
+            include_str!("../fuzz/crashers/list-marker-in-blockquote-hangs.md"),
+            include_str!("../fuzz/crashers/macro-without-arguments.md"),
+            include_str!("../fuzz/crashers/ordered-list-overflow.md"),
+            include_str!("../fuzz/crashers/ordered-list-without-digits.md"),
+        ];
+
+        for src in CRASHERS {
+            parse_and_read(src);
+        }
+    }
+
+    // a smoke test over a fixed set of pseudo-random documents,
+    // arbitrary input is left to the fuzz target
+    #[test]
+    fn random_documents_smoke() {
+        // pieces of Markdown syntax, along with text and multi-byte characters
+        const PIECES: &[&str] = &[
+            "#",
+            "# ",
+            "###### ",
+            "\n",
+            "\n\n",
+            " ",
+            "\t",
+            "\r",
+            ">",
+            "> ",
+            "- ",
+            "* ",
+            "+ ",
+            "1. ",
+            "12) ",
+            "99999999999999999999. ",
+            "<>= ",
+            "note ",
+            "(",
+            ")",
+            "===",
+            "---",
+            "***",
+            "```",
+            "~~~",
+            "`",
+            "_",
+            "\\",
+            "[a][b]",
+            "[a]: /b",
+            "Salt",
+            "\u{e9}",
+            "\u{65e5}\u{672c}",
+        ];
+
+        // xorshift with a fixed seed, so failures can be reproduced
+        let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+
+            usize::try_from(state % 1024).unwrap()
+        };
+
+        let mut src = String::new();
+
+        for _ in 0..5_000 {
+            src.clear();
+
+            for _ in 0..next() % 24 {
+                src.push_str(PIECES[next() % PIECES.len()]);
+            }
+
+            parse_and_read(&src);
+        }
+    }
 }
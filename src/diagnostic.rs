@@ -0,0 +1,544 @@
+//! Problems found while parsing a document.
+//!
+//! The parser never rejects its input, odd constructs are
+//! absorbed into the tree instead. `Diagnostic`s record where
+//! that happened, so tools can point the writer at them.
+
+use crate::ast::{AstNode, Position, TextSource, Value};
+use crate::lib::{Box, String, ToString, Vec};
+use crate::source::Sources;
+use crate::tree::TreeArena;
+use core::fmt::{self, Write};
+
+/// How serious a `Diagnostic` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The document likely doesn't render as intended.
+    Error,
+
+    /// The document renders, but something looks off.
+    Warning,
+}
+
+impl Severity {
+    /// Returns the name of the severity, as shown by the pretty printer.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+/// A problem found in a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+
+    /// Stable, kebab-case identifier of the kind of problem,
+    /// e.g. `unclosed-macro`.
+    pub code: &'static str,
+
+    pub message: String,
+
+    /// Where the problem is.
+    pub position: Position,
+
+    /// Additional explanations.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    /// A macro invocation is never closed with a `)`.
+    pub const UNCLOSED_MACRO: &'static str = "unclosed-macro";
+
+    /// A macro invocation names a macro the parser doesn't know.
+    pub const UNKNOWN_MACRO: &'static str = "unknown-macro";
+
+    /// A reference link uses a label which is never defined.
+    pub const UNRESOLVED_REFERENCE: &'static str = "unresolved-reference";
+
+    /// Two headings end up with the same ID.
+    pub const DUPLICATE_HEADING_ID: &'static str = "duplicate-heading-id";
+
+    /// Containers are nested deeper than `Parser::max_depth`.
+    pub const NESTING_TOO_DEEP: &'static str = "nesting-too-deep";
+
+    /// The document has more nodes than a `TreeArena` can index.
+    pub const TOO_MANY_NODES: &'static str = "too-many-nodes";
+
+    /// Creates a `Diagnostic` without notes.
+    pub fn new(
+        severity: Severity,
+        code: &'static str,
+        message: impl Into<String>,
+        position: Position,
+    ) -> Self {
+        Self {
+            severity,
+            code,
+            message: message.into(),
+            position,
+            notes: Vec::new(),
+        }
+    }
+
+    /// Adds a note to the diagnostic.
+    #[must_use]
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Renders the diagnostic in the style of rustc,
+    /// with the source line it points at and carets under its range.
+    ///
+    /// ```text
+    /// error[unclosed-macro]: macro `note` is never closed
+    ///  --> main.md:1:1
+    ///   |
+    /// 1 | <>= note (x) (
+    ///   | ^^^^^^^^^^^^^^
+    ///   |
+    ///   = note: close it with a `)` on a line of its own
+    /// ```
+    pub fn pretty<S: Sources + ?Sized>(&self, sources: &S) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, sources)
+            .expect("writing to a `String` is infallible");
+
+        out
+    }
+
+    /// Renders the diagnostic like `Diagnostic::pretty` into `out`.
+    ///
+    /// # Errors
+    /// if writing to `out` fails.
+    pub fn write_pretty<W, S>(&self, mut out: W, sources: &S) -> fmt::Result
+    where
+        W: Write,
+        S: Sources + ?Sized,
+    {
+        writeln!(
+            out,
+            "{}[{}]: {}",
+            self.severity.name(),
+            self.code,
+            self.message
+        )?;
+
+        let name = sources.name(self.position.source).unwrap_or("<input>");
+        let snippet = sources
+            .text(self.position.source)
+            .and_then(|text| Snippet::new(text, self.position));
+
+        let Some(snippet) = snippet else {
+            writeln!(out, " --> {name}")?;
+            return self.write_notes(&mut out, 1);
+        };
+
+        let line_number = snippet.line.to_string();
+        let gutter = line_number.len();
+
+        writeln!(
+            out,
+            "{:gutter$}--> {name}:{}:{}",
+            "", snippet.line, snippet.column
+        )?;
+        writeln!(out, "{:gutter$} |", "")?;
+        writeln!(out, "{line_number} | {}", snippet.text)?;
+        writeln!(
+            out,
+            "{:gutter$} | {:offset$}{}",
+            "",
+            "",
+            "^".repeat(snippet.carets),
+            offset = snippet.offset,
+        )?;
+
+        self.write_notes(&mut out, gutter)
+    }
+
+    fn write_notes<W: Write>(&self, out: &mut W, gutter: usize) -> fmt::Result {
+        if self.notes.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(out, "{:gutter$} |", "")?;
+
+        for note in &self.notes {
+            writeln!(out, "{:gutter$} = note: {note}", "")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The source line a `Diagnostic` points at.
+struct Snippet<'a> {
+    /// Line number, starting at 1.
+    line: usize,
+
+    /// Column number in characters, starting at 1.
+    column: usize,
+
+    /// The line itself, without the line break.
+    text: &'a str,
+
+    /// Width of the line before the range.
+    offset: usize,
+
+    /// Amount of carets under the range, at least one.
+    carets: usize,
+}
+
+impl<'a> Snippet<'a> {
+    fn new(text: &'a str, position: Position) -> Option<Self> {
+        let start = position.start.min(text.len());
+        let before = text.get(..start)?;
+
+        let line_start = before.rfind('\n').map_or(0, |ix| ix + 1);
+        let line_end = text[start..].find('\n').map_or(text.len(), |ix| start + ix);
+        let line = &text[line_start..line_end];
+
+        let offset = before[line_start..].chars().count();
+        let end = position.end.clamp(start, line_end);
+        let carets = text
+            .get(start..end)
+            .map_or(0, |range| range.chars().count());
+
+        Some(Self {
+            line: before.matches('\n').count() + 1,
+            column: offset + 1,
+            text: line,
+            offset,
+            carets: carets.max(1),
+        })
+    }
+}
+
+/// Checks the parsed `tree` for problems which need
+/// the whole document, appending them to `diagnostics`.
+pub(crate) fn check<S: Sources + ?Sized>(
+    tree: &TreeArena<AstNode>,
+    sources: &S,
+    known_macros: Option<&[Box<str>]>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    // heading IDs seen so far, along with the heading they belong to
+    let mut heading_ids: Vec<(String, Position)> = Vec::new();
+    let mut references = References::default();
+
+    for id in tree.nodes() {
+        let Some(node) = tree.get(id) else { continue };
+
+        match &node.data.value {
+            Value::Macro { name } if known_macros.is_some_and(|known| !known.contains(name)) => {
+                diagnostics.push(
+                    Diagnostic::new(
+                        Severity::Warning,
+                        Diagnostic::UNKNOWN_MACRO,
+                        format!("unknown macro `{name}`"),
+                        node.data.pos,
+                    )
+                    .with_note("its contents are kept as they are"),
+                );
+            }
+
+            Value::Heading { .. } => {
+                let text = tree
+                    .children(id)
+                    .filter_map(|child| tree.get(child))
+                    .map(|child| child.data.as_str(sources))
+                    .collect::<String>();
+                let heading_id = slug(&text);
+
+                if let Some((_, first)) = heading_ids.iter().find(|(seen, _)| *seen == heading_id) {
+                    let mut diagnostic = Diagnostic::new(
+                        Severity::Warning,
+                        Diagnostic::DUPLICATE_HEADING_ID,
+                        format!("duplicate heading ID `{heading_id}`"),
+                        node.data.pos,
+                    );
+
+                    if let Some(line) = sources
+                        .text(first.source)
+                        .and_then(|text| line_of(text, first.start))
+                    {
+                        diagnostic
+                            .notes
+                            .push(format!("first used by the heading on line {line}"));
+                    }
+
+                    diagnostics.push(diagnostic);
+                } else {
+                    heading_ids.push((heading_id, node.data.pos));
+                }
+            }
+
+            Value::Text => {
+                let in_code = tree.ancestors(id).any(|id| {
+                    tree.get(id)
+                        .is_some_and(|node| matches!(node.data.value, Value::Code { .. }))
+                });
+
+                if !in_code {
+                    // offsets into owned text don't map back to the source
+                    let pos = match node.data.text {
+                        TextSource::Span => node.data.pos,
+                        TextSource::Owned(_) => Position::generated(),
+                    };
+
+                    references.scan(node.data.as_str(sources), pos);
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    references.check(diagnostics);
+}
+
+// line number, starting at 1, of the byte `offset` in `text`
+fn line_of(text: &str, offset: usize) -> Option<usize> {
+    Some(text.get(..offset)?.matches('\n').count() + 1)
+}
+
+/// Turns heading text into its ID,
+/// lowercase words joined by dashes.
+pub(crate) fn slug(text: &str) -> String {
+    let mut id = String::with_capacity(text.len());
+
+    for ch in text.trim().chars() {
+        if ch.is_alphanumeric() || ch == '_' || ch == '-' {
+            id.extend(ch.to_lowercase());
+        } else if ch.is_whitespace() && !id.ends_with('-') {
+            id.push('-');
+        }
+    }
+
+    id
+}
+
+/// Reference links and definitions found in the text of a document.
+///
+/// Inline syntax isn't parsed yet, so they are recognized
+/// directly in the text: `[label]: url` at the start of a line
+/// defines a label, `[text][label]` and `[label][]` use one.
+#[derive(Default)]
+struct References {
+    /// Normalized labels which are defined.
+    definitions: Vec<String>,
+
+    /// Labels which are used, along with where.
+    uses: Vec<(String, Position)>,
+}
+
+impl References {
+    /// Scans `text`, found at `pos`. Uses in text
+    /// at a generated `pos` are reported without a position.
+    fn scan(&mut self, text: &str, pos: Position) {
+        let mut ix = 0;
+
+        while let Some(open) = text[ix..].find('[').map(|open| ix + open) {
+            let Some(close) = text[open + 1..].find(']').map(|close| open + 1 + close) else {
+                break;
+            };
+
+            let label = &text[open + 1..close];
+            let rest = &text[close + 1..];
+            let before = text[..open].trim_end_matches([' ', '\t']);
+            ix = close + 1;
+
+            if (before.is_empty() || before.ends_with('\n')) && rest.starts_with(':') {
+                self.definitions.push(normalize(label));
+            } else if let Some(after) = rest.strip_prefix('[')
+                && let Some(end) = after.find(']')
+            {
+                // `[label][]` uses its text as the label
+                let used = if after[..end].is_empty() {
+                    label
+                } else {
+                    &after[..end]
+                };
+
+                ix = close + end + 3;
+                let position = if pos.is_generated() {
+                    pos
+                } else {
+                    Position::new(pos.start + open, pos.start + ix).with_source(pos.source)
+                };
+
+                self.uses.push((normalize(used), position));
+            }
+        }
+    }
+
+    fn check(self, diagnostics: &mut Vec<Diagnostic>) {
+        for (label, position) in self.uses {
+            if !self.definitions.contains(&label) {
+                diagnostics.push(
+                    Diagnostic::new(
+                        Severity::Warning,
+                        Diagnostic::UNRESOLVED_REFERENCE,
+                        format!("unresolved reference `{label}`"),
+                        position,
+                    )
+                    .with_note(format!(
+                        "define it with `[{label}]: <url>` on a line of its own"
+                    )),
+                );
+            }
+        }
+    }
+}
+
+// reference labels match case-insensitively,
+// with runs of whitespace treated as a single space
+fn normalize(label: &str) -> String {
+    let mut normalized = String::with_capacity(label.len());
+
+    for word in label.split_whitespace() {
+        if !normalized.is_empty() {
+            normalized.push(' ');
+        }
+
+        normalized.extend(word.chars().flat_map(char::to_lowercase));
+    }
+
+    normalized
+}
+
+impl Diagnostic {
+    pub(crate) fn unclosed_macro(name: &str, position: Position) -> Self {
+        Diagnostic::new(
+            Severity::Error,
+            Diagnostic::UNCLOSED_MACRO,
+            format!("macro `{name}` is never closed"),
+            position,
+        )
+        .with_note("close it with a `)` on a line of its own")
+    }
+
+    pub(crate) fn nesting_too_deep(max_depth: usize, position: Position) -> Self {
+        Diagnostic::new(
+            Severity::Warning,
+            Diagnostic::NESTING_TOO_DEEP,
+            "containers are nested too deeply",
+            position,
+        )
+        .with_note(format!(
+            "at most {max_depth} containers can be nested, the markers past that are kept as text"
+        ))
+    }
+
+    pub(crate) fn too_many_nodes(position: Position) -> Self {
+        Diagnostic::new(
+            Severity::Error,
+            Diagnostic::TOO_MANY_NODES,
+            "the document has too many nodes",
+            position,
+        )
+        .with_note("the rest of the document is left out")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, Severity, check, slug};
+    use crate::ast::TextSource;
+    use crate::block_parser::Parser;
+    use crate::lib::Vec;
+
+    fn codes(src: &str, parser: &Parser) -> Vec<&'static str> {
+        parser
+            .parse_document(src)
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect()
+    }
+
+    #[test]
+    fn clean_document() {
+        let src = "# Salt\n\n<>= note (x) (\nWater.\n\n)\n\n## Water\n";
+        let parser = Parser::new().known_macros(["note"]);
+
+        assert!(codes(src, &parser).is_empty());
+    }
+
+    #[test]
+    fn macros() {
+        let parser = Parser::new().known_macros(["note"]);
+
+        assert_eq!(
+            codes("<>= warning (x) (\nSalt.\n\n)\n", &parser),
+            [Diagnostic::UNKNOWN_MACRO]
+        );
+        assert_eq!(
+            codes("<>= note (x) (\nSalt.\n", &parser),
+            [Diagnostic::UNCLOSED_MACRO]
+        );
+    }
+
+    #[test]
+    fn headings_and_references() {
+        let src =
+            "# Salt water\n\nSee [the sea][sea] and [salt][].\n\n[salt]: /salt\n\n# Salt  Water\n";
+
+        let document = Parser::new().parse_document(src);
+        let diagnostics = document.diagnostics();
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].code, Diagnostic::DUPLICATE_HEADING_ID);
+        assert_eq!(diagnostics[1].code, Diagnostic::UNRESOLVED_REFERENCE);
+        assert_eq!(diagnostics[1].position.start, src.find("[the").unwrap());
+        assert_eq!(slug(" Salt  water! "), "salt-water");
+    }
+
+    #[test]
+    fn references_in_owned_text() {
+        let src = "Salt.\n";
+        let mut document = Parser::new().parse_document(src);
+        let text = document.tree().nodes().nth(1).unwrap();
+        document.tree_mut().get_mut(text).unwrap().data.text =
+            TextSource::Owned("See [the sea][sea].".into());
+
+        let mut diagnostics = Vec::new();
+        check(document.tree(), &document, None, &mut diagnostics);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Diagnostic::UNRESOLVED_REFERENCE);
+        assert!(diagnostics[0].position.is_generated());
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let src = ">".repeat(10);
+        let parser = Parser::new().max_depth(3);
+
+        assert_eq!(codes(&src, &parser), [Diagnostic::NESTING_TOO_DEEP]);
+    }
+
+    #[test]
+    fn pretty() {
+        let src = "# Salt\n<>= note (x) (\nWater.\n";
+        let mut document = Parser::new().parse_document(src);
+        document.sources_mut().clear();
+        document.sources_mut().add("sea.md", src);
+
+        let diagnostic = &document.diagnostics()[0];
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(
+            diagnostic.pretty(&document),
+            "error[unclosed-macro]: macro `note` is never closed\n \
+             --> sea.md:2:1\n  \
+             |\n\
+             2 | <>= note (x) (\n  \
+             | ^^^^^^^^^^^^^^\n  \
+             |\n  \
+             = note: close it with a `)` on a line of its own\n"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! A parsed document, which can be reused between parses.
+
+use crate::ast::{AstNode, SourceId};
+use crate::diagnostic::Diagnostic;
+use crate::events::Events;
+use crate::lib::Vec;
+use crate::source::{SourceMap, Sources};
+use crate::syntax::Syntax;
+use crate::tree::TreeArena;
+
+/// The result of parsing a Markdown document.
+///
+/// Keeps the memory it allocated, so parsing many documents
+/// with `Parser::parse_into` and the same `Document`
+/// doesn't allocate a new tree for every one of them.
+#[derive(Debug, Default)]
+pub struct Document {
+    tree: TreeArena<AstNode>,
+    sources: SourceMap,
+    diagnostics: Vec<Diagnostic>,
+    syntax: Option<Syntax>,
+}
+
+impl Document {
+    /// Creates an empty `Document`.
+    ///
+    /// Doesn't allocate until something is parsed into it.
+    pub fn new() -> Self {
+        Self {
+            tree: TreeArena::new(),
+            sources: SourceMap::new(),
+            diagnostics: Vec::new(),
+            syntax: None,
+        }
+    }
+
+    /// Returns the tree of the document.
+    #[inline]
+    pub fn tree(&self) -> &TreeArena<AstNode> {
+        &self.tree
+    }
+
+    /// Returns the tree of the document mutably.
+    #[inline]
+    pub fn tree_mut(&mut self) -> &mut TreeArena<AstNode> {
+        &mut self.tree
+    }
+
+    /// Returns the sources of the document.
+    ///
+    /// The source the document was parsed from is `SourceId::MAIN`.
+    #[inline]
+    pub fn sources(&self) -> &SourceMap {
+        &self.sources
+    }
+
+    /// Returns the sources of the document mutably,
+    /// to add the sources of grafted nodes.
+    #[inline]
+    pub fn sources_mut(&mut self) -> &mut SourceMap {
+        &mut self.sources
+    }
+
+    /// Returns the problems found while parsing the document.
+    #[inline]
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Returns the problems found while parsing the document mutably,
+    /// so tools can add their own.
+    #[inline]
+    pub fn diagnostics_mut(&mut self) -> &mut Vec<Diagnostic> {
+        &mut self.diagnostics
+    }
+
+    /// Returns the lossless `Syntax` of the document,
+    /// if it was parsed with `Parser::lossless`.
+    #[inline]
+    pub fn syntax(&self) -> Option<&Syntax> {
+        self.syntax.as_ref()
+    }
+
+    #[inline]
+    pub(crate) fn syntax_mut(&mut self) -> &mut Option<Syntax> {
+        &mut self.syntax
+    }
+
+    /// Returns an iterator over the `Event`s of the document,
+    /// with text resolved through its sources.
+    pub fn events(&self) -> Events<'_> {
+        self.tree.events(&self.sources)
+    }
+
+    /// Takes the tree out of the document.
+    pub fn into_tree(self) -> TreeArena<AstNode> {
+        self.tree
+    }
+
+    /// Removes the contents of the document,
+    /// keeping the allocated memory for reuse.
+    pub fn clear(&mut self) {
+        self.tree.clear();
+        self.sources.clear();
+        self.diagnostics.clear();
+        self.syntax = None;
+    }
+}
+
+impl Sources for Document {
+    fn text(&self, source: SourceId) -> Option<&str> {
+        self.sources.text(source)
+    }
+
+    fn name(&self, source: SourceId) -> Option<&str> {
+        Sources::name(&self.sources, source)
+    }
+}
+
+impl From<TreeArena<AstNode>> for Document {
+    /// Wraps a tree without any sources,
+    /// add them with `Document::sources_mut`.
+    fn from(tree: TreeArena<AstNode>) -> Self {
+        Self {
+            tree,
+            sources: SourceMap::new(),
+            diagnostics: Vec::new(),
+            syntax: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Document;
+    use crate::ast::{AstNode, Position, SourceId, Value};
+    use crate::block_parser::{Parser, parse};
+    use crate::lib::String;
+    use crate::render::html::push_html;
+
+    fn html(document: &Document, src: &str) -> String {
+        let mut out = String::new();
+        push_html(&mut out, document.tree().events(src));
+
+        out
+    }
+
+    #[test]
+    fn parse_into_reuses_the_document() {
+        let parser = Parser::new();
+        let mut document = Document::new();
+
+        let first = "# Salt\n\n> Water.\n\n- One\n- Two\n".repeat(1_000);
+        parser.parse_into(&mut document, &first);
+        let stale = document.tree().root().unwrap();
+        let allocated = document.tree().len();
+
+        let second = "Salt *water*.";
+        parser.parse_into(&mut document, second);
+
+        assert!(document.tree().get(stale).is_none());
+        assert!(document.tree().len() < allocated);
+        assert_eq!(html(&document, second), html(&parse(second).into(), second));
+
+        parser.parse_into(&mut document, &first);
+        assert_eq!(document.tree().len(), allocated);
+        assert_eq!(html(&document, &first), html(&parse(&first).into(), &first));
+    }
+
+    #[test]
+    fn multiple_sources() {
+        let mut document = Document::new();
+        Parser::new().parse_into(&mut document, "> Salt.\n");
+
+        let include = "Water.";
+        let source = document.sources_mut().add("water.md", include);
+        let other = Parser::new().source(source).parse(include);
+
+        let tree = document.tree_mut();
+        let quote = tree.root().unwrap();
+        let paragraph = tree
+            .graft(quote, &other, other.root().unwrap(), source)
+            .unwrap();
+        let text = tree.children(paragraph).next().unwrap();
+        let generated = tree
+            .append_child(
+                paragraph,
+                AstNode::new(Value::Text, Position::generated(), 0),
+            )
+            .unwrap();
+
+        assert_eq!(
+            document.tree().get(text).unwrap().data.as_str(&document),
+            "Water."
+        );
+        assert_eq!(document.tree().get(text).unwrap().data.as_str("Salt."), "");
+        assert_eq!(
+            document
+                .tree()
+                .get(generated)
+                .unwrap()
+                .data
+                .as_str(&document),
+            ""
+        );
+        assert_eq!(document.sources().name(source), Some("water.md"));
+
+        let mut out = String::new();
+        push_html(&mut out, document.events());
+        assert_eq!(
+            out,
+            "<blockquote>\n<p>Salt.</p>\n<p>Water.</p>\n</blockquote>\n"
+        );
+
+        assert_eq!(
+            document.tree().get(quote).unwrap().data.pos.source,
+            SourceId::MAIN
+        );
+    }
+
+    #[test]
+    fn mismatched_sources() {
+        let long = "# A heading which is longer than the other source.";
+        let tree = parse(long);
+        let heading = &tree.get(tree.root().unwrap()).unwrap().data;
+
+        assert_eq!(heading.as_str("short"), "");
+
+        // a node of another document's main source
+        let document = Parser::new().parse_document("Salt.");
+        assert_eq!(heading.as_str(&document), "");
+    }
+
+    #[test]
+    fn graft_between_documents() {
+        let mut document = Parser::new().parse_document("> Salt.\n");
+        let other = Parser::new().parse_document("Water and *more*.");
+
+        let source = document.sources_mut().add("water.md", "Water and *more*.");
+        let quote = document.tree().root().unwrap();
+        let root = other.tree().root().unwrap();
+        let paragraph = document
+            .tree_mut()
+            .graft(quote, other.tree(), root, source)
+            .unwrap();
+
+        let tree = document.tree();
+        for id in tree.descendants(paragraph) {
+            assert_eq!(tree.get(id).unwrap().data.pos.source, source);
+        }
+
+        let text = tree.children(paragraph).next().unwrap();
+        assert_eq!(
+            tree.get(text).unwrap().data.as_str(&document),
+            "Water and *more*."
+        );
+
+        let mut out = String::new();
+        push_html(&mut out, document.events());
+        assert_eq!(
+            out,
+            "<blockquote>\n<p>Salt.</p>\n<p>Water and *more*.</p>\n</blockquote>\n"
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! Pull-parser style access to a parsed document.
+//!
+//! `Events` walks a `TreeArena<AstNode>` and flattens it into
+//! a stream of `Start`/`End` pairs for containers and single
+//! events for leaves, so that simple tools can stream through
+//! a document without dealing with the tree itself.
+
+use crate::ast::{AstNode, Lang, Value};
+use crate::lib::Vec;
+use crate::render::{link_destination, link_title};
+use crate::source::SourceText;
+use crate::tree::{NodeId, TreeArena};
+use core::num::NonZero;
+
+/// A container element, opened by `Event::Start`
+/// and closed by the matching `Event::End`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tag<'a> {
+    /// Macro invocation.
+    Macro {
+        name: &'a str,
+    },
+
+    Paragraph,
+
+    Blockquote,
+
+    Heading {
+        level: NonZero<u8>,
+    },
+
+    BulletList {
+        tight: bool,
+    },
+
+    OrderedList {
+        tight: bool,
+        start_index: u64,
+    },
+
+    ListItem,
+
+    /// A code block, its contents are
+    /// yielded as `Event::Code`.
+    Code {
+        lang: Option<&'a Lang>,
+        info: Option<&'a str>,
+    },
+
+    Emphasis {
+        strong: bool,
+    },
+
+    /// A link, with the destination and title
+    /// read from its source, empty if it has none.
+    Link {
+        destination: &'a str,
+        title: &'a str,
+    },
+
+    /// An image, its text is the alternative text.
+    Image {
+        destination: &'a str,
+        title: &'a str,
+    },
+
+    Strikethrough,
+}
+
+/// A single step through the document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// Start of a container.
+    Start(Tag<'a>),
+
+    /// End of a container.
+    End(Tag<'a>),
+
+    /// Text.
+    Text(&'a str),
+
+    /// Unparsed text inside of a code block.
+    Code(&'a str),
+
+    /// Raw HTML.
+    Html(&'a str),
+
+    /// Emoji code.
+    Emoji(&'a str),
+
+    /// Escaped character.
+    EscapeChar(char),
+
+    /// A style break.
+    StyleBreak,
+
+    /// A hard break.
+    HardBreak,
+
+    /// A soft break.
+    SoftBreak,
+}
+
+/// Iterator over the `Event`s of a document.
+///
+/// Created by `TreeArena::events` or `Events::subtree`.
+pub struct Events<'a> {
+    tree: &'a TreeArena<AstNode>,
+    src: SourceText<'a>,
+
+    /// Next node to be entered.
+    cursor: Option<NodeId>,
+
+    /// Containers which were entered but not yet left.
+    stack: Vec<NodeId>,
+
+    /// Amount of currently open code blocks.
+    code_depth: usize,
+
+    /// Whether to stop after the first node is left,
+    /// instead of continuing with its siblings.
+    bounded: bool,
+}
+
+impl<'a> Events<'a> {
+    /// Creates an iterator over the whole document.
+    ///
+    /// The given `src` should be the original data
+    /// from which the `tree` was parsed, or a `SourceMap`
+    /// holding all of its sources.
+    pub fn new(tree: &'a TreeArena<AstNode>, src: impl Into<SourceText<'a>>) -> Self {
+        Self {
+            tree,
+            src: src.into(),
+            cursor: tree.root(),
+            stack: Vec::new(),
+            code_depth: 0,
+            bounded: false,
+        }
+    }
+
+    /// Creates an iterator over the node `id` and its descendants.
+    pub fn subtree(
+        tree: &'a TreeArena<AstNode>,
+        src: impl Into<SourceText<'a>>,
+        id: NodeId,
+    ) -> Self {
+        Self {
+            tree,
+            src: src.into(),
+            cursor: Some(id),
+            stack: Vec::new(),
+            code_depth: 0,
+            bounded: true,
+        }
+    }
+
+    fn tag(&self, node: &'a AstNode) -> Option<Tag<'a>> {
+        let tag = match &node.value {
+            Value::Macro { name } => Tag::Macro { name },
+            Value::Paragraph => Tag::Paragraph,
+            Value::Blockquote => Tag::Blockquote,
+            Value::Heading { level } => Tag::Heading { level: *level },
+            Value::BulletList { tight } => Tag::BulletList { tight: *tight },
+            Value::OrderedList { tight, start_index } => Tag::OrderedList {
+                tight: *tight,
+                start_index: *start_index,
+            },
+            Value::ListItem => Tag::ListItem,
+            Value::Code { meta, .. } => Tag::Code {
+                lang: node.value.lang(),
+                info: meta
+                    .as_ref()
+                    .and_then(|meta| meta.info())
+                    .map(|x| x.as_str()),
+            },
+            Value::Emphasis { strong } => Tag::Emphasis { strong: *strong },
+            Value::Link => Tag::Link {
+                destination: link_destination(self.text(node)).unwrap_or_default(),
+                title: link_title(self.text(node)).unwrap_or_default(),
+            },
+            Value::Image => Tag::Image {
+                destination: link_destination(self.text(node)).unwrap_or_default(),
+                title: link_title(self.text(node)).unwrap_or_default(),
+            },
+            Value::Strikethrough => Tag::Strikethrough,
+
+            Value::Root
+            | Value::HtmlBlock
+            | Value::StyleBreak
+            | Value::Emoji
+            | Value::HardBreak
+            | Value::SoftBreak
+            | Value::EscapeChar(_)
+            | Value::Text => return None,
+        };
+
+        Some(tag)
+    }
+
+    fn leaf(&self, node: &'a AstNode) -> Option<Event<'a>> {
+        let event = match node.value {
+            Value::HtmlBlock => Event::Html(self.text(node)),
+            Value::StyleBreak => Event::StyleBreak,
+            Value::Emoji => Event::Emoji(self.text(node)),
+            Value::HardBreak => Event::HardBreak,
+            Value::SoftBreak => Event::SoftBreak,
+            Value::EscapeChar(ch) => Event::EscapeChar(ch),
+            Value::Text if self.code_depth > 0 => Event::Code(self.text(node)),
+            Value::Text => Event::Text(self.text(node)),
+
+            _ => return None,
+        };
+
+        Some(event)
+    }
+
+    fn text(&self, node: &'a AstNode) -> &'a str {
+        match self.src {
+            SourceText::Str(src) => node.as_str(src),
+            SourceText::Map(sources) => node.as_str(sources),
+        }
+    }
+
+    // sibling to continue with after leaving a node
+    fn after(&self, next: Option<NodeId>) -> Option<NodeId> {
+        if self.bounded && self.stack.is_empty() {
+            None
+        } else {
+            next
+        }
+    }
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(id) = self.cursor {
+                let Some(node) = self.tree.get(id) else {
+                    self.cursor = None;
+                    continue;
+                };
+
+                if let Some(event) = self.leaf(&node.data) {
+                    self.cursor = self.after(node.next_sibling());
+                    return Some(event);
+                }
+
+                self.stack.push(id);
+                self.cursor = node.first_child();
+
+                // `Root` is transparent.
+                if let Some(tag) = self.tag(&node.data) {
+                    if matches!(tag, Tag::Code { .. }) {
+                        self.code_depth += 1;
+                    }
+
+                    return Some(Event::Start(tag));
+                }
+
+                continue;
+            }
+
+            let id = self.stack.pop()?;
+            let node = self.tree.get(id)?;
+            self.cursor = self.after(node.next_sibling());
+
+            if let Some(tag) = self.tag(&node.data) {
+                if matches!(tag, Tag::Code { .. }) {
+                    self.code_depth -= 1;
+                }
+
+                return Some(Event::End(tag));
+            }
+        }
+    }
+}
+
+impl TreeArena<AstNode> {
+    /// Returns an iterator over the `Event`s of this tree.
+    ///
+    /// The given `src` should be the original data
+    /// from which this tree was parsed, or a `SourceMap`
+    /// holding all of its sources.
+    pub fn events<'a>(&'a self, src: impl Into<SourceText<'a>>) -> Events<'a> {
+        Events::new(self, src)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, Events, Tag};
+    use crate::block_parser::parse;
+    use crate::lib::Vec;
+    use core::num::NonZero;
+
+    #[test]
+    fn paragraph_and_heading() {
+        let src = "This is a paragraph!\n\n# This is a heading.";
+        let tree = parse(src);
+
+        let events = tree.events(src).collect::<Vec<_>>();
+
+        assert_eq!(
+            events,
+            [
+                Event::Start(Tag::Paragraph),
+                Event::Text("This is a paragraph!"),
+                Event::End(Tag::Paragraph),
+                Event::Start(Tag::Heading {
+                    level: NonZero::new(1).unwrap()
+                }),
+                Event::Text("This is a heading."),
+                Event::End(Tag::Heading {
+                    level: NonZero::new(1).unwrap()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_containers_are_balanced() {
+        let src = "> > - Salt water.\n";
+        let tree = parse(src);
+
+        let mut depth = 0_usize;
+        for event in tree.events(src) {
+            match event {
+                Event::Start(_) => depth += 1,
+                Event::End(_) => depth -= 1,
+                _ => (),
+            }
+        }
+
+        assert_eq!(depth, 0);
+    }
+
+    #[test]
+    fn subtree() {
+        let src = "First paragraph.\n\nSecond paragraph.";
+        let tree = parse(src);
+        let first = tree.root().unwrap();
+
+        let events = Events::subtree(&tree, src, first).collect::<Vec<_>>();
+
+        assert_eq!(
+            events,
+            [
+                Event::Start(Tag::Paragraph),
+                Event::Text("First paragraph."),
+                Event::End(Tag::Paragraph),
+            ]
+        );
+    }
+}
@@ -0,0 +1,1262 @@
+//! JSON serialization of a parsed document.
+//!
+//! A document is written as an object holding the schema version
+//! and the top-level nodes:
+//!
+//! ```json
+//! {"type": "document", "version": 1, "children": [NODE, ...]}
+//! ```
+//!
+//! Every node is an object with its kind, as returned by `Value::name`,
+//! its position and children, along with the attributes of its kind:
+//!
+//! ```json
+//! {"type": "heading", "level": 2,
+//!  "position": {"start": 0, "end": 9}, "children": [...]}
+//! ```
+//!
+//! - `position` is `null` for generated nodes, and has a `source`
+//!   index when the node doesn't come from `SourceId::MAIN`,
+//! - `text`, a string, is present on `text`, `html-block` and `emoji` nodes,
+//! - `children` is an array, empty for leaves.
+//!
+//! The attributes, named like in `crate::query`, are
+//!
+//! - `heading`: `level`, a number from 1 to 6,
+//! - `bullet-list`: `tight`, a boolean,
+//! - `ordered-list`: `tight`, a boolean, and `start`, a number,
+//! - `code`: `lang` and `info`, strings or `null`,
+//! - `emphasis`: `strong`, a boolean,
+//! - `escape-char`: `char`, a string of one character,
+//! - `macro`: `name`, a string.
+//!
+//! Fields may come in any order, unknown fields are ignored when reading.
+//! Numbers are non-negative integers. New fields and node kinds may be
+//! added without bumping `version`, changes to existing ones bump it.
+//!
+//! The pandoc mode writes the document in the JSON format
+//! of pandoc's AST instead, so it can be fed to pandoc filters
+//! or converted further with `pandoc -f json`.
+
+use crate::ast::{AstNode, CodeMeta, Lang, Position, SourceId, TextSource, Value};
+use crate::lib::{String, ToString, Vec};
+use crate::render::{NodeWriter, children_text, list_delimiter, write_tree};
+use crate::source::Sources;
+use crate::tree::{Control, NodeId, TreeArena};
+use core::fmt::{self, Write};
+use core::num::NonZero;
+
+/// Version of the JSON schema written by `write_json`.
+pub const VERSION: u64 = 1;
+
+/// Version of pandoc's AST written by `write_pandoc_json`.
+pub const PANDOC_API_VERSION: [u32; 3] = [1, 23, 1];
+
+/// Error returned when JSON fails to be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    /// Byte offset into the JSON at which the error happened.
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl core::error::Error for JsonError {}
+
+/// Serializes `tree` as JSON, appending it to `out`.
+///
+/// The text of nodes is looked up in `sources`.
+pub fn push_json<S>(out: &mut String, tree: &TreeArena<AstNode>, sources: &S)
+where
+    S: Sources + ?Sized,
+{
+    write_json(out, tree, sources).expect("writing to a `String` is infallible");
+}
+
+/// Serializes `tree` as JSON into `out`.
+///
+/// # Errors
+/// if writing to `out` fails.
+pub fn write_json<W, S>(mut out: W, tree: &TreeArena<AstNode>, sources: &S) -> fmt::Result
+where
+    W: Write,
+    S: Sources + ?Sized,
+{
+    write!(
+        out,
+        "{{\"type\":\"document\",\"version\":{VERSION},\"children\":["
+    )?;
+
+    let mut writer = JsonWriter { out, tree, sources };
+    write_tree(tree, None, &mut writer)?;
+
+    writer.out.write_str("]}")
+}
+
+/// Reads a tree serialized by `write_json`.
+///
+/// Nodes with a `text` carry it as owned text,
+/// so the tree doesn't need the sources it was parsed from.
+///
+/// # Errors
+/// if `json` isn't valid JSON or doesn't follow the schema.
+pub fn from_json(json: &str) -> Result<TreeArena<AstNode>, JsonError> {
+    let document = JsonParser { src: json, ix: 0 }.run()?;
+    let mut tree = TreeArena::new();
+
+    let fields = document.object()?;
+    match document.field(fields, "type")?.string()? {
+        "document" => (),
+        _ => return document.error("expected a document"),
+    }
+
+    match document.field(fields, "version")?.number()? {
+        VERSION => (),
+        _ => return document.error("unsupported version"),
+    }
+
+    for child in document.field(fields, "children")?.array()? {
+        attach(&mut tree, child)?;
+    }
+
+    Ok(tree)
+}
+
+/// Serializes `tree` as pandoc JSON, appending it to `out`.
+///
+/// The text of nodes is looked up in `sources`.
+pub fn push_pandoc_json<S>(out: &mut String, tree: &TreeArena<AstNode>, sources: &S)
+where
+    S: Sources + ?Sized,
+{
+    write_pandoc_json(out, tree, sources).expect("writing to a `String` is infallible");
+}
+
+/// Serializes `tree` as pandoc JSON into `out`.
+///
+/// Macro invocations become `Div`s with a `macro` attribute,
+/// links and images have empty targets.
+///
+/// # Errors
+/// if writing to `out` fails.
+pub fn write_pandoc_json<W, S>(mut out: W, tree: &TreeArena<AstNode>, sources: &S) -> fmt::Result
+where
+    W: Write,
+    S: Sources + ?Sized,
+{
+    let [major, minor, patch] = PANDOC_API_VERSION;
+    write!(
+        out,
+        "{{\"pandoc-api-version\":[{major},{minor},{patch}],\"meta\":{{}},\"blocks\":["
+    )?;
+
+    let mut writer = PandocWriter {
+        out,
+        tree,
+        sources,
+        arrays: Vec::from([Array { empty: true }]),
+        contexts: Vec::new(),
+    };
+    write_tree(tree, None, &mut writer)?;
+
+    writer.out.write_str("]}")
+}
+
+struct JsonWriter<'t, W, S: ?Sized> {
+    out: W,
+    tree: &'t TreeArena<AstNode>,
+    sources: &'t S,
+}
+
+impl<W: Write, S: Sources + ?Sized> NodeWriter for JsonWriter<'_, W, S> {
+    fn enter_node(&mut self, id: NodeId, node: &AstNode, _: usize) -> Result<Control, fmt::Error> {
+        if self
+            .tree
+            .get(id)
+            .is_some_and(|target| target.prev_sibling().is_some())
+        {
+            self.out.write_char(',')?;
+        }
+
+        write!(self.out, "{{\"type\":\"{}\"", node.value.name())?;
+
+        match &node.value {
+            Value::Macro { name } => {
+                self.out.write_str(",\"name\":")?;
+                write_string(&mut self.out, name)?;
+            }
+            Value::Heading { level } => write!(self.out, ",\"level\":{level}")?,
+            Value::BulletList { tight } => write!(self.out, ",\"tight\":{tight}")?,
+            Value::OrderedList { tight, start_index } => {
+                write!(self.out, ",\"tight\":{tight},\"start\":{start_index}")?;
+            }
+            Value::Code { meta, .. } => {
+                self.out.write_str(",\"lang\":")?;
+                write_optional(&mut self.out, node.value.lang().and_then(Lang::name))?;
+                self.out.write_str(",\"info\":")?;
+                write_optional(
+                    &mut self.out,
+                    meta.as_ref().and_then(CodeMeta::info).map(String::as_str),
+                )?;
+            }
+            Value::Emphasis { strong } => write!(self.out, ",\"strong\":{strong}")?,
+            Value::EscapeChar(ch) => {
+                self.out.write_str(",\"char\":")?;
+                write_string(&mut self.out, ch.encode_utf8(&mut [0; 4]))?;
+            }
+            Value::Text | Value::HtmlBlock | Value::Emoji => {
+                self.out.write_str(",\"text\":")?;
+                write_string(&mut self.out, node.as_str(self.sources))?;
+            }
+
+            _ => (),
+        }
+
+        let pos = node.pos;
+        if pos.is_generated() {
+            self.out.write_str(",\"position\":null")?;
+        } else if pos.source == SourceId::MAIN {
+            write!(
+                self.out,
+                ",\"position\":{{\"start\":{},\"end\":{}}}",
+                pos.start, pos.end
+            )?;
+        } else {
+            write!(
+                self.out,
+                ",\"position\":{{\"start\":{},\"end\":{},\"source\":{}}}",
+                pos.start,
+                pos.end,
+                pos.source.index()
+            )?;
+        }
+
+        self.out.write_str(",\"children\":[")?;
+
+        Ok(Control::Continue)
+    }
+
+    fn exit_node(&mut self, _: NodeId, _: &AstNode, _: usize) -> fmt::Result {
+        self.out.write_str("]}")
+    }
+}
+
+/// Whether the children of a node are pandoc blocks or inlines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    Blocks,
+    Inlines,
+
+    /// A list, whose children are items.
+    List,
+
+    /// A node whose children are already written, or left out.
+    Skipped,
+}
+
+/// A JSON array being written.
+struct Array {
+    empty: bool,
+}
+
+struct PandocWriter<'t, W, S: ?Sized> {
+    out: W,
+    tree: &'t TreeArena<AstNode>,
+    sources: &'t S,
+
+    /// Arrays currently open, the innermost one last.
+    arrays: Vec<Array>,
+
+    /// Context of the children of every entered node.
+    contexts: Vec<Context>,
+}
+
+impl<W: Write, S: Sources + ?Sized> PandocWriter<'_, W, S> {
+    fn context(&self) -> Context {
+        self.contexts.last().copied().unwrap_or(Context::Blocks)
+    }
+
+    // starts an element of the innermost array
+    fn element(&mut self) -> fmt::Result {
+        if let Some(array) = self.arrays.last_mut()
+            && !core::mem::take(&mut array.empty)
+        {
+            return self.out.write_char(',');
+        }
+
+        Ok(())
+    }
+
+    fn open(&mut self, prefix: &str) -> fmt::Result {
+        self.out.write_str(prefix)?;
+        self.arrays.push(Array { empty: true });
+
+        Ok(())
+    }
+
+    fn close(&mut self, suffix: &str) -> fmt::Result {
+        self.arrays.pop();
+        self.out.write_str(suffix)
+    }
+
+    // whether `id` is an item of a tight list, or inside of one
+    fn in_tight_list(&self, id: NodeId) -> bool {
+        let mut ancestors = self
+            .tree
+            .ancestors(id)
+            .filter_map(|id| self.tree.get(id))
+            .map(|node| &node.data.value);
+
+        let list = match ancestors.next() {
+            Some(Value::ListItem) => ancestors.next(),
+            parent => parent,
+        };
+
+        matches!(
+            list,
+            Some(Value::BulletList { tight: true } | Value::OrderedList { tight: true, .. })
+        )
+    }
+
+    // writes the start of `node`, returning the context of its children
+    fn start_node(&mut self, id: NodeId, node: &AstNode) -> Result<Context, fmt::Error> {
+        let context = self.context();
+
+        // other nodes than items inside of lists are wrapped in items
+        // of their own, inlines among blocks in a `Plain` block
+        match (context, node.value.is_inline()) {
+            (Context::Skipped, _) => return Ok(Context::Skipped),
+            (Context::List, _) if node.value != Value::ListItem => {
+                self.element()?;
+                self.open("[")?;
+            }
+            (Context::Blocks, true) => {
+                self.element()?;
+                self.open("{\"t\":\"Plain\",\"c\":[")?;
+            }
+
+            _ => (),
+        }
+
+        let inner = match &node.value {
+            Value::Root => Context::Blocks,
+            Value::Macro { name } => {
+                self.element()?;
+                self.out
+                    .write_str("{\"t\":\"Div\",\"c\":[[\"\",[],[[\"macro\",")?;
+                write_string(&mut self.out, name)?;
+                self.open("]]],[")?;
+
+                Context::Blocks
+            }
+            // paragraphs of tight lists are `Plain`, as in pandoc
+            Value::Paragraph if self.in_tight_list(id) => {
+                self.element()?;
+                self.open("{\"t\":\"Plain\",\"c\":[")?;
+
+                Context::Inlines
+            }
+            Value::Paragraph => {
+                self.element()?;
+                self.open("{\"t\":\"Para\",\"c\":[")?;
+
+                Context::Inlines
+            }
+            Value::Blockquote => {
+                self.element()?;
+                self.open("{\"t\":\"BlockQuote\",\"c\":[")?;
+
+                Context::Blocks
+            }
+            Value::HtmlBlock if context == Context::Inlines => {
+                self.element()?;
+                self.out
+                    .write_str("{\"t\":\"RawInline\",\"c\":[\"html\",")?;
+                write_string(&mut self.out, node.as_str(self.sources))?;
+                self.out.write_str("]}")?;
+
+                Context::Skipped
+            }
+            Value::HtmlBlock => {
+                self.element()?;
+                self.out.write_str("{\"t\":\"RawBlock\",\"c\":[\"html\",")?;
+                write_string(&mut self.out, node.as_str(self.sources))?;
+                self.out.write_str("]}")?;
+
+                Context::Skipped
+            }
+            Value::StyleBreak => {
+                self.element()?;
+                self.out.write_str("{\"t\":\"HorizontalRule\"}")?;
+
+                Context::Skipped
+            }
+            Value::Heading { level } => {
+                self.element()?;
+                write!(self.out, "{{\"t\":\"Header\",\"c\":[{level},[\"\",[],[]],")?;
+                self.open("[")?;
+
+                Context::Inlines
+            }
+            Value::BulletList { .. } => {
+                self.element()?;
+                self.open("{\"t\":\"BulletList\",\"c\":[")?;
+
+                Context::List
+            }
+            Value::OrderedList { start_index, .. } => {
+                let delimiter = match list_delimiter(node.as_str(self.sources)) {
+                    Some('.') => "Period",
+                    Some(')') => "OneParen",
+                    _ => "DefaultDelim",
+                };
+
+                self.element()?;
+                write!(
+                    self.out,
+                    "{{\"t\":\"OrderedList\",\"c\":[[{start_index},{{\"t\":\"Decimal\"}},{{\"t\":\"{delimiter}\"}}],"
+                )?;
+                self.open("[")?;
+
+                Context::List
+            }
+            Value::ListItem => {
+                self.element()?;
+                self.open("[")?;
+
+                Context::Blocks
+            }
+            Value::Code { .. } => {
+                let code = children_text(self.tree, id, self.sources);
+
+                self.element()?;
+                self.out.write_str("{\"t\":\"CodeBlock\",\"c\":[[\"\",[")?;
+                if let Some(name) = node.value.lang().and_then(Lang::name) {
+                    write_string(&mut self.out, name)?;
+                }
+                self.out.write_str("],[]],")?;
+                write_string(&mut self.out, code.strip_suffix('\n').unwrap_or(&code))?;
+                self.out.write_str("]}")?;
+
+                Context::Skipped
+            }
+            Value::Emphasis { strong } => {
+                self.element()?;
+                let tag = if *strong { "Strong" } else { "Emph" };
+                write!(self.out, "{{\"t\":\"{tag}\",\"c\":")?;
+                self.open("[")?;
+
+                Context::Inlines
+            }
+            Value::Link => {
+                self.element()?;
+                self.open("{\"t\":\"Link\",\"c\":[[\"\",[],[]],[")?;
+
+                Context::Inlines
+            }
+            Value::Image => {
+                self.element()?;
+                self.open("{\"t\":\"Image\",\"c\":[[\"\",[],[]],[")?;
+
+                Context::Inlines
+            }
+            Value::Strikethrough => {
+                self.element()?;
+                self.open("{\"t\":\"Strikeout\",\"c\":[")?;
+
+                Context::Inlines
+            }
+            Value::Emoji => {
+                self.str(node.as_str(self.sources))?;
+
+                Context::Skipped
+            }
+            Value::HardBreak => {
+                self.element()?;
+                self.out.write_str("{\"t\":\"LineBreak\"}")?;
+
+                Context::Skipped
+            }
+            Value::SoftBreak => {
+                self.element()?;
+                self.out.write_str("{\"t\":\"SoftBreak\"}")?;
+
+                Context::Skipped
+            }
+            Value::EscapeChar(ch) => {
+                self.str(ch.encode_utf8(&mut [0; 4]))?;
+
+                Context::Skipped
+            }
+            Value::Text => {
+                self.text(node.as_str(self.sources))?;
+
+                Context::Skipped
+            }
+        };
+
+        Ok(inner)
+    }
+
+    // writes text as pandoc `Str`s, separated by `Space`s and `SoftBreak`s
+    fn text(&mut self, text: &str) -> fmt::Result {
+        let mut rest = text.trim_matches('\n');
+
+        while let Some(first) = rest.chars().next() {
+            let space = first.is_ascii_whitespace();
+            let end = rest
+                .find(|ch: char| ch.is_ascii_whitespace() != space)
+                .unwrap_or(rest.len());
+            let (run, next) = rest.split_at(end);
+
+            if !space {
+                self.str(run)?;
+            } else if run.contains('\n') {
+                self.element()?;
+                self.out.write_str("{\"t\":\"SoftBreak\"}")?;
+            } else {
+                self.element()?;
+                self.out.write_str("{\"t\":\"Space\"}")?;
+            }
+
+            rest = next;
+        }
+
+        Ok(())
+    }
+
+    fn str(&mut self, text: &str) -> fmt::Result {
+        self.element()?;
+        self.out.write_str("{\"t\":\"Str\",\"c\":")?;
+        write_string(&mut self.out, text)?;
+        self.out.write_char('}')
+    }
+}
+
+impl<W: Write, S: Sources + ?Sized> NodeWriter for PandocWriter<'_, W, S> {
+    fn enter_node(&mut self, id: NodeId, node: &AstNode, _: usize) -> Result<Control, fmt::Error> {
+        let context = self.start_node(id, node)?;
+        self.contexts.push(context);
+
+        Ok(Control::Continue)
+    }
+
+    fn exit_node(&mut self, _: NodeId, node: &AstNode, _: usize) -> fmt::Result {
+        self.contexts.pop();
+
+        let context = self.context();
+
+        if context == Context::Skipped {
+            return Ok(());
+        }
+
+        match &node.value {
+            Value::Macro { .. } | Value::Heading { .. } | Value::OrderedList { .. } => {
+                self.close("]]}")?;
+            }
+            Value::Paragraph
+            | Value::Blockquote
+            | Value::BulletList { .. }
+            | Value::Emphasis { .. }
+            | Value::Strikethrough => self.close("]}")?,
+            Value::ListItem => self.close("]")?,
+            Value::Link | Value::Image => self.close("],[\"\",\"\"]]}")?,
+
+            _ => (),
+        }
+
+        match (context, node.value.is_inline()) {
+            (Context::List, _) if node.value != Value::ListItem => self.close("]"),
+            (Context::Blocks, true) => self.close("]}"),
+
+            _ => Ok(()),
+        }
+    }
+}
+
+// whether `value` is a pandoc inline
+// writes `text` as a JSON string
+fn write_string<W: Write>(out: &mut W, text: &str) -> fmt::Result {
+    out.write_char('"')?;
+    let mut last = 0;
+
+    for (ix, byte) in text.bytes().enumerate() {
+        let replacement = match byte {
+            b'"' => "\\\"",
+            b'\\' => "\\\\",
+            b'\n' => "\\n",
+            b'\r' => "\\r",
+            b'\t' => "\\t",
+            0..0x20 => "",
+
+            _ => continue,
+        };
+
+        out.write_str(&text[last..ix])?;
+        if replacement.is_empty() {
+            write!(out, "\\u{byte:04x}")?;
+        } else {
+            out.write_str(replacement)?;
+        }
+        last = ix + 1;
+    }
+
+    out.write_str(&text[last..])?;
+    out.write_char('"')
+}
+
+fn write_optional<W: Write>(out: &mut W, text: Option<&str>) -> fmt::Result {
+    match text {
+        Some(text) => write_string(out, text),
+        None => out.write_str("null"),
+    }
+}
+
+/// Maximum nesting of arrays and objects when reading,
+/// keeping malicious input from exhausting the stack.
+const MAX_NESTING: usize = 512;
+
+/// A parsed JSON value, along with where it starts.
+struct Json {
+    offset: usize,
+    kind: JsonKind,
+}
+
+enum JsonKind {
+    Null,
+    Bool(bool),
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn error<T>(&self, message: &'static str) -> Result<T, JsonError> {
+        Err(JsonError {
+            offset: self.offset,
+            message,
+        })
+    }
+
+    fn object(&self) -> Result<&[(String, Json)], JsonError> {
+        match &self.kind {
+            JsonKind::Object(fields) => Ok(fields),
+            _ => self.error("expected an object"),
+        }
+    }
+
+    fn array(&self) -> Result<&[Json], JsonError> {
+        match &self.kind {
+            JsonKind::Array(items) => Ok(items),
+            _ => self.error("expected an array"),
+        }
+    }
+
+    fn string(&self) -> Result<&str, JsonError> {
+        match &self.kind {
+            JsonKind::String(text) => Ok(text),
+            _ => self.error("expected a string"),
+        }
+    }
+
+    fn number(&self) -> Result<u64, JsonError> {
+        match self.kind {
+            JsonKind::Number(number) => Ok(number),
+            _ => self.error("expected a number"),
+        }
+    }
+
+    fn bool(&self) -> Result<bool, JsonError> {
+        match self.kind {
+            JsonKind::Bool(value) => Ok(value),
+            _ => self.error("expected a boolean"),
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        matches!(self.kind, JsonKind::Null)
+    }
+
+    // looks up a field of the object `fields`, which is `self`
+    fn get<'j>(fields: &'j [(String, Json)], name: &str) -> Option<&'j Json> {
+        fields
+            .iter()
+            .find_map(|(key, value)| (key == name).then_some(value))
+    }
+
+    fn field<'j>(&self, fields: &'j [(String, Json)], name: &str) -> Result<&'j Json, JsonError> {
+        match Self::get(fields, name) {
+            Some(value) => Ok(value),
+            None => self.error("missing field"),
+        }
+    }
+}
+
+// attaches the node serialized in `json` and its descendants
+// as the next sibling at the cursor of `tree`
+fn attach(tree: &mut TreeArena<AstNode>, json: &Json) -> Result<(), JsonError> {
+    let fields = json.object()?;
+    let attribute = |name| json.field(fields, name);
+
+    let value = match json.field(fields, "type")?.string()? {
+        "root" => Value::Root,
+        "macro" => Value::Macro {
+            name: attribute("name")?.string()?.into(),
+        },
+        "paragraph" => Value::Paragraph,
+        "blockquote" => Value::Blockquote,
+        "html-block" => Value::HtmlBlock,
+        "style-break" => Value::StyleBreak,
+        "heading" => {
+            let level = attribute("level")?;
+
+            match u8::try_from(level.number()?)
+                .ok()
+                .filter(|level| (1..=6).contains(level))
+            {
+                Some(level) => Value::Heading {
+                    level: NonZero::new(level).unwrap_or(NonZero::<u8>::MIN),
+                },
+                None => return level.error("heading level out of range"),
+            }
+        }
+        "bullet-list" => Value::BulletList {
+            tight: attribute("tight")?.bool()?,
+        },
+        "ordered-list" => Value::OrderedList {
+            tight: attribute("tight")?.bool()?,
+            start_index: attribute("start")?.number()?,
+        },
+        "list-item" => Value::ListItem,
+        "code" => {
+            let optional = |name| -> Result<Option<&str>, JsonError> {
+                match Json::get(fields, name) {
+                    Some(value) if !value.is_null() => value.string().map(Some),
+                    _ => Ok(None),
+                }
+            };
+
+            let lang = Lang::recognize(optional("lang")?.unwrap_or_default());
+
+            match optional("info")? {
+                Some(info) => Value::Code {
+                    lang: None,
+                    meta: Some(CodeMeta::new(lang, info.to_string())),
+                },
+                None => Value::Code {
+                    lang: Some(lang),
+                    meta: None,
+                },
+            }
+        }
+        "emphasis" => Value::Emphasis {
+            strong: attribute("strong")?.bool()?,
+        },
+        "link" => Value::Link,
+        "image" => Value::Image,
+        "strikethrough" => Value::Strikethrough,
+        "emoji" => Value::Emoji,
+        "hard-break" => Value::HardBreak,
+        "soft-break" => Value::SoftBreak,
+        "escape-char" => {
+            let ch = attribute("char")?;
+            let mut chars = ch.string()?.chars();
+
+            match (chars.next(), chars.next()) {
+                (Some(ch), None) => Value::EscapeChar(ch),
+                _ => return ch.error("expected a single character"),
+            }
+        }
+        "text" => Value::Text,
+
+        _ => return json.error("unknown node type"),
+    };
+
+    let pos = match Json::get(fields, "position") {
+        Some(position) if !position.is_null() => {
+            let position_fields = position.object()?;
+            let start = position.field(position_fields, "start")?.number()?;
+            let end = position.field(position_fields, "end")?.number()?;
+            let source = match Json::get(position_fields, "source") {
+                Some(source) => match u32::try_from(source.number()?) {
+                    Ok(index) => SourceId::new(index),
+                    Err(_) => return source.error("source out of range"),
+                },
+                None => SourceId::MAIN,
+            };
+
+            match (usize::try_from(start), usize::try_from(end)) {
+                (Ok(start), Ok(end)) if start <= end => {
+                    Position::new(start, end).with_source(source)
+                }
+                _ => return position.error("invalid position"),
+            }
+        }
+        _ => Position::generated(),
+    };
+
+    let mut node = AstNode::new(value, pos, 0);
+    if matches!(node.value, Value::Text | Value::HtmlBlock | Value::Emoji) {
+        node.text = TextSource::from(attribute("text")?.string()?);
+    }
+
+    if tree.attach_node(node).is_err() {
+        return json.error("too many nodes");
+    }
+
+    if let Some(children) = Json::get(fields, "children") {
+        let children = children.array()?;
+
+        if !children.is_empty() {
+            tree.go_down();
+
+            for child in children {
+                attach(tree, child)?;
+            }
+
+            tree.go_up();
+        }
+    }
+
+    Ok(())
+}
+
+struct JsonParser<'s> {
+    src: &'s str,
+    ix: usize,
+}
+
+impl JsonParser<'_> {
+    fn error<T>(&self, message: &'static str) -> Result<T, JsonError> {
+        Err(JsonError {
+            offset: self.ix,
+            message,
+        })
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.ix).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        self.ix += usize::from(found);
+
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .peek()
+            .is_some_and(|byte| matches!(byte, b' ' | b'\t' | b'\n' | b'\r'))
+        {
+            self.ix += 1;
+        }
+    }
+
+    fn run(mut self) -> Result<Json, JsonError> {
+        let value = self.value(0)?;
+        self.skip_whitespace();
+
+        if self.peek().is_some() {
+            return self.error("trailing characters");
+        }
+
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        let offset = self.ix;
+
+        let kind = match self.peek() {
+            Some(b'{') | Some(b'[') if depth >= MAX_NESTING => {
+                return self.error("nested too deeply");
+            }
+            Some(b'{') => {
+                self.ix += 1;
+                let mut fields = Vec::new();
+
+                self.skip_whitespace();
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+
+                        self.skip_whitespace();
+                        if !self.eat(b':') {
+                            return self.error("expected `:`");
+                        }
+
+                        fields.push((key, self.value(depth + 1)?));
+
+                        self.skip_whitespace();
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        if !self.eat(b',') {
+                            return self.error("expected `,` or `}`");
+                        }
+                    }
+                }
+
+                JsonKind::Object(fields)
+            }
+            Some(b'[') => {
+                self.ix += 1;
+                let mut items = Vec::new();
+
+                self.skip_whitespace();
+                if !self.eat(b']') {
+                    loop {
+                        items.push(self.value(depth + 1)?);
+
+                        self.skip_whitespace();
+                        if self.eat(b']') {
+                            break;
+                        }
+                        if !self.eat(b',') {
+                            return self.error("expected `,` or `]`");
+                        }
+                    }
+                }
+
+                JsonKind::Array(items)
+            }
+            Some(b'"') => JsonKind::String(self.string()?),
+            Some(b'0'..=b'9') => JsonKind::Number(self.number()?),
+            Some(b'-') => return self.error("negative numbers are not supported"),
+            _ => {
+                let rest = &self.src[self.ix..];
+                let (kind, len) = if rest.starts_with("null") {
+                    (JsonKind::Null, 4)
+                } else if rest.starts_with("true") {
+                    (JsonKind::Bool(true), 4)
+                } else if rest.starts_with("false") {
+                    (JsonKind::Bool(false), 5)
+                } else {
+                    return self.error("expected a value");
+                };
+
+                self.ix += len;
+                kind
+            }
+        };
+
+        Ok(Json { offset, kind })
+    }
+
+    fn number(&mut self) -> Result<u64, JsonError> {
+        let start = self.ix;
+        let mut number: u64 = 0;
+
+        while let Some(digit @ b'0'..=b'9') = self.peek() {
+            number = match number
+                .checked_mul(10)
+                .and_then(|number| number.checked_add(u64::from(digit - b'0')))
+            {
+                Some(number) => number,
+                None => return self.error("number out of range"),
+            };
+
+            self.ix += 1;
+        }
+
+        if self.ix - start > 1 && self.src.as_bytes()[start] == b'0' {
+            self.ix = start;
+            return self.error("leading zeros are not allowed");
+        }
+
+        if matches!(self.peek(), Some(b'.' | b'e' | b'E')) {
+            return self.error("only integers are supported");
+        }
+
+        Ok(number)
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        if !self.eat(b'"') {
+            return self.error("expected a string");
+        }
+
+        let mut text = String::new();
+
+        loop {
+            // copies over everything up to the next quote or escape
+            let rest = &self.src[self.ix..];
+            let end = rest
+                .find(|ch: char| ch == '"' || ch == '\\' || ch < ' ')
+                .unwrap_or(rest.len());
+            text.push_str(&rest[..end]);
+            self.ix += end;
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.ix += 1;
+                    return Ok(text);
+                }
+                Some(b'\\') => {
+                    self.ix += 1;
+                    let Some(byte) = self.peek() else {
+                        return self.error("unterminated string");
+                    };
+
+                    let escaped = match byte {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            self.ix += 1;
+                            text.push(self.unicode_escape()?);
+                            continue;
+                        }
+
+                        _ => return self.error("invalid escape"),
+                    };
+
+                    self.ix += 1;
+                    text.push(escaped);
+                }
+                Some(_) => return self.error("control character in string"),
+                None => return self.error("unterminated string"),
+            }
+        }
+    }
+
+    // reads the hex digits after `\u`, along with
+    // the low surrogate if they're a high surrogate
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.hex()?;
+
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !(self.eat(b'\\') && self.eat(b'u')) {
+                return self.error("expected a low surrogate");
+            }
+
+            let low = self.hex()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return self.error("expected a low surrogate");
+            }
+
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+
+        match char::from_u32(code) {
+            Some(ch) => Ok(ch),
+            None => self.error("invalid unicode escape"),
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, JsonError> {
+        let digits = self.src.get(self.ix..self.ix + 4).unwrap_or_default();
+
+        match u32::from_str_radix(digits, 16) {
+            Ok(code) if digits.bytes().all(|byte| byte.is_ascii_hexdigit()) => {
+                self.ix += 4;
+                Ok(code)
+            }
+            _ => self.error("invalid unicode escape"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JsonError, from_json, push_json, push_pandoc_json};
+    use crate::ast::{AstNode, CodeMeta, Lang, Value};
+    use crate::block_parser::parse;
+    use crate::lib::{String, ToString};
+    use crate::render::tests::code_block;
+    use crate::tree::TreeArena;
+
+    fn json(src: &str) -> String {
+        let mut out = String::new();
+        push_json(&mut out, &parse(src), src);
+
+        out
+    }
+
+    #[test]
+    fn schema() {
+        assert_eq!(
+            json("## Salt \"water\"\n"),
+            concat!(
+                r#"{"type":"document","version":1,"children":["#,
+                r#"{"type":"heading","level":2,"position":{"start":0,"end":15},"children":["#,
+                r#"{"type":"text","text":"Salt \"water\"","position":{"start":3,"end":15},"children":[]}"#,
+                "]}]}",
+            )
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let src = "# Salt\u{1F30A}\tsea\n\n3. water\n4. salt\n\n<>= note (x) (\n> Salt\n\n)\n";
+        let serialized = json(src);
+        let tree = from_json(&serialized).unwrap();
+
+        let mut out = String::new();
+        push_json(&mut out, &tree, "");
+        assert_eq!(out, serialized);
+
+        // the text is carried by the nodes themselves
+        let text = tree
+            .nodes()
+            .map(|id| &tree.get(id).unwrap().data)
+            .find(|node| node.value == Value::Text)
+            .unwrap();
+        assert_eq!(text.as_str(""), "Salt\u{1F30A}\tsea");
+    }
+
+    #[test]
+    fn generated_nodes() {
+        let mut tree = TreeArena::new();
+        let code = code_block(
+            &mut tree,
+            None,
+            Some(CodeMeta::new(Lang::Rust, "ignore".to_string())),
+            "let x = 1;\n",
+        );
+        tree.insert_after(code, AstNode::with_text(Value::EscapeChar('*'), ""))
+            .unwrap();
+
+        let mut out = String::new();
+        push_json(&mut out, &tree, "");
+        assert_eq!(
+            out,
+            concat!(
+                r#"{"type":"document","version":1,"children":["#,
+                r#"{"type":"code","lang":"rust","info":"ignore","position":null,"children":["#,
+                r#"{"type":"text","text":"let x = 1;\n","position":null,"children":[]}]},"#,
+                r#"{"type":"escape-char","char":"*","position":null,"children":[]}]}"#,
+            )
+        );
+
+        let mut again = String::new();
+        push_json(&mut again, &from_json(&out).unwrap(), "");
+        assert_eq!(again, out);
+    }
+
+    #[test]
+    fn reading() {
+        // fields in any order, unknown fields and escapes
+        let tree = from_json(
+            r#" {"children": [{"children": [], "text": "é🌊\/", "extra": [1, {}],
+                "type": "text"}], "version": 1, "type": "document"} "#,
+        )
+        .unwrap();
+        let text = &tree.get(tree.root().unwrap()).unwrap().data;
+        assert_eq!(text.as_str(""), "\u{e9}\u{1F30A}/");
+        assert!(text.pos.is_generated());
+
+        let error = |json: &str| from_json(json).unwrap_err();
+        let document = |children: &str| {
+            [
+                "{\"type\":\"document\",\"version\":1,\"children\":[",
+                children,
+                "]}",
+            ]
+            .concat()
+        };
+
+        assert_eq!(
+            error("{\"type\":\"document\",\"version\":1,"),
+            JsonError {
+                offset: 31,
+                message: "expected a string"
+            }
+        );
+        assert_eq!(error("[1.5]").message, "only integers are supported");
+        assert_eq!(
+            error(&document("{\"type\":\"table\"}")).message,
+            "unknown node type"
+        );
+        assert_eq!(
+            error(&document("{\"type\":\"heading\",\"level\":7}")).message,
+            "heading level out of range"
+        );
+        assert_eq!(
+            error(&document("{\"type\":\"macro\"}")).message,
+            "missing field"
+        );
+        assert_eq!(
+            error(&document("{\"type\":\"text\",\"position\":null}")).message,
+            "missing field"
+        );
+        assert_eq!(error(&"[".repeat(100_000)).message, "nested too deeply");
+    }
+
+    #[test]
+    fn pandoc() {
+        let pandoc = |src: &str| {
+            let mut out = String::new();
+            push_pandoc_json(&mut out, &parse(src), src);
+
+            out
+        };
+
+        assert_eq!(
+            pandoc("# Salt water\n\nThe sea\nis salty.\n\n- one\n- two\n"),
+            concat!(
+                r#"{"pandoc-api-version":[1,23,1],"meta":{},"blocks":["#,
+                r#"{"t":"Header","c":[1,["",[],[]],[{"t":"Str","c":"Salt"},{"t":"Space"},{"t":"Str","c":"water"}]]},"#,
+                r#"{"t":"Para","c":[{"t":"Str","c":"The"},{"t":"Space"},{"t":"Str","c":"sea"},"#,
+                r#"{"t":"SoftBreak"},{"t":"Str","c":"is"},{"t":"Space"},{"t":"Str","c":"salty."}]},"#,
+                r#"{"t":"BulletList","c":[[{"t":"Plain","c":[{"t":"Str","c":"one"}]}],[{"t":"Plain","c":[{"t":"Str","c":"two"}]}]]}"#,
+                "]}",
+            )
+        );
+
+        // items of loose lists keep their paragraphs
+        assert_eq!(
+            pandoc("- one\n- two\n"),
+            concat!(
+                r#"{"pandoc-api-version":[1,23,1],"meta":{},"blocks":["#,
+                r#"{"t":"BulletList","c":[[{"t":"Plain","c":[{"t":"Str","c":"one"}]}],[{"t":"Plain","c":[{"t":"Str","c":"two"}]}]]}"#,
+                "]}",
+            )
+        );
+        assert_eq!(
+            pandoc("- one\n\n- two\n"),
+            concat!(
+                r#"{"pandoc-api-version":[1,23,1],"meta":{},"blocks":["#,
+                r#"{"t":"BulletList","c":[[{"t":"Para","c":[{"t":"Str","c":"one"}]}],[{"t":"Para","c":[{"t":"Str","c":"two"}]}]]}"#,
+                "]}",
+            )
+        );
+
+        assert_eq!(
+            pandoc("3) one\n4) two\n"),
+            concat!(
+                r#"{"pandoc-api-version":[1,23,1],"meta":{},"blocks":["#,
+                r#"{"t":"OrderedList","c":[[3,{"t":"Decimal"},{"t":"OneParen"}],"#,
+                r#"[[{"t":"Plain","c":[{"t":"Str","c":"one"}]}],[{"t":"Plain","c":[{"t":"Str","c":"two"}]}]]]}"#,
+                "]}",
+            )
+        );
+        assert!(pandoc("1. one\n").contains(r#"[1,{"t":"Decimal"},{"t":"Period"}]"#));
+
+        assert_eq!(
+            pandoc("<>= note (x) (\nSalt.\n\n)"),
+            concat!(
+                r#"{"pandoc-api-version":[1,23,1],"meta":{},"blocks":["#,
+                r#"{"t":"Div","c":[["",[],[["macro","note"]]],["#,
+                r#"{"t":"Para","c":[{"t":"Str","c":"Salt."}]}]]}"#,
+                "]}",
+            )
+        );
+    }
+}
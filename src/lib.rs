@@ -9,9 +9,17 @@ pub(crate) mod lib {
     pub use alloc::vec::Vec;
 }
 
-mod ast;
 mod scan;
-mod tree;
 
+pub mod ast;
 pub mod block_parser;
+pub mod diagnostic;
+pub mod document;
+pub mod events;
+pub mod json;
+pub mod query;
+pub mod render;
+pub mod source;
+pub mod syntax;
+pub mod tree;
 pub mod walker;
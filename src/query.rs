@@ -0,0 +1,569 @@
+//! CSS-selector-like queries over a parsed document.
+//!
+//! ```text
+//! heading[level=2] link        links anywhere inside level 2 headings
+//! blockquote > paragraph       paragraphs directly inside blockquotes
+//! paragraph:first              first paragraph among its siblings
+//! ordered-list > list-item:nth(3), code[lang=rust]
+//! ```
+//!
+//! A selector is made out of compounds joined by combinators,
+//! whitespace for "descendant of" and `>` for "child of".
+//! A compound is a node kind (as returned by `Value::name`) or `*`,
+//! followed by any amount of attributes and pseudo-classes:
+//!
+//! - `[level=N]` heading level,
+//! - `[tight]`, `[tight=false]` list tightness,
+//! - `[start=N]` start index of an ordered list,
+//! - `[lang=NAME]` language of code,
+//! - `[strong]`, `[strong=false]` strength of an emphasis,
+//! - `[name=NAME]` name of a macro,
+//! - `:first` first node of its kind among its siblings,
+//! - `:nth(N)` N-th node of its kind among its siblings, counted from 1.
+//!
+//! Several selectors can be separated by `,`,
+//! a node matches if it matches any of them.
+
+use crate::ast::{AstNode, Value};
+use crate::lib::{Box, Vec};
+use crate::tree::{Descendants, NodeId, TreeArena};
+use core::fmt;
+
+/// Error returned when a query fails to parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    /// Byte offset into the query at which the error happened.
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl core::error::Error for QueryError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Attribute {
+    Level(u8),
+    Tight(bool),
+    Start(u64),
+    Lang(Box<str>),
+    Strong(bool),
+    Name(Box<str>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Compound {
+    /// How this compound relates to the previous one.
+    combinator: Combinator,
+
+    /// Kind of the node, `None` stands for `*`.
+    kind: Option<&'static str>,
+
+    attributes: Vec<Attribute>,
+
+    /// Position among the siblings of the same kind, counted from 1.
+    nth: Option<usize>,
+}
+
+/// A compiled query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    selectors: Vec<Vec<Compound>>,
+}
+
+impl Query {
+    /// Compiles `src` into a `Query`.
+    ///
+    /// # Errors
+    /// if `src` isn't a valid query.
+    pub fn parse(src: &str) -> Result<Self, QueryError> {
+        QueryParser { src, ix: 0 }.run()
+    }
+
+    /// Checks if the node `id` matches this query.
+    pub fn is_match(&self, tree: &TreeArena<AstNode>, id: NodeId) -> bool {
+        self.selectors
+            .iter()
+            .any(|selector| Self::matches_from(tree, id, selector))
+    }
+
+    /// Returns an iterator over the nodes matching this query, in preorder.
+    pub fn find<'t, 'q>(&'q self, tree: &'t TreeArena<AstNode>) -> Matches<'t, 'q> {
+        Matches {
+            tree,
+            query: self,
+            nodes: tree.nodes(),
+        }
+    }
+
+    /// Returns the first node matching this query.
+    pub fn first(&self, tree: &TreeArena<AstNode>) -> Option<NodeId> {
+        self.find(tree).next()
+    }
+
+    // matches `id` against the last compound, and its ancestors against the rest
+    fn matches_from(tree: &TreeArena<AstNode>, id: NodeId, compounds: &[Compound]) -> bool {
+        let Some((last, rest)) = compounds.split_last() else {
+            return true;
+        };
+
+        if !last.matches(tree, id) {
+            return false;
+        }
+
+        if rest.is_empty() {
+            return true;
+        }
+
+        match last.combinator {
+            Combinator::Child => tree
+                .parent(id)
+                .is_some_and(|parent| Self::matches_from(tree, parent, rest)),
+            Combinator::Descendant => tree
+                .ancestors(id)
+                .any(|ancestor| Self::matches_from(tree, ancestor, rest)),
+        }
+    }
+}
+
+impl Compound {
+    fn matches(&self, tree: &TreeArena<AstNode>, id: NodeId) -> bool {
+        let Some(node) = tree.get(id) else {
+            return false;
+        };
+
+        let value = &node.data.value;
+
+        if self.kind.is_some_and(|kind| kind != value.name()) {
+            return false;
+        }
+
+        if !self.attributes.iter().all(|attr| attr.matches(value)) {
+            return false;
+        }
+
+        match self.nth {
+            None => true,
+            Some(nth) => {
+                let position = 1 + tree
+                    .preceding_siblings(id)
+                    .filter(|&sibling| {
+                        tree.get(sibling).is_some_and(|sibling| {
+                            self.kind
+                                .is_none_or(|kind| kind == sibling.data.value.name())
+                        })
+                    })
+                    .count();
+
+                position == nth
+            }
+        }
+    }
+}
+
+impl Attribute {
+    fn matches(&self, value: &Value) -> bool {
+        match (self, value) {
+            (Self::Level(expected), Value::Heading { level }) => level.get() == *expected,
+            (
+                Self::Tight(expected),
+                Value::BulletList { tight } | Value::OrderedList { tight, .. },
+            ) => tight == expected,
+            (Self::Start(expected), Value::OrderedList { start_index, .. }) => {
+                start_index == expected
+            }
+            (Self::Lang(expected), Value::Code { .. }) => value
+                .lang()
+                .and_then(|lang| lang.name())
+                .is_some_and(|name| name == &**expected),
+            (Self::Strong(expected), Value::Emphasis { strong }) => strong == expected,
+            (Self::Name(expected), Value::Macro { name }) => name == expected,
+
+            _ => false,
+        }
+    }
+}
+
+/// Iterator over the nodes matching a `Query`.
+///
+/// Created by `Query::find`.
+pub struct Matches<'t, 'q> {
+    tree: &'t TreeArena<AstNode>,
+    query: &'q Query,
+    nodes: Descendants<'t, AstNode>,
+}
+
+impl Iterator for Matches<'_, '_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (tree, query) = (self.tree, self.query);
+
+        self.nodes.find(|&id| query.is_match(tree, id))
+    }
+}
+
+struct QueryParser<'s> {
+    src: &'s str,
+    ix: usize,
+}
+
+impl<'s> QueryParser<'s> {
+    fn error<T>(&self, message: &'static str) -> Result<T, QueryError> {
+        Err(QueryError {
+            offset: self.ix,
+            message,
+        })
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.ix).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        self.ix += usize::from(found);
+
+        found
+    }
+
+    // skips whitespace, returns whether there was any
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.ix;
+
+        while self.peek().is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.ix += 1;
+        }
+
+        self.ix != start
+    }
+
+    // consumes bytes while `pred` holds
+    fn take_while(&mut self, pred: impl Fn(u8) -> bool) -> &'s str {
+        let start = self.ix;
+
+        while self.peek().is_some_and(&pred) {
+            self.ix += 1;
+        }
+
+        let src = self.src;
+        &src[start..self.ix]
+    }
+
+    fn run(mut self) -> Result<Query, QueryError> {
+        let mut selectors = Vec::new();
+
+        loop {
+            self.skip_whitespace();
+            selectors.push(self.selector()?);
+
+            if self.peek().is_none() {
+                break;
+            }
+
+            if !self.eat(b',') {
+                return self.error("expected `,`");
+            }
+        }
+
+        Ok(Query { selectors })
+    }
+
+    fn selector(&mut self) -> Result<Vec<Compound>, QueryError> {
+        let mut compounds = Vec::new();
+        let mut combinator = Combinator::Descendant;
+
+        loop {
+            compounds.push(self.compound(combinator)?);
+
+            let had_whitespace = self.skip_whitespace();
+
+            combinator = match self.peek() {
+                None | Some(b',') => return Ok(compounds),
+                Some(b'>') => {
+                    self.ix += 1;
+                    self.skip_whitespace();
+
+                    Combinator::Child
+                }
+                Some(_) if had_whitespace => Combinator::Descendant,
+
+                Some(_) => return self.error("unexpected character"),
+            };
+        }
+    }
+
+    fn compound(&mut self, combinator: Combinator) -> Result<Compound, QueryError> {
+        let start = self.ix;
+
+        let kind = if self.eat(b'*') {
+            None
+        } else {
+            let name = self.take_while(|byte| byte.is_ascii_alphanumeric() || byte == b'-');
+
+            match Value::kinds()
+                .iter()
+                .map(Value::name)
+                .find(|&kind| kind == name)
+            {
+                Some(kind) => Some(kind),
+                None if name.is_empty() => None,
+                None => {
+                    self.ix = start;
+                    return self.error("unknown node kind");
+                }
+            }
+        };
+
+        let mut compound = Compound {
+            combinator,
+            kind,
+            attributes: Vec::new(),
+            nth: None,
+        };
+
+        loop {
+            match self.peek() {
+                Some(b'[') => {
+                    self.ix += 1;
+                    let attribute = self.attribute()?;
+                    compound.attributes.push(attribute);
+                }
+                Some(b':') => {
+                    self.ix += 1;
+                    compound.nth = Some(self.pseudo_class()?);
+                }
+
+                _ => break,
+            }
+        }
+
+        if self.ix == start {
+            return self.error("expected a selector");
+        }
+
+        Ok(compound)
+    }
+
+    fn attribute(&mut self) -> Result<Attribute, QueryError> {
+        self.skip_whitespace();
+        let name_start = self.ix;
+        let name = self.take_while(|byte| byte.is_ascii_alphabetic());
+        self.skip_whitespace();
+
+        let value = if self.eat(b'=') {
+            self.skip_whitespace();
+            let quoted = self.eat(b'"');
+            let value = self.take_while(|byte| {
+                !matches!(byte, b']' | b'"') && (quoted || !byte.is_ascii_whitespace())
+            });
+
+            if quoted && !self.eat(b'"') {
+                return self.error("unclosed quote");
+            }
+
+            self.skip_whitespace();
+            Some(value)
+        } else {
+            None
+        };
+
+        let value_offset = self.ix;
+        let attribute = match (name, value) {
+            ("level", Some(value)) => value.parse().ok().map(Attribute::Level),
+            ("tight", value) => parse_bool(value).map(Attribute::Tight),
+            ("start", Some(value)) => value.parse().ok().map(Attribute::Start),
+            ("lang", Some(value)) => Some(Attribute::Lang(value.into())),
+            ("strong", value) => parse_bool(value).map(Attribute::Strong),
+            ("name", Some(value)) => Some(Attribute::Name(value.into())),
+
+            ("level" | "start" | "lang" | "name", None) => {
+                return self.error("attribute requires a value");
+            }
+            _ => {
+                self.ix = name_start;
+                return self.error("unknown attribute");
+            }
+        };
+
+        let Some(attribute) = attribute else {
+            self.ix = value_offset;
+            return self.error("invalid attribute value");
+        };
+
+        if !self.eat(b']') {
+            return self.error("expected `]`");
+        }
+
+        Ok(attribute)
+    }
+
+    fn pseudo_class(&mut self) -> Result<usize, QueryError> {
+        let start = self.ix;
+
+        match self.take_while(|byte| byte.is_ascii_alphabetic()) {
+            "first" => Ok(1),
+            "nth" => {
+                if !self.eat(b'(') {
+                    return self.error("expected `(`");
+                }
+
+                let digits_start = self.ix;
+                let nth = self
+                    .take_while(|byte| byte.is_ascii_digit())
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|nth| *nth > 0);
+
+                let Some(nth) = nth else {
+                    self.ix = digits_start;
+                    return self.error("expected a number larger than 0");
+                };
+
+                if !self.eat(b')') {
+                    return self.error("expected `)`");
+                }
+
+                Ok(nth)
+            }
+
+            _ => {
+                self.ix = start;
+                self.error("unknown pseudo-class")
+            }
+        }
+    }
+}
+
+fn parse_bool(value: Option<&str>) -> Option<bool> {
+    match value {
+        None | Some("true") => Some(true),
+        Some("false") => Some(false),
+
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Query, QueryError};
+    use crate::ast::{AstNode, Value};
+    use crate::block_parser::parse;
+    use crate::lib::Vec;
+    use crate::tree::{NodeId, TreeArena};
+
+    fn names(tree: &TreeArena<AstNode>, query: &str) -> Vec<&'static str> {
+        let query = Query::parse(query).unwrap();
+
+        query
+            .find(tree)
+            .map(|id: NodeId| tree.get(id).unwrap().data.value.name())
+            .collect()
+    }
+
+    fn texts<'s>(tree: &'s TreeArena<AstNode>, src: &'s str, query: &str) -> Vec<&'s str> {
+        let query = Query::parse(query).unwrap();
+
+        query
+            .find(tree)
+            .map(|id| tree.get(id).unwrap().data.as_str(src))
+            .collect()
+    }
+
+    #[test]
+    fn kinds_and_attributes() {
+        let src = "# One\n\nSalt.\n\n## Two\n\nWater.";
+        let tree = parse(src);
+
+        assert_eq!(texts(&tree, src, "heading[level=2] > text"), ["Two"]);
+        assert_eq!(texts(&tree, src, "heading text"), ["One", "Two"]);
+        assert_eq!(names(&tree, "*[level=1]"), ["heading"]);
+        assert_eq!(names(&tree, "bullet-list"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn every_kind() {
+        let kinds = Value::kinds();
+
+        for (ix, kind) in kinds.iter().enumerate() {
+            assert!(Query::parse(kind.name()).is_ok());
+            assert!(kinds[..ix].iter().all(|other| other.name() != kind.name()));
+        }
+    }
+
+    #[test]
+    fn combinators_and_pseudo_classes() {
+        let src = "1. One\n2. Two\n3. Three\n";
+        let tree = parse(src);
+
+        assert_eq!(
+            texts(&tree, src, "ordered-list > list-item:first text"),
+            ["One\n"]
+        );
+        assert_eq!(
+            texts(
+                &tree,
+                src,
+                "ordered-list[start=1] > list-item:nth(3) > paragraph"
+            ),
+            ["Three\n"]
+        );
+        assert_eq!(names(&tree, "ordered-list > paragraph"), Vec::<&str>::new());
+        assert_eq!(
+            names(&tree, "ordered-list[tight=true], list-item:nth(2)"),
+            ["ordered-list", "list-item"]
+        );
+    }
+
+    #[test]
+    fn is_match() {
+        let src = "> Salt water.";
+        let tree = parse(src);
+        let query = Query::parse("blockquote text").unwrap();
+
+        let text = tree
+            .nodes()
+            .find(|&id| matches!(tree.get(id).unwrap().data.value, Value::Text))
+            .unwrap();
+
+        assert!(query.is_match(&tree, text));
+        assert!(!query.is_match(&tree, tree.root().unwrap()));
+        assert_eq!(query.first(&tree), Some(text));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |query: &str| Query::parse(query).unwrap_err();
+
+        assert_eq!(
+            error("heading > quote"),
+            QueryError {
+                offset: 10,
+                message: "unknown node kind"
+            }
+        );
+        assert_eq!(error("heading[colour=red]").message, "unknown attribute");
+        assert_eq!(error("heading[level=x]").message, "invalid attribute value");
+        assert_eq!(
+            error("heading[level]").message,
+            "attribute requires a value"
+        );
+        assert_eq!(
+            error("list-item:nth(0)").message,
+            "expected a number larger than 0"
+        );
+        assert_eq!(error("list-item:last").message, "unknown pseudo-class");
+        assert_eq!(error("heading >").message, "expected a selector");
+        assert_eq!(error("").message, "expected a selector");
+    }
+}
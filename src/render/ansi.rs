@@ -0,0 +1,835 @@
+//! Terminal rendering of a parsed document.
+//!
+//! Styles the document with ANSI escape codes for previewing it
+//! in a terminal: headings are bold or underlined by their level,
+//! emphasis is italic, blockquotes get a bar on their left, lists
+//! are numbered from their `start_index` and code blocks are boxed.
+//! Paragraphs are wrapped to the given width, counting the display
+//! width of wide and zero-width characters. Without colours, the
+//! same layout is written as plain text.
+//!
+//! Control characters of the document are replaced by U+FFFD,
+//! so its text can't drive the terminal.
+
+use super::{NodeWriter, children_text, words, write_tree};
+use crate::ast::{AstNode, Value};
+use crate::lib::{String, Vec};
+use crate::source::Sources;
+use crate::tree::{Control, NodeId, TreeArena};
+use core::fmt::{self, Write};
+
+/// Options for rendering to a terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnsiOptions {
+    width: usize,
+    colors: bool,
+}
+
+impl Default for AnsiOptions {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            colors: true,
+        }
+    }
+}
+
+impl AnsiOptions {
+    /// Returns the default options,
+    /// wrapping at 80 columns with colours.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps lines at `width` columns.
+    ///
+    /// Words wider than the remaining space are put on a line of their own.
+    #[must_use]
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Enables or disables escape codes.
+    #[must_use]
+    pub fn colors(mut self, colors: bool) -> Self {
+        self.colors = colors;
+        self
+    }
+}
+
+/// Renders `tree` for a terminal, appending it to `out`.
+pub fn push_ansi<S>(out: &mut String, tree: &TreeArena<AstNode>, sources: &S, options: &AnsiOptions)
+where
+    S: Sources + ?Sized,
+{
+    write_ansi(out, tree, sources, options).expect("writing to a `String` is infallible");
+}
+
+/// Renders `tree` for a terminal into `out`.
+///
+/// # Errors
+/// if writing to `out` fails.
+pub fn write_ansi<W, S>(
+    out: W,
+    tree: &TreeArena<AstNode>,
+    sources: &S,
+    options: &AnsiOptions,
+) -> fmt::Result
+where
+    W: Write,
+    S: Sources + ?Sized,
+{
+    let mut writer = AnsiWriter {
+        out,
+        tree,
+        sources,
+        options: *options,
+        current: Style::PLAIN,
+        containers: Vec::new(),
+        lists: Vec::new(),
+        quotes: 0,
+        blank: false,
+        started: false,
+        styles: Vec::new(),
+        inline: None,
+        words: Vec::new(),
+        buffer: String::new(),
+    };
+
+    write_tree(tree, None, &mut writer)
+}
+
+/// Returns the number of columns `ch` takes up in a terminal.
+///
+/// Covers the common wide scripts and emoji as well as
+/// combining marks, without the full Unicode tables.
+pub(crate) fn char_width(ch: char) -> usize {
+    match u32::from(ch) {
+        // control characters, combining marks, zero-width
+        // spaces and joiners and variation selectors
+        0..0x20
+        | 0x7f..0xa0
+        | 0x300..0x370
+        | 0x1ab0..0x1b00
+        | 0x1dc0..0x1e00
+        | 0x200b..0x2010
+        | 0x20d0..0x2100
+        | 0xfe00..0xfe10
+        | 0xfe20..0xfe30 => 0,
+
+        // hangul jamo, CJK, hangul syllables, fullwidth forms and emoji
+        0x1100..0x1160
+        | 0x2e80..0x303f
+        | 0x3041..0x3400
+        | 0x3400..0x4dc0
+        | 0x4e00..0xa000
+        | 0xa000..0xa4d0
+        | 0xac00..0xd7a4
+        | 0xf900..0xfb00
+        | 0xfe30..0xfe50
+        | 0xff00..0xff61
+        | 0xffe0..0xffe7
+        | 0x1f300..0x1f650
+        | 0x1f900..0x1fa00
+        | 0x20000..0x2fffe
+        | 0x30000..0x3fffe => 2,
+
+        _ => 1,
+    }
+}
+
+/// Returns the number of columns `text` takes up in a terminal.
+pub(crate) fn text_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// Text attributes, set with SGR escape codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Style(u8);
+
+impl Style {
+    const PLAIN: Self = Self(0);
+    const BOLD: Self = Self(1);
+    const DIM: Self = Self(1 << 1);
+    const ITALIC: Self = Self(1 << 2);
+    const UNDERLINE: Self = Self(1 << 3);
+    const STRIKE: Self = Self(1 << 4);
+    const LINK: Self = Self(1 << 5);
+
+    const CODES: [(Self, &'static str); 6] = [
+        (Self::BOLD, "1"),
+        (Self::DIM, "2"),
+        (Self::ITALIC, "3"),
+        (Self::UNDERLINE, "4"),
+        (Self::STRIKE, "9"),
+        (Self::LINK, "34"),
+    ];
+
+    const fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    fn has(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// A block the current lines are nested in.
+struct Container {
+    /// Prefix of the first line, like the marker of a list item.
+    first: String,
+
+    /// Prefix of the following lines.
+    rest: String,
+
+    /// Whether the first line was written.
+    used: bool,
+
+    quote: bool,
+}
+
+/// A piece of text of a paragraph,
+/// which may be moved to the next line.
+struct Word {
+    start: usize,
+    end: usize,
+    style: Style,
+
+    /// Whether whitespace separates it from the previous word.
+    space: bool,
+
+    /// Whether a hard break goes before it.
+    newline: bool,
+}
+
+struct AnsiWriter<'t, W, S: ?Sized> {
+    out: W,
+    tree: &'t TreeArena<AstNode>,
+    sources: &'t S,
+    options: AnsiOptions,
+
+    /// Style set by the last escape code.
+    current: Style,
+
+    containers: Vec<Container>,
+
+    /// Next number and its width for the open lists, `None` for bullet lists.
+    lists: Vec<Option<(u64, usize)>>,
+
+    quotes: usize,
+
+    /// Whether a blank line goes before the next block.
+    blank: bool,
+
+    /// Whether any block was written.
+    started: bool,
+
+    /// Styles of the open inline nodes.
+    styles: Vec<Style>,
+
+    /// Node whose inline content is being collected.
+    inline: Option<NodeId>,
+
+    /// Collected inline content, as ranges of the `buffer`.
+    words: Vec<Word>,
+    buffer: String,
+}
+
+impl<W: Write, S: Sources + ?Sized> AnsiWriter<'_, W, S> {
+    fn item(&mut self) {
+        let marker = match self.lists.last_mut() {
+            Some(Some((number, width))) => {
+                let width = *width;
+                let mut marker = String::new();
+                let _ = write!(marker, "{number:>width$}. ");
+                *number = number.saturating_add(1);
+                marker
+            }
+            Some(None) => {
+                let bullet = ["• ", "◦ ", "▪ "][(self.lists.len() - 1) % 3];
+                String::from(bullet)
+            }
+
+            // items outside of lists get no marker
+            None => String::new(),
+        };
+
+        let rest = " ".repeat(text_width(&marker));
+        self.containers.push(Container {
+            first: marker,
+            rest,
+            used: false,
+            quote: false,
+        });
+    }
+
+    // collects the words of `text`
+    fn words(&mut self, text: &str) {
+        for (word, space) in words(text, self.quotes) {
+            self.word(word, space);
+        }
+    }
+
+    fn word(&mut self, text: &str, space: bool) {
+        let style = self
+            .styles
+            .iter()
+            .fold(Style::PLAIN, |style, &other| style.with(other));
+
+        let start = self.buffer.len();
+        self.buffer.extend(text.chars().map(visible));
+        self.words.push(Word {
+            start,
+            end: self.buffer.len(),
+            style,
+            space,
+            newline: false,
+        });
+    }
+
+    // wraps the collected words into lines
+    fn paragraph(&mut self) -> fmt::Result {
+        let mut words = core::mem::take(&mut self.words);
+        let buffer = core::mem::take(&mut self.buffer);
+
+        // empty words only carry separation over to the next one
+        let mut ix = 0;
+        while ix < words.len() {
+            if words[ix].start == words[ix].end {
+                let removed = words.remove(ix);
+                if let Some(next) = words.get_mut(ix) {
+                    next.space |= removed.space;
+                    next.newline |= removed.newline;
+                }
+            } else {
+                ix += 1;
+            }
+        }
+
+        if words.is_empty() {
+            self.words = words;
+            self.buffer = buffer;
+            return Ok(());
+        }
+
+        self.separate()?;
+        self.prefix()?;
+
+        let available = self.available();
+        let mut column = 0;
+        let mut ix = 0;
+
+        while ix < words.len() {
+            // words without whitespace between them stay together
+            let run_end = (ix + 1..words.len())
+                .find(|&next| words[next].space || words[next].newline)
+                .unwrap_or(words.len());
+            let run_width = words[ix..run_end]
+                .iter()
+                .map(|word| text_width(&buffer[word.start..word.end]))
+                .sum::<usize>();
+
+            let first = &words[ix];
+            let fits = column + usize::from(first.space) + run_width <= available;
+
+            if column > 0 && (first.newline || !fits) {
+                self.end_line()?;
+                self.prefix()?;
+                column = 0;
+            } else if column > 0 && first.space {
+                // spaces inside of links keep their underline
+                let previous = words[ix - 1].style;
+                let style = if previous == first.style {
+                    previous
+                } else {
+                    Style::PLAIN
+                };
+
+                self.set_style(style)?;
+                self.out.write_char(' ')?;
+                column += 1;
+            }
+
+            for word in &words[ix..run_end] {
+                self.set_style(word.style)?;
+                self.out.write_str(&buffer[word.start..word.end])?;
+            }
+
+            column += run_width;
+            ix = run_end;
+        }
+
+        self.end_line()?;
+
+        // keeps the allocations around for the next paragraph
+        words.clear();
+        self.words = words;
+        self.buffer = buffer;
+        self.buffer.clear();
+
+        Ok(())
+    }
+
+    // writes a code block in a box, with its language in the top border
+    fn code(&mut self, id: NodeId, node: &AstNode) -> fmt::Result {
+        let code = children_text(self.tree, id, self.sources);
+
+        let code = code.trim_matches('\n').replace('\t', "    ");
+        let lines = code.split('\n').map(visible_line).collect::<Vec<_>>();
+        let label = node
+            .value
+            .lang()
+            .and_then(|lang| lang.name())
+            .map(visible_line);
+
+        // the box is as wide as its longest line, if it fits
+        let available = self.available().saturating_sub(4).max(1);
+        let longest = lines.iter().map(|line| text_width(line)).max().unwrap_or(0);
+        let label_width = label.as_ref().map_or(0, |label| text_width(label) + 2);
+        let inner = longest.max(label_width).min(available);
+
+        self.separate()?;
+        self.prefix()?;
+        self.set_style(Style::DIM)?;
+
+        match label {
+            Some(label) if label_width <= inner => {
+                write!(self.out, "┌─ {label} ")?;
+                self.out.write_str(&"─".repeat(inner - label_width + 1))?;
+            }
+            _ => {
+                self.out.write_char('┌')?;
+                self.out.write_str(&"─".repeat(inner + 2))?;
+            }
+        }
+        self.out.write_char('┐')?;
+        self.end_line()?;
+
+        for line in &lines {
+            let mut rest = line.as_str();
+
+            // lines too long for the box are broken up
+            loop {
+                let mut width = 0;
+                let cut = rest
+                    .char_indices()
+                    .find(|&(_, ch)| {
+                        width += char_width(ch);
+                        width > inner
+                    })
+                    .map_or(
+                        rest.len(),
+                        |(ix, ch)| if ix == 0 { ch.len_utf8() } else { ix },
+                    );
+                let (piece, after) = rest.split_at(cut);
+
+                self.prefix()?;
+                self.styled(Style::DIM, "│ ")?;
+                self.out.write_str(piece)?;
+                let padding = inner.saturating_sub(text_width(piece));
+                write!(self.out, "{:padding$}", "")?;
+                self.styled(Style::DIM, " │")?;
+                self.end_line()?;
+
+                if after.is_empty() {
+                    break;
+                }
+                rest = after;
+            }
+        }
+
+        self.prefix()?;
+        self.set_style(Style::DIM)?;
+        self.out.write_char('└')?;
+        self.out.write_str(&"─".repeat(inner + 2))?;
+        self.out.write_char('┘')?;
+        self.end_line()
+    }
+
+    // writes a blank line between blocks
+    fn separate(&mut self) -> fmt::Result {
+        if self.started && self.blank {
+            // only the bars of blockquotes are kept
+            let last = self
+                .containers
+                .iter()
+                .rposition(|container| container.quote);
+
+            if let Some(last) = last {
+                for ix in 0..=last {
+                    let container = &self.containers[ix];
+                    if container.quote {
+                        let bar = if ix == last { "│" } else { "│ " };
+                        self.styled(Style::DIM, bar)?;
+                    } else {
+                        let rest = &container.rest;
+                        self.out.write_str(rest)?;
+                    }
+                }
+            }
+
+            self.out.write_char('\n')?;
+        }
+
+        self.started = true;
+        self.blank = self.lists.is_empty();
+        Ok(())
+    }
+
+    fn prefix(&mut self) -> fmt::Result {
+        for ix in 0..self.containers.len() {
+            let container = &mut self.containers[ix];
+            let quote = container.quote;
+            let prefix = if container.used {
+                container.rest.clone()
+            } else {
+                container.used = true;
+                container.first.clone()
+            };
+
+            if quote {
+                self.styled(Style::DIM, &prefix)?;
+            } else {
+                self.set_style(Style::PLAIN)?;
+                self.out.write_str(&prefix)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // returns the columns left after the prefix
+    fn available(&self) -> usize {
+        let prefix = self
+            .containers
+            .iter()
+            .map(|container| text_width(&container.rest))
+            .sum::<usize>();
+
+        self.options.width.saturating_sub(prefix).max(1)
+    }
+
+    fn styled(&mut self, style: Style, text: &str) -> fmt::Result {
+        self.set_style(style)?;
+        self.out.write_str(text)?;
+        self.set_style(Style::PLAIN)
+    }
+
+    fn end_line(&mut self) -> fmt::Result {
+        self.set_style(Style::PLAIN)?;
+        self.out.write_char('\n')
+    }
+
+    fn set_style(&mut self, style: Style) -> fmt::Result {
+        if !self.options.colors || style == self.current {
+            return Ok(());
+        }
+
+        if self.current != Style::PLAIN {
+            self.out.write_str("\x1b[0m")?;
+        }
+
+        if style != Style::PLAIN {
+            self.out.write_str("\x1b[")?;
+
+            let codes = Style::CODES.iter().filter(|(flag, _)| style.has(*flag));
+            for (ix, (_, code)) in codes.enumerate() {
+                if ix > 0 {
+                    self.out.write_char(';')?;
+                }
+                self.out.write_str(code)?;
+            }
+
+            self.out.write_char('m')?;
+        }
+
+        self.current = style;
+        Ok(())
+    }
+}
+
+impl<W: Write, S: Sources + ?Sized> NodeWriter for AnsiWriter<'_, W, S> {
+    fn enter_node(&mut self, id: NodeId, node: &AstNode, _: usize) -> Result<Control, fmt::Error> {
+        // inlines outside of paragraphs are wrapped on their own
+        if self.inline.is_none() && node.value.is_inline() {
+            self.inline = Some(id);
+        }
+
+        match &node.value {
+            Value::Paragraph => self.inline = Some(id),
+            Value::Heading { level } => {
+                self.inline = Some(id);
+                self.styles.push(match level.get() {
+                    1 => Style::BOLD.with(Style::UNDERLINE),
+                    2 => Style::BOLD,
+                    _ => Style::UNDERLINE,
+                });
+            }
+            Value::Blockquote => {
+                self.quotes += 1;
+                self.containers.push(Container {
+                    first: String::from("│ "),
+                    rest: String::from("│ "),
+                    used: false,
+                    quote: true,
+                });
+            }
+            Value::BulletList { .. } => self.lists.push(None),
+            Value::OrderedList { start_index, .. } => {
+                // numbers are right aligned, up to the last one of the list
+                let items = self.tree.children(id).count().saturating_sub(1);
+                let last = start_index.saturating_add(items.try_into().unwrap_or(u64::MAX));
+
+                self.lists.push(Some((*start_index, digits(last))));
+            }
+            Value::ListItem => self.item(),
+            Value::Code { .. } => {
+                self.code(id, node)?;
+                return Ok(Control::SkipChildren);
+            }
+            Value::HtmlBlock => {
+                self.separate()?;
+                for line in node.as_str(self.sources).trim_matches('\n').split('\n') {
+                    let line = visible_line(line);
+                    self.prefix()?;
+                    self.styled(Style::DIM, &line)?;
+                    self.end_line()?;
+                }
+            }
+            Value::StyleBreak => {
+                self.separate()?;
+                self.prefix()?;
+
+                let rule = "─".repeat(self.available());
+                self.styled(Style::DIM, &rule)?;
+                self.end_line()?;
+            }
+
+            Value::Emphasis { strong: true } => self.styles.push(Style::BOLD),
+            Value::Emphasis { strong: false } => self.styles.push(Style::ITALIC),
+            Value::Link | Value::Image => self.styles.push(Style::LINK.with(Style::UNDERLINE)),
+            Value::Strikethrough => self.styles.push(Style::STRIKE),
+            Value::Text | Value::Emoji => self.words(node.as_str(self.sources)),
+            Value::EscapeChar(ch) => self.word(ch.encode_utf8(&mut [0; 4]), false),
+            Value::HardBreak => {
+                self.word("", true);
+                if let Some(word) = self.words.last_mut() {
+                    word.newline = true;
+                }
+            }
+            Value::SoftBreak => self.word("", true),
+
+            Value::Root | Value::Macro { .. } => (),
+        }
+
+        Ok(Control::Continue)
+    }
+
+    fn exit_node(&mut self, id: NodeId, node: &AstNode, _: usize) -> fmt::Result {
+        match &node.value {
+            Value::Emphasis { .. } | Value::Link | Value::Image | Value::Strikethrough => {
+                self.styles.pop();
+            }
+            Value::Blockquote => {
+                self.quotes -= 1;
+                self.containers.pop();
+                self.blank = self.lists.is_empty();
+            }
+            Value::BulletList { .. } | Value::OrderedList { .. } => {
+                self.lists.pop();
+                self.blank = self.lists.is_empty();
+            }
+            Value::ListItem => {
+                self.containers.pop();
+            }
+
+            _ => (),
+        }
+
+        if self.inline == Some(id) {
+            self.inline = None;
+            self.styles.clear();
+            self.paragraph()?;
+        }
+
+        Ok(())
+    }
+}
+
+// replaces a control character, which the terminal
+// would interpret, with U+FFFD
+fn visible(ch: char) -> char {
+    if ch.is_control() {
+        char::REPLACEMENT_CHARACTER
+    } else {
+        ch
+    }
+}
+
+// makes a line of code or html visible, dropping its `\r`
+fn visible_line(line: &str) -> String {
+    line.trim_end_matches('\r').chars().map(visible).collect()
+}
+
+fn digits(number: u64) -> usize {
+    number.checked_ilog10().map_or(1, |log| log as usize + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnsiOptions, push_ansi, text_width};
+    use crate::ast::{AstNode, CodeMeta, Lang, Value};
+    use crate::block_parser::parse;
+    use crate::lib::String;
+    use crate::render::tests::code_block;
+    use crate::tree::TreeArena;
+
+    fn render(src: &str, options: &AnsiOptions) -> String {
+        let mut out = String::new();
+        push_ansi(&mut out, &parse(src), src, options);
+
+        out
+    }
+
+    #[test]
+    fn layout() {
+        let options = AnsiOptions::new().width(16).colors(false);
+
+        assert_eq!(
+            render(
+                "> The sea is salty\nand deep.\n>\n> * Very deep.\n",
+                &options
+            ),
+            concat!(
+                "│ The sea is\n",
+                "│ salty and\n",
+                "│ deep.\n",
+                "│\n",
+                "│ • Very deep.\n",
+            )
+        );
+        assert_eq!(
+            render("9. nine\n10. ten words that wrap\n", &options),
+            " 9. nine\n10. ten words\n    that wrap\n"
+        );
+        assert_eq!(
+            render("8) a\n9) b\n10) c\n", &options),
+            " 8. a\n 9. b\n10. c\n"
+        );
+    }
+
+    #[test]
+    fn styles() {
+        let src = "# Salt\n\nThe sea.\n";
+
+        assert_eq!(
+            render(src, &AnsiOptions::new()),
+            "\x1b[1;4mSalt\x1b[0m\n\nThe sea.\n"
+        );
+        assert_eq!(
+            render(src, &AnsiOptions::new().colors(false)),
+            "Salt\n\nThe sea.\n"
+        );
+
+        let mut tree = TreeArena::new();
+        let paragraph = tree
+            .attach_node(AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::Text, "a "))
+            .unwrap();
+        let emphasis = tree
+            .append_child(
+                paragraph,
+                AstNode::with_text(Value::Emphasis { strong: false }, ""),
+            )
+            .unwrap();
+        tree.append_child(emphasis, AstNode::with_text(Value::Text, "salty"))
+            .unwrap();
+        let link = tree
+            .append_child(paragraph, AstNode::with_text(Value::Link, ""))
+            .unwrap();
+        tree.append_child(link, AstNode::with_text(Value::Text, " sea link"))
+            .unwrap();
+
+        let mut out = String::new();
+        push_ansi(&mut out, &tree, "", &AnsiOptions::new());
+        assert_eq!(out, "a \x1b[3msalty\x1b[0m \x1b[4;34msea link\x1b[0m\n");
+    }
+
+    #[test]
+    fn code_blocks() {
+        let mut tree = TreeArena::new();
+        let lang = Lang::recognize("rust");
+        code_block(
+            &mut tree,
+            None,
+            Some(CodeMeta::new(lang, None)),
+            "fn main() {}\n\tx\n",
+        );
+
+        let mut out = String::new();
+        push_ansi(&mut out, &tree, "", &AnsiOptions::new().colors(false));
+        assert_eq!(
+            out,
+            concat!(
+                "┌─ rust ───────┐\n",
+                "│ fn main() {} │\n",
+                "│     x        │\n",
+                "└──────────────┘\n",
+            )
+        );
+
+        // long lines are broken up to fit the width
+        out.clear();
+        push_ansi(
+            &mut out,
+            &tree,
+            "",
+            &AnsiOptions::new().width(12).colors(false),
+        );
+        assert_eq!(
+            out,
+            concat!(
+                "┌─ rust ───┐\n",
+                "│ fn main( │\n",
+                "│ ) {}     │\n",
+                "│     x    │\n",
+                "└──────────┘\n",
+            )
+        );
+    }
+
+    #[test]
+    fn control_characters() {
+        let options = AnsiOptions::new().colors(false);
+
+        assert_eq!(
+            render("hi \x1b]0;pwned\x07 \x1b[2J", &options),
+            "hi \u{fffd}]0;pwned\u{fffd} \u{fffd}[2J\n"
+        );
+
+        let mut tree = TreeArena::new();
+        code_block(&mut tree, None, None, "\x1b[2J\r\n");
+
+        let mut out = String::new();
+        push_ansi(&mut out, &tree, "", &options);
+        assert!(!out.contains(['\x1b', '\r']));
+        assert_eq!(out, "┌──────┐\n│ \u{fffd}[2J │\n└──────┘\n");
+    }
+
+    #[test]
+    fn unicode_width() {
+        assert_eq!(text_width("salt"), 4);
+        assert_eq!(text_width("塩水"), 4);
+        assert_eq!(text_width("e\u{301}"), 1);
+
+        assert_eq!(
+            render(
+                "塩 塩水 水 塩水",
+                &AnsiOptions::new().width(7).colors(false)
+            ),
+            "塩 塩水\n水 塩水\n"
+        );
+    }
+}
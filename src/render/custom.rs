@@ -0,0 +1,721 @@
+//! HTML rendering with per-node overrides.
+//!
+//! `Renderer` has a method for every kind of node, each of which
+//! defaults to the HTML `push_html` would write. Implementors
+//! override the kinds they want to render differently and inherit
+//! the rest:
+//!
+//! ```ignore
+//! struct Figures;
+//!
+//! impl Renderer for Figures {
+//!     fn image(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+//!         cx.write_str("<figure>")?;
+//!         render_default(self, cx)?;
+//!         cx.write_str("</figure>")
+//!     }
+//! }
+//! ```
+//!
+//! Nodes are rendered recursively, through `Renderer::children`,
+//! up to `MAX_DEPTH` levels deep.
+
+use super::html::{escape, write_html};
+use super::{link_destination, link_title};
+use crate::ast::{AstNode, Lang, Value};
+use crate::document::Document;
+use crate::events::Events;
+use crate::lib::String;
+use crate::tree::{NodeId, TreeArena};
+use core::fmt::{self, Write};
+use core::num::NonZero;
+
+/// Amount of nested nodes rendered through the `Renderer`.
+///
+/// Deeper nodes are rendered with the defaults as a whole,
+/// without recursing, so deep trees can't overflow the stack.
+pub const MAX_DEPTH: usize = 256;
+
+/// Renders `document` with `renderer`, appending it to `out`.
+pub fn push_custom<R>(out: &mut String, document: &Document, renderer: &mut R)
+where
+    R: Renderer + ?Sized,
+{
+    write_custom(out, document, renderer).expect("writing to a `String` is infallible");
+}
+
+/// Renders `document` with `renderer` into `out`.
+///
+/// # Errors
+/// if writing to `out` or the `renderer` fails.
+pub fn write_custom<W, R>(mut out: W, document: &Document, renderer: &mut R) -> fmt::Result
+where
+    W: Write,
+    R: Renderer + ?Sized,
+{
+    let tree = document.tree();
+    let mut next = tree.root();
+
+    while let Some(id) = next {
+        let Some(node) = tree.get(id) else { break };
+        let mut cx = RenderContext {
+            document,
+            id,
+            node: &node.data,
+            depth: 0,
+            out: &mut out,
+        };
+        renderer.node(&mut cx)?;
+
+        next = node.next_sibling();
+    }
+
+    Ok(())
+}
+
+/// The node being rendered, along with the document
+/// it belongs to and the output it's rendered into.
+///
+/// Text written to the context goes straight to the output,
+/// so it should already be escaped.
+pub struct RenderContext<'a> {
+    document: &'a Document,
+    id: NodeId,
+    node: &'a AstNode,
+
+    /// Amount of ancestors of the node.
+    depth: usize,
+
+    out: &'a mut dyn Write,
+}
+
+impl<'a> RenderContext<'a> {
+    /// Returns the document being rendered.
+    pub fn document(&self) -> &'a Document {
+        self.document
+    }
+
+    /// Returns the tree of the document being rendered.
+    pub fn tree(&self) -> &'a TreeArena<AstNode> {
+        self.document.tree()
+    }
+
+    /// Returns the id of the node being rendered.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Returns the node being rendered.
+    pub fn node(&self) -> &'a AstNode {
+        self.node
+    }
+
+    /// Returns the amount of ancestors of the node.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the text of the node being rendered.
+    pub fn text(&self) -> &'a str {
+        self.node().as_str(self.document)
+    }
+
+    /// Returns the text of the children of the node,
+    /// like the contents of a code block.
+    pub fn children_text(&self) -> String {
+        super::children_text(self.tree(), self.id, self.document)
+    }
+
+    /// Returns the parent of the node, `None` for top-level nodes.
+    pub fn parent(&self) -> Option<NodeId> {
+        self.tree().parent(self.id)
+    }
+
+    /// Returns the parent chain of the node,
+    /// from its parent up to the top-level node.
+    pub fn parents(&self) -> impl Iterator<Item = NodeId> + 'a {
+        self.tree().ancestors(self.id)
+    }
+
+    /// Returns a context for rendering `id`, one level deeper,
+    /// into the same output.
+    ///
+    /// Returns `None` if `id` isn't part of the document.
+    pub fn child(&mut self, id: NodeId) -> Option<RenderContext<'_>> {
+        Some(RenderContext {
+            document: self.document,
+            id,
+            node: &self.tree().get(id)?.data,
+            depth: self.depth + 1,
+            out: &mut *self.out,
+        })
+    }
+
+    /// Writes `text` with the HTML special characters escaped.
+    ///
+    /// # Errors
+    /// if writing to the output fails.
+    pub fn write_escaped(&mut self, text: &str) -> fmt::Result {
+        escape(self, text)
+    }
+}
+
+impl Write for RenderContext<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.out.write_str(s)
+    }
+
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        self.out.write_char(c)
+    }
+}
+
+/// Renders the nodes of a document, one method per kind of node.
+///
+/// Every method defaults to `render_default`, and gets the
+/// attributes of its node on top of the context for convenience.
+pub trait Renderer {
+    /// Renders the node of `cx`, dispatching on its kind.
+    ///
+    /// # Errors
+    /// if writing to the output fails.
+    fn node(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        match &cx.node().value {
+            Value::Root => self.root(cx),
+            Value::Macro { name } => self.macro_invocation(cx, name),
+            Value::Paragraph => self.paragraph(cx),
+            Value::Blockquote => self.blockquote(cx),
+            Value::HtmlBlock => self.html_block(cx),
+            Value::StyleBreak => self.style_break(cx),
+            Value::Heading { level } => self.heading(cx, *level),
+            Value::BulletList { tight } => self.bullet_list(cx, *tight),
+            Value::OrderedList { tight, start_index } => {
+                self.ordered_list(cx, *tight, *start_index)
+            }
+            Value::ListItem => self.list_item(cx),
+            Value::Code { meta, .. } => {
+                let node = cx.node();
+                let info = meta.as_ref().and_then(|meta| meta.info());
+
+                self.code(cx, node.value.lang(), info.map(String::as_str))
+            }
+            Value::Emphasis { strong } => self.emphasis(cx, *strong),
+            Value::Link => self.link(cx),
+            Value::Image => self.image(cx),
+            Value::Strikethrough => self.strikethrough(cx),
+            Value::Emoji => self.emoji(cx),
+            Value::HardBreak => self.hard_break(cx),
+            Value::SoftBreak => self.soft_break(cx),
+            Value::EscapeChar(ch) => self.escape_char(cx, *ch),
+            Value::Text => self.text(cx),
+        }
+    }
+
+    /// Renders the children of the node of `cx` in order.
+    ///
+    /// Children past `MAX_DEPTH` are rendered like `push_html` does,
+    /// without going through the renderer.
+    ///
+    /// # Errors
+    /// if writing to the output fails.
+    fn children(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        let tree = cx.tree();
+
+        for child in tree.children(cx.id()) {
+            if cx.depth() + 1 >= MAX_DEPTH {
+                let events = Events::subtree(tree, cx.document().sources(), child);
+                write_html(&mut *cx, events)?;
+            } else if let Some(mut cx) = cx.child(child) {
+                self.node(&mut cx)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn root(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+
+    fn macro_invocation(&mut self, cx: &mut RenderContext<'_>, name: &str) -> fmt::Result {
+        let _ = name;
+        render_default(self, cx)
+    }
+
+    fn paragraph(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+
+    fn blockquote(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+
+    fn html_block(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+
+    fn style_break(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+
+    fn heading(&mut self, cx: &mut RenderContext<'_>, level: NonZero<u8>) -> fmt::Result {
+        let _ = level;
+        render_default(self, cx)
+    }
+
+    fn bullet_list(&mut self, cx: &mut RenderContext<'_>, tight: bool) -> fmt::Result {
+        let _ = tight;
+        render_default(self, cx)
+    }
+
+    fn ordered_list(
+        &mut self,
+        cx: &mut RenderContext<'_>,
+        tight: bool,
+        start_index: u64,
+    ) -> fmt::Result {
+        let _ = (tight, start_index);
+        render_default(self, cx)
+    }
+
+    fn list_item(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+
+    /// Renders a code block, whose contents
+    /// are the `RenderContext::children_text`.
+    fn code(
+        &mut self,
+        cx: &mut RenderContext<'_>,
+        lang: Option<&Lang>,
+        info: Option<&str>,
+    ) -> fmt::Result {
+        let _ = (lang, info);
+        render_default(self, cx)
+    }
+
+    fn emphasis(&mut self, cx: &mut RenderContext<'_>, strong: bool) -> fmt::Result {
+        let _ = strong;
+        render_default(self, cx)
+    }
+
+    fn link(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+
+    fn image(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+
+    fn strikethrough(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+
+    fn emoji(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+
+    fn hard_break(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+
+    fn soft_break(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+
+    fn escape_char(&mut self, cx: &mut RenderContext<'_>, ch: char) -> fmt::Result {
+        let _ = ch;
+        render_default(self, cx)
+    }
+
+    fn text(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+        render_default(self, cx)
+    }
+}
+
+/// Renders every node with the defaults of `Renderer`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultRenderer;
+
+impl Renderer for DefaultRenderer {}
+
+/// Renders the node of `cx` as HTML, the way `push_html` does,
+/// with its children going through `renderer`.
+///
+/// Lets overrides wrap the default output of their node.
+///
+/// # Errors
+/// if writing to the output fails.
+pub fn render_default<R>(renderer: &mut R, cx: &mut RenderContext<'_>) -> fmt::Result
+where
+    R: Renderer + ?Sized,
+{
+    let node = cx.node();
+
+    let (open, close) = match &node.value {
+        Value::Root => return renderer.children(cx),
+        Value::Macro { name } => {
+            cx.write_str("<div data-macro=\"")?;
+            cx.write_escaped(name)?;
+            cx.write_str("\">\n")?;
+            renderer.children(cx)?;
+
+            return cx.write_str("</div>\n");
+        }
+        Value::Heading { level } => {
+            write!(cx, "<h{level}>")?;
+            renderer.children(cx)?;
+
+            return writeln!(cx, "</h{level}>");
+        }
+        Value::OrderedList { start_index, .. } if *start_index != 1 => {
+            writeln!(cx, "<ol start=\"{start_index}\">")?;
+            renderer.children(cx)?;
+
+            return cx.write_str("</ol>\n");
+        }
+        Value::Code { .. } => {
+            match node.value.lang().and_then(|lang| lang.name()) {
+                Some(name) => {
+                    cx.write_str("<pre><code class=\"language-")?;
+                    cx.write_escaped(name)?;
+                    cx.write_str("\">")?;
+                }
+                None => cx.write_str("<pre><code>")?,
+            }
+
+            let code = cx.children_text();
+            cx.write_escaped(&code)?;
+
+            return cx.write_str("</code></pre>\n");
+        }
+        Value::Paragraph if in_tight_list(cx) => {
+            renderer.children(cx)?;
+
+            // only followed by a line break if more content
+            // follows inside of the list item
+            let tree = cx.tree();
+            if tree
+                .get(cx.id())
+                .and_then(|node| node.next_sibling())
+                .is_some()
+            {
+                cx.write_char('\n')?;
+            }
+
+            return Ok(());
+        }
+        Value::Link => {
+            cx.write_str("<a href=\"")?;
+            attributes(cx)?;
+            cx.write_str("\">")?;
+            renderer.children(cx)?;
+
+            return cx.write_str("</a>");
+        }
+        Value::Image => {
+            cx.write_str("<img src=\"")?;
+            attributes(cx)?;
+            cx.write_str("\" alt=\"")?;
+            alt(cx)?;
+
+            return cx.write_str("\" />");
+        }
+
+        Value::Paragraph => ("<p>", "</p>\n"),
+        Value::Blockquote => ("<blockquote>\n", "</blockquote>\n"),
+        Value::BulletList { .. } => ("<ul>\n", "</ul>\n"),
+        Value::OrderedList { .. } => ("<ol>\n", "</ol>\n"),
+        Value::ListItem => ("<li>", "</li>\n"),
+        Value::Emphasis { strong: true } => ("<strong>", "</strong>"),
+        Value::Emphasis { strong: false } => ("<em>", "</em>"),
+        Value::Strikethrough => ("<del>", "</del>"),
+
+        Value::HtmlBlock => return cx.write_str(cx.text()),
+        Value::StyleBreak => return cx.write_str("<hr />\n"),
+        Value::HardBreak => return cx.write_str("<br />\n"),
+        Value::SoftBreak => return cx.write_char('\n'),
+        Value::Emoji => return cx.write_escaped(cx.text()),
+        Value::EscapeChar(ch) => return cx.write_escaped(ch.encode_utf8(&mut [0; 4])),
+        Value::Text => return cx.write_escaped(cx.text().trim_matches('\n')),
+    };
+
+    cx.write_str(open)?;
+    renderer.children(cx)?;
+    cx.write_str(close)
+}
+
+// whether the paragraph of `cx` is unwrapped, as an item of a tight list
+fn in_tight_list(cx: &RenderContext<'_>) -> bool {
+    let tree = cx.tree();
+    let is = |id: Option<NodeId>, f: fn(&Value) -> bool| {
+        id.and_then(|id| tree.get(id))
+            .is_some_and(|node| f(&node.data.value))
+    };
+
+    let mut parent = tree.parent(cx.id());
+    if is(parent, |value| matches!(value, Value::ListItem)) {
+        parent = parent.and_then(|item| tree.parent(item));
+    }
+
+    is(parent, |value| {
+        matches!(
+            value,
+            Value::BulletList { tight: true } | Value::OrderedList { tight: true, .. }
+        )
+    })
+}
+
+// writes the destination of a link or image and, after
+// closing it, its `title` attribute if it has one
+fn attributes(cx: &mut RenderContext<'_>) -> fmt::Result {
+    let text = cx.text();
+    cx.write_escaped(link_destination(text).unwrap_or_default())?;
+
+    if let Some(title) = link_title(text).filter(|title| !title.is_empty()) {
+        cx.write_str("\" title=\"")?;
+        cx.write_escaped(title)?;
+    }
+
+    Ok(())
+}
+
+// writes the text below an image for its `alt` attribute
+fn alt(cx: &mut RenderContext<'_>) -> fmt::Result {
+    let tree = cx.tree();
+
+    for id in tree.descendants(cx.id()) {
+        let Some(node) = tree.get(id) else {
+            continue;
+        };
+
+        match node.data.value {
+            Value::Text | Value::Emoji => {
+                cx.write_escaped(node.data.as_str(cx.document()).trim_matches('\n'))?;
+            }
+            Value::EscapeChar(ch) => cx.write_escaped(ch.encode_utf8(&mut [0; 4]))?,
+            Value::HardBreak | Value::SoftBreak => cx.write_char(' ')?,
+
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DefaultRenderer, RenderContext, Renderer, push_custom, render_default};
+    use crate::ast::{AstNode, CodeMeta, Lang, Value};
+    use crate::block_parser::Parser;
+    use crate::diagnostic::slug;
+    use crate::document::Document;
+    use crate::lib::{String, Vec};
+    use crate::render::html::push_html;
+    use crate::render::tests::code_block;
+    use crate::tree::TreeArena;
+    use core::fmt::{self, Write};
+    use core::num::NonZero;
+
+    fn html(document: &Document) -> String {
+        let mut out = String::new();
+        push_html(&mut out, document.events());
+
+        out
+    }
+
+    fn custom<R: Renderer>(document: &Document, mut renderer: R) -> String {
+        let mut out = String::new();
+        push_custom(&mut out, document, &mut renderer);
+
+        out
+    }
+
+    #[test]
+    fn defaults_match_html() {
+        let parser = Parser::new();
+
+        for src in [
+            "This is a <paragraph>!\n\n## This is a heading.",
+            "3. Salt\n4. Water\n   - nested\n",
+            "- Salt\n\n- Water\n",
+            "> *Salt* and **water**\n> ~~lazy~~ :smile: \\*\n\n---\n",
+        ] {
+            let document = parser.parse_document(src);
+            assert_eq!(custom(&document, DefaultRenderer), html(&document));
+        }
+
+        let mut tree = TreeArena::new();
+        let code = code_block(
+            &mut tree,
+            None,
+            Some(CodeMeta::new(Lang::recognize("rust"), None)),
+            "let x = 1 < 2;\n",
+        );
+        let para = tree
+            .insert_after(code, AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        let image = tree
+            .append_child(
+                para,
+                AstNode::with_text(Value::Image, "![salt](<salt.png> \"A <salt> shaker\")"),
+            )
+            .unwrap();
+        tree.append_child(image, AstNode::with_text(Value::Text, "a \"salt\""))
+            .unwrap();
+        tree.append_child(image, AstNode::with_text(Value::SoftBreak, "\n"))
+            .unwrap();
+        tree.append_child(image, AstNode::with_text(Value::Text, "shaker"))
+            .unwrap();
+
+        let document = Document::from(tree);
+        assert_eq!(custom(&document, DefaultRenderer), html(&document));
+    }
+
+    struct Site;
+
+    impl Renderer for Site {
+        fn heading(&mut self, cx: &mut RenderContext<'_>, level: NonZero<u8>) -> fmt::Result {
+            let id = slug(&cx.children_text());
+            write!(cx, "<h{level} id=\"{id}\">")?;
+            self.children(cx)?;
+            writeln!(cx, "</h{level}>")
+        }
+
+        fn image(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+            cx.write_str("<figure>")?;
+            render_default(self, cx)?;
+            cx.write_str("</figure>")
+        }
+
+        fn code(
+            &mut self,
+            cx: &mut RenderContext<'_>,
+            lang: Option<&Lang>,
+            _info: Option<&str>,
+        ) -> fmt::Result {
+            let lang = lang.and_then(|lang| lang.name()).unwrap_or("text");
+            write!(cx, "<pre data-lang=\"{lang}\">")?;
+
+            for line in cx.children_text().lines() {
+                cx.write_str("<span class=\"line\">")?;
+                cx.write_escaped(line)?;
+                cx.write_str("</span>")?;
+            }
+
+            cx.write_str("</pre>\n")
+        }
+    }
+
+    #[test]
+    fn overrides_inherit_the_rest() {
+        let document = Parser::new().parse_document("## Salt and Water\n\nSome text.");
+        assert_eq!(
+            custom(&document, Site),
+            "<h2 id=\"salt-and-water\">Salt and Water</h2>\n<p>Some text.</p>\n"
+        );
+
+        let mut tree = TreeArena::new();
+        let para = tree
+            .attach_node(AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        let image = tree
+            .append_child(para, AstNode::with_text(Value::Image, ""))
+            .unwrap();
+        tree.append_child(image, AstNode::with_text(Value::Text, "salt"))
+            .unwrap();
+        let code = tree
+            .insert_after(
+                para,
+                AstNode::with_text(
+                    Value::Code {
+                        lang: Some(Lang::Rust),
+                        meta: None,
+                    },
+                    "",
+                ),
+            )
+            .unwrap();
+        tree.append_child(code, AstNode::with_text(Value::Text, "a < b\nc\n"))
+            .unwrap();
+
+        assert_eq!(
+            custom(&Document::from(tree), Site),
+            concat!(
+                "<p><figure><img src=\"\" alt=\"salt\" /></figure></p>\n",
+                "<pre data-lang=\"rust\"><span class=\"line\">a &lt; b</span>",
+                "<span class=\"line\">c</span></pre>\n",
+            )
+        );
+    }
+
+    // records the kinds of the parents of every text
+    #[derive(Default)]
+    struct Parents(Vec<Vec<&'static str>>);
+
+    impl Renderer for Parents {
+        fn text(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+            let tree = cx.tree();
+            let kinds = cx
+                .parents()
+                .filter_map(|id| tree.get(id))
+                .map(|node| node.data.value.name())
+                .collect();
+
+            assert_eq!(cx.parent(), cx.parents().next());
+            self.0.push(kinds);
+
+            render_default(self, cx)
+        }
+    }
+
+    #[test]
+    fn parent_chain() {
+        let document = Parser::new().parse_document("> - salt\n");
+        let mut parents = Parents::default();
+        let mut out = String::new();
+        push_custom(&mut out, &document, &mut parents);
+
+        assert_eq!(out, html(&document));
+        assert_eq!(
+            parents.0,
+            [["paragraph", "list-item", "bullet-list", "blockquote"]]
+        );
+    }
+
+    #[test]
+    fn deep_documents() {
+        extern crate std;
+
+        let src = "> ".repeat(20_000) + "salt";
+        let document = Parser::new().max_depth(usize::MAX).parse_document(&src);
+        let expected = html(&document);
+
+        let rendered = std::thread::Builder::new()
+            .stack_size(2 * 1024 * 1024)
+            .spawn(move || custom(&document, Site))
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn foreign_ids() {
+        struct Foreign(crate::tree::NodeId);
+
+        impl Renderer for Foreign {
+            fn paragraph(&mut self, cx: &mut RenderContext<'_>) -> fmt::Result {
+                assert!(cx.child(self.0).is_none());
+                render_default(self, cx)
+            }
+        }
+
+        let other = Parser::new().parse_document("Water.");
+        let document = Parser::new().parse_document("Salt.");
+        let foreign = Foreign(other.tree().root().unwrap());
+
+        assert_eq!(custom(&document, foreign), "<p>Salt.</p>\n");
+    }
+}
@@ -0,0 +1,413 @@
+//! Gemtext rendering of a parsed document, for Gemini capsules.
+//!
+//! Gemtext is line oriented, so every paragraph becomes a single
+//! line and links can't be inline: they are gathered into `=>` link
+//! lines after the paragraph, or after the list or quote holding it.
+//! Headings deeper than level 3 are clamped, nested lists flattened
+//! into `*` lines and code blocks set between preformatting toggles.
+
+use super::{NodeWriter, children_text, link_destination, words, write_tree};
+use crate::ast::{AstNode, Value};
+use crate::lib::{String, Vec};
+use crate::source::Sources;
+use crate::tree::{Control, NodeId, TreeArena};
+use core::fmt::{self, Write};
+
+/// Renders `tree` as gemtext, appending it to `out`.
+pub fn push_gemtext<S>(out: &mut String, tree: &TreeArena<AstNode>, sources: &S)
+where
+    S: Sources + ?Sized,
+{
+    write_gemtext(out, tree, sources).expect("writing to a `String` is infallible");
+}
+
+/// Renders `tree` as gemtext into `out`.
+///
+/// # Errors
+/// if writing to `out` fails.
+pub fn write_gemtext<W, S>(out: W, tree: &TreeArena<AstNode>, sources: &S) -> fmt::Result
+where
+    W: Write,
+    S: Sources + ?Sized,
+{
+    let mut writer = GemtextWriter {
+        out,
+        tree,
+        sources,
+        blank: false,
+        lists: Vec::new(),
+        marker: None,
+        quotes: 0,
+        inline: None,
+        heading: 0,
+        buffer: String::new(),
+        space: false,
+        open_links: Vec::new(),
+        links: Vec::new(),
+    };
+
+    write_tree(tree, None, &mut writer)
+}
+
+/// A link gathered from the text, written as a link line.
+struct Link {
+    destination: String,
+    text: String,
+}
+
+struct GemtextWriter<'t, W, S: ?Sized> {
+    out: W,
+    tree: &'t TreeArena<AstNode>,
+    sources: &'t S,
+
+    /// Whether a blank line goes before the next block.
+    blank: bool,
+
+    /// Next number of the open lists, `None` for bullet lists.
+    lists: Vec<Option<u64>>,
+
+    /// Number of the list item whose first line is pending.
+    marker: Option<Option<u64>>,
+
+    quotes: usize,
+
+    /// Node whose inline content is being collected.
+    inline: Option<NodeId>,
+
+    /// Level of the heading being collected, `0` for other blocks.
+    heading: u8,
+
+    /// Collected inline content, with hard breaks as `\n`.
+    buffer: String,
+
+    /// Whether a space goes before the next word.
+    space: bool,
+
+    /// Destination and start in the `buffer` of the open links.
+    open_links: Vec<Option<(String, usize)>>,
+
+    /// Links waiting for the end of their block.
+    links: Vec<Link>,
+}
+
+impl<W: Write, S: Sources + ?Sized> GemtextWriter<'_, W, S> {
+    fn words(&mut self, text: &str) {
+        for (word, space) in words(text, self.quotes) {
+            self.space |= space;
+
+            if !word.is_empty() {
+                self.word(word);
+            }
+        }
+    }
+
+    fn word(&mut self, word: &str) {
+        if self.space && !self.buffer.is_empty() && !self.buffer.ends_with('\n') {
+            self.buffer.push(' ');
+        }
+
+        self.space = false;
+        self.buffer.push_str(word);
+    }
+
+    // writes the collected inline content, a line for every hard break
+    fn lines(&mut self) -> fmt::Result {
+        let buffer = core::mem::take(&mut self.buffer);
+        let heading = core::mem::take(&mut self.heading);
+        self.space = false;
+
+        let mut lines = buffer
+            .split('\n')
+            .filter(|line| !line.is_empty())
+            .peekable();
+        if lines.peek().is_none() {
+            self.buffer = buffer;
+            self.buffer.clear();
+            return Ok(());
+        }
+
+        self.separate()?;
+
+        for line in lines {
+            if heading > 0 {
+                for _ in 0..heading {
+                    self.out.write_char('#')?;
+                }
+                self.out.write_char(' ')?;
+            } else if !self.lists.is_empty() {
+                self.out.write_str("* ")?;
+
+                // only the first line of an item is numbered
+                if let Some(Some(number)) = self.marker.take() {
+                    write!(self.out, "{number}. ")?;
+                }
+            } else if self.quotes > 0 {
+                self.out.write_str("> ")?;
+            } else if is_line_type(line) {
+                // keeps text from being taken as another type of line
+                self.out.write_char(' ')?;
+            }
+
+            self.out.write_str(line)?;
+            self.out.write_char('\n')?;
+        }
+
+        self.buffer = buffer;
+        self.buffer.clear();
+        self.ended();
+
+        if self.lists.is_empty() && self.quotes == 0 {
+            self.links()?;
+        }
+
+        Ok(())
+    }
+
+    // writes the gathered links as link lines
+    fn links(&mut self) -> fmt::Result {
+        for link in self.links.drain(..) {
+            write!(self.out, "=> {}", link.destination)?;
+
+            if !link.text.is_empty() {
+                write!(self.out, " {}", link.text)?;
+            }
+
+            self.out.write_char('\n')?;
+        }
+
+        self.blank = true;
+        Ok(())
+    }
+
+    fn code(&mut self, id: NodeId, node: &AstNode) -> fmt::Result {
+        let code = children_text(self.tree, id, self.sources);
+
+        self.separate()?;
+        self.out.write_str("```")?;
+
+        if let Some(lang) = node.value.lang().and_then(|lang| lang.name()) {
+            self.out.write_str(lang)?;
+        }
+        self.out.write_char('\n')?;
+
+        for line in code.trim_matches('\n').split('\n') {
+            // a line starting with a toggle would end the block early
+            if line.starts_with("```") {
+                self.out.write_char(' ')?;
+            }
+
+            self.out.write_str(line)?;
+            self.out.write_char('\n')?;
+        }
+
+        self.out.write_str("```\n")?;
+        self.ended();
+
+        Ok(())
+    }
+
+    // writes a blank line between blocks, items of a list
+    // follow each other and paragraphs of a quote stay quoted
+    fn separate(&mut self) -> fmt::Result {
+        if self.blank {
+            self.blank = false;
+
+            if self.quotes > 0 {
+                return self.out.write_str(">\n");
+            }
+            return self.out.write_char('\n');
+        }
+
+        Ok(())
+    }
+
+    fn ended(&mut self) {
+        self.blank = self.lists.is_empty();
+    }
+}
+
+impl<W: Write, S: Sources + ?Sized> NodeWriter for GemtextWriter<'_, W, S> {
+    fn enter_node(&mut self, id: NodeId, node: &AstNode, _: usize) -> Result<Control, fmt::Error> {
+        // inlines outside of paragraphs are written on a line of their own
+        if self.inline.is_none() && node.value.is_inline() {
+            self.inline = Some(id);
+        }
+
+        match &node.value {
+            Value::Paragraph => self.inline = Some(id),
+            Value::Heading { level } => {
+                self.inline = Some(id);
+                self.heading = level.get().min(3);
+            }
+            Value::Blockquote => {
+                if self.quotes == 0 {
+                    self.separate()?;
+                }
+                self.quotes += 1;
+            }
+            Value::BulletList { .. } => self.lists.push(None),
+            Value::OrderedList { start_index, .. } => self.lists.push(Some(*start_index)),
+            Value::ListItem => {
+                self.marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        let current = *number;
+                        *number = number.saturating_add(1);
+                        Some(Some(current))
+                    }
+                    _ => Some(None),
+                };
+            }
+            Value::Code { .. } => {
+                self.code(id, node)?;
+                return Ok(Control::SkipChildren);
+            }
+            Value::HtmlBlock => return Ok(Control::SkipChildren),
+            Value::StyleBreak => {
+                self.separate()?;
+                self.out.write_str("---\n")?;
+                self.ended();
+            }
+
+            Value::Link | Value::Image => {
+                let destination = link_destination(node.as_str(self.sources));
+                let start = self.buffer.len();
+
+                self.open_links
+                    .push(destination.map(|destination| (String::from(destination), start)));
+            }
+            Value::Text | Value::Emoji => self.words(node.as_str(self.sources)),
+            Value::EscapeChar(ch) => self.word(ch.encode_utf8(&mut [0; 4])),
+            Value::HardBreak => {
+                self.buffer.push('\n');
+                self.space = false;
+            }
+            Value::SoftBreak => self.space = true,
+
+            Value::Root | Value::Macro { .. } | Value::Emphasis { .. } | Value::Strikethrough => (),
+        }
+
+        Ok(Control::Continue)
+    }
+
+    fn exit_node(&mut self, id: NodeId, node: &AstNode, _: usize) -> fmt::Result {
+        match &node.value {
+            Value::Link | Value::Image => {
+                if let Some((destination, start)) = self.open_links.pop().flatten() {
+                    let text = self.buffer[start..].trim().replace('\n', " ");
+                    self.links.push(Link { destination, text });
+                }
+            }
+            Value::Blockquote => {
+                self.quotes -= 1;
+                if self.quotes == 0 {
+                    self.links()?;
+                }
+            }
+            Value::BulletList { .. } | Value::OrderedList { .. } => {
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.links()?;
+                }
+            }
+
+            _ => (),
+        }
+
+        if self.inline == Some(id) {
+            self.inline = None;
+            self.lines()?;
+        }
+
+        Ok(())
+    }
+}
+
+// returns `true` if a text line would be read as another type of line
+fn is_line_type(line: &str) -> bool {
+    ["#", "* ", ">", "=>", "```"]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::push_gemtext;
+    use crate::ast::{AstNode, Value};
+    use crate::block_parser::parse;
+    use crate::lib::String;
+    use crate::render::tests::code_block;
+    use crate::tree::Anchor;
+    use crate::tree::TreeArena;
+
+    fn render(src: &str) -> String {
+        let mut out = String::new();
+        push_gemtext(&mut out, &parse(src), src);
+
+        out
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(render("##### Deep\n"), "### Deep\n");
+        assert_eq!(
+            render("> The sea\nis salty.\n>\n> # Salt\n"),
+            "> The sea is salty.\n>\n# Salt\n"
+        );
+        assert_eq!(
+            render("* one\n* two\n  - nested\n\n  3. three\n"),
+            "* one\n* two\n* nested\n* 3. three\n"
+        );
+        assert_eq!(render("=> not a link\n"), " => not a link\n");
+    }
+
+    #[test]
+    fn links() {
+        let mut tree = TreeArena::new();
+        let paragraph = tree
+            .attach_node(AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::Text, "Read "))
+            .unwrap();
+        let link = tree
+            .append_child(
+                paragraph,
+                AstNode::with_text(Value::Link, "[the docs](gemini://example.org/docs)"),
+            )
+            .unwrap();
+        tree.append_child(link, AstNode::with_text(Value::Text, "the docs"))
+            .unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::Text, ", see "))
+            .unwrap();
+        let image = tree
+            .append_child(
+                paragraph,
+                AstNode::with_text(Value::Image, "![a map](map.png)"),
+            )
+            .unwrap();
+        tree.append_child(image, AstNode::with_text(Value::Text, "a map"))
+            .unwrap();
+
+        code_block(
+            &mut tree,
+            Some(Anchor::After(paragraph)),
+            None,
+            "```\nrun\n",
+        );
+
+        let mut out = String::new();
+        push_gemtext(&mut out, &tree, "");
+        assert_eq!(
+            out,
+            concat!(
+                "Read the docs, see a map\n",
+                "=> gemini://example.org/docs the docs\n",
+                "=> map.png a map\n",
+                "\n",
+                "```\n",
+                " ```\n",
+                "run\n",
+                "```\n",
+            )
+        );
+    }
+}
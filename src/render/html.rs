@@ -0,0 +1,422 @@
+//! HTML rendering of an `Event` stream.
+//!
+//! Works on any iterator of `Event`s, so the events coming
+//! out of `TreeArena::events` may be filtered or transformed
+//! before being rendered.
+
+use crate::events::{Event, Tag};
+use crate::lib::{String, Vec};
+use core::fmt::{self, Write};
+use core::mem;
+
+/// Renders `events` as HTML, appending it to `out`.
+pub fn push_html<'a, I>(out: &mut String, events: I)
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    write_html(out, events).expect("writing to a `String` is infallible");
+}
+
+/// Renders `events` as HTML into `out`.
+///
+/// # Errors
+/// if writing to `out` fails.
+pub fn write_html<'a, I, W>(out: W, events: I) -> fmt::Result
+where
+    I: Iterator<Item = Event<'a>>,
+    W: Write,
+{
+    HtmlWriter {
+        out,
+        image_depth: 0,
+        tight: Vec::new(),
+        newline: false,
+    }
+    .run(events)
+}
+
+struct HtmlWriter<W> {
+    out: W,
+
+    /// Amount of currently open images,
+    /// text inside of them goes to the `alt` attribute.
+    image_depth: usize,
+
+    /// Whether paragraphs in each open container are
+    /// left unwrapped, as they are in tight lists.
+    tight: Vec<bool>,
+
+    /// Whether a tight paragraph ended and the
+    /// next block should start on a new line.
+    newline: bool,
+}
+
+impl<W: Write> HtmlWriter<W> {
+    fn run<'a, I>(mut self, events: I) -> fmt::Result
+    where
+        I: Iterator<Item = Event<'a>>,
+    {
+        for event in events {
+            // a tight paragraph is only followed by a line break
+            // if more content follows inside of its container
+            if mem::take(&mut self.newline) && !matches!(event, Event::End(_)) {
+                self.out.write_char('\n')?;
+            }
+
+            match event {
+                Event::Start(tag) => self.start(&tag)?,
+                Event::End(tag) => self.end(&tag)?,
+
+                _ if self.image_depth > 0 => self.alt(&event)?,
+
+                Event::Text(text) => escape(&mut self.out, text.trim_matches('\n'))?,
+                Event::Code(text) => escape(&mut self.out, text)?,
+                Event::Html(html) => self.out.write_str(html)?,
+                Event::Emoji(code) => escape(&mut self.out, code)?,
+                Event::EscapeChar(ch) => escape(&mut self.out, ch.encode_utf8(&mut [0; 4]))?,
+                Event::StyleBreak => self.out.write_str("<hr />\n")?,
+                Event::HardBreak => self.out.write_str("<br />\n")?,
+                Event::SoftBreak => self.out.write_char('\n')?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn start(&mut self, tag: &Tag<'_>) -> fmt::Result {
+        if self.image_depth > 0 {
+            if matches!(tag, Tag::Image { .. }) {
+                self.image_depth += 1;
+            }
+
+            return Ok(());
+        }
+
+        match tag {
+            Tag::Macro { .. } | Tag::Blockquote => self.tight.push(false),
+            Tag::BulletList { tight } | Tag::OrderedList { tight, .. } => self.tight.push(*tight),
+            Tag::ListItem => self.tight.push(self.is_tight()),
+
+            _ => (),
+        }
+
+        match tag {
+            Tag::Macro { name } => {
+                self.out.write_str("<div data-macro=\"")?;
+                escape(&mut self.out, name)?;
+                self.out.write_str("\">\n")
+            }
+            Tag::Paragraph if self.is_tight() => Ok(()),
+            Tag::Paragraph => self.out.write_str("<p>"),
+            Tag::Blockquote => self.out.write_str("<blockquote>\n"),
+            Tag::Heading { level } => write!(self.out, "<h{level}>"),
+            Tag::BulletList { .. } => self.out.write_str("<ul>\n"),
+            Tag::OrderedList { start_index, .. } if *start_index != 1 => {
+                writeln!(self.out, "<ol start=\"{start_index}\">")
+            }
+            Tag::OrderedList { .. } => self.out.write_str("<ol>\n"),
+            Tag::ListItem => self.out.write_str("<li>"),
+            Tag::Code { lang, .. } => match lang.and_then(|lang| lang.name()) {
+                Some(name) => {
+                    self.out.write_str("<pre><code class=\"language-")?;
+                    escape(&mut self.out, name)?;
+                    self.out.write_str("\">")
+                }
+                None => self.out.write_str("<pre><code>"),
+            },
+            Tag::Emphasis { strong: true } => self.out.write_str("<strong>"),
+            Tag::Emphasis { strong: false } => self.out.write_str("<em>"),
+            Tag::Link { destination, title } => {
+                self.out.write_str("<a href=\"")?;
+                escape(&mut self.out, destination)?;
+                self.title(title)?;
+                self.out.write_str("\">")
+            }
+            Tag::Image { destination, title } => {
+                self.image_depth += 1;
+                self.out.write_str("<img src=\"")?;
+                escape(&mut self.out, destination)?;
+                self.title(title)?;
+                self.out.write_str("\" alt=\"")
+            }
+            Tag::Strikethrough => self.out.write_str("<del>"),
+        }
+    }
+
+    fn end(&mut self, tag: &Tag<'_>) -> fmt::Result {
+        if self.image_depth > 0 {
+            if matches!(tag, Tag::Image { .. }) {
+                self.image_depth -= 1;
+
+                if self.image_depth == 0 {
+                    return self.out.write_str("\" />");
+                }
+            }
+
+            return Ok(());
+        }
+
+        if matches!(
+            tag,
+            Tag::Macro { .. }
+                | Tag::Blockquote
+                | Tag::BulletList { .. }
+                | Tag::OrderedList { .. }
+                | Tag::ListItem
+        ) {
+            self.tight.pop();
+        }
+
+        match tag {
+            Tag::Macro { .. } => self.out.write_str("</div>\n"),
+            Tag::Paragraph if self.is_tight() => {
+                self.newline = true;
+                Ok(())
+            }
+            Tag::Paragraph => self.out.write_str("</p>\n"),
+            Tag::Blockquote => self.out.write_str("</blockquote>\n"),
+            Tag::Heading { level } => writeln!(self.out, "</h{level}>"),
+            Tag::BulletList { .. } => self.out.write_str("</ul>\n"),
+            Tag::OrderedList { .. } => self.out.write_str("</ol>\n"),
+            Tag::ListItem => self.out.write_str("</li>\n"),
+            Tag::Code { .. } => self.out.write_str("</code></pre>\n"),
+            Tag::Emphasis { strong: true } => self.out.write_str("</strong>"),
+            Tag::Emphasis { strong: false } => self.out.write_str("</em>"),
+            Tag::Link { .. } => self.out.write_str("</a>"),
+            // images which were started are handled above,
+            // a stray end out of a filtered stream is ignored
+            Tag::Image { .. } => Ok(()),
+            Tag::Strikethrough => self.out.write_str("</del>"),
+        }
+    }
+
+    // whether paragraphs in the innermost container are unwrapped
+    fn is_tight(&self) -> bool {
+        self.tight.last().copied().unwrap_or(false)
+    }
+
+    // writes the `title` attribute, closing the attribute before it
+    fn title(&mut self, title: &str) -> fmt::Result {
+        if title.is_empty() {
+            return Ok(());
+        }
+
+        self.out.write_str("\" title=\"")?;
+        escape(&mut self.out, title)
+    }
+
+    // writes the textual content of an event inside of an `alt` attribute
+    fn alt(&mut self, event: &Event<'_>) -> fmt::Result {
+        match *event {
+            Event::Text(text) | Event::Code(text) | Event::Emoji(text) => {
+                escape(&mut self.out, text.trim_matches('\n'))
+            }
+            Event::EscapeChar(ch) => escape(&mut self.out, ch.encode_utf8(&mut [0; 4])),
+            Event::HardBreak | Event::SoftBreak => self.out.write_char(' '),
+
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Writes `text` with the HTML special characters escaped.
+pub(crate) fn escape<W: Write>(out: &mut W, text: &str) -> fmt::Result {
+    let mut last = 0;
+
+    for (ix, byte) in text.bytes().enumerate() {
+        let replacement = match byte {
+            b'&' => "&amp;",
+            b'<' => "&lt;",
+            b'>' => "&gt;",
+            b'"' => "&quot;",
+
+            _ => continue,
+        };
+
+        out.write_str(&text[last..ix])?;
+        out.write_str(replacement)?;
+        last = ix + 1;
+    }
+
+    out.write_str(&text[last..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::push_html;
+    use crate::ast::{AstNode, Value};
+    use crate::block_parser::parse;
+    use crate::events::{Event, Tag};
+    use crate::lib::String;
+    use crate::tree::{Control, Edits, NodeId, TreeArena, TreeVisitorMut};
+
+    fn render(src: &str) -> String {
+        let mut out = String::new();
+        push_html(&mut out, parse(src).events(src));
+
+        out
+    }
+
+    #[test]
+    fn paragraph_and_heading() {
+        assert_eq!(
+            render("This is a <paragraph>!\n\n## This is a heading."),
+            "<p>This is a &lt;paragraph&gt;!</p>\n<h2>This is a heading.</h2>\n"
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            render("3. Salt\n4. Water\n"),
+            "<ol start=\"3\">\n<li>Salt</li>\n<li>Water</li>\n</ol>\n"
+        );
+        assert_eq!(
+            render("- Salt\n\n- Water\n"),
+            "<ul>\n<li><p>Salt</p>\n</li>\n<li><p>Water</p>\n</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn tight_items_with_blocks() {
+        let mut tree = TreeArena::new();
+        let list = tree
+            .attach_node(AstNode::with_text(Value::BulletList { tight: true }, ""))
+            .unwrap();
+        let item = tree
+            .append_child(list, AstNode::with_text(Value::ListItem, ""))
+            .unwrap();
+        let para = tree
+            .append_child(item, AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        tree.append_child(para, AstNode::with_text(Value::Text, "Salt"))
+            .unwrap();
+        let nested = tree
+            .append_child(
+                item,
+                AstNode::with_text(Value::BulletList { tight: true }, ""),
+            )
+            .unwrap();
+        let item = tree
+            .append_child(nested, AstNode::with_text(Value::ListItem, ""))
+            .unwrap();
+        let para = tree
+            .append_child(item, AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        tree.append_child(para, AstNode::with_text(Value::Text, "sea"))
+            .unwrap();
+
+        let mut out = String::new();
+        push_html(&mut out, tree.events(""));
+
+        assert_eq!(
+            out,
+            "<ul>\n<li>Salt\n<ul>\n<li>sea</li>\n</ul>\n</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn links_and_images() {
+        let mut tree = TreeArena::new();
+        let para = tree
+            .attach_node(AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        let link = tree
+            .append_child(
+                para,
+                AstNode::with_text(Value::Link, "[Salt](<salt.md> \"The <sea>\")"),
+            )
+            .unwrap();
+        tree.append_child(link, AstNode::with_text(Value::Text, "Salt"))
+            .unwrap();
+        let image = tree
+            .append_child(para, AstNode::with_text(Value::Image, "![map](map.png)"))
+            .unwrap();
+        tree.append_child(image, AstNode::with_text(Value::Text, "a \"map\""))
+            .unwrap();
+
+        let mut out = String::new();
+        push_html(&mut out, tree.events(""));
+
+        assert_eq!(
+            out,
+            concat!(
+                "<p><a href=\"salt.md\" title=\"The &lt;sea&gt;\">Salt</a>",
+                "<img src=\"map.png\" alt=\"a &quot;map&quot;\" /></p>\n",
+            )
+        );
+    }
+
+    #[test]
+    fn filtered_events() {
+        let src = "# Title\n\nSome text.";
+        let tree = parse(src);
+        let mut out = String::new();
+
+        let events = tree.events(src).map(|event| match event {
+            Event::Start(Tag::Heading { .. }) => Event::Start(Tag::Paragraph),
+            Event::End(Tag::Heading { .. }) => Event::End(Tag::Paragraph),
+            other => other,
+        });
+
+        push_html(&mut out, events);
+
+        assert_eq!(out, "<p>Title</p>\n<p>Some text.</p>\n");
+    }
+
+    #[test]
+    fn unbalanced_events() {
+        let mut out = String::new();
+        let events = [
+            Event::Start(Tag::Paragraph),
+            Event::Text("salt"),
+            Event::End(Tag::Image {
+                destination: "",
+                title: "",
+            }),
+            Event::End(Tag::Paragraph),
+        ];
+
+        push_html(&mut out, events.into_iter());
+
+        assert_eq!(out, "<p>salt</p>\n");
+    }
+
+    #[test]
+    fn owned_text() {
+        struct SmartPunctuation<'s>(&'s str);
+
+        impl TreeVisitorMut for SmartPunctuation<'_> {
+            fn enter(
+                &mut self,
+                _: NodeId,
+                node: &mut AstNode,
+                _: usize,
+                _: &mut Edits<AstNode>,
+            ) -> Control {
+                if let Value::Text = node.value {
+                    let text = node.as_str(self.0).replace("...", "\u{2026}");
+                    node.set_text(text);
+                }
+
+                Control::Continue
+            }
+        }
+
+        let src = "Salt and water...";
+        let mut tree = parse(src);
+        tree.walk_mut(&mut SmartPunctuation(src));
+
+        let paragraph = tree.root().unwrap();
+        tree.insert_before(
+            paragraph,
+            AstNode::with_text(Value::HtmlBlock, "<nav></nav>\n"),
+        )
+        .unwrap();
+
+        let mut out = String::new();
+        push_html(&mut out, tree.events(src));
+
+        assert_eq!(out, "<nav></nav>\n<p>Salt and water\u{2026}</p>\n");
+    }
+}
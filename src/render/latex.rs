@@ -0,0 +1,697 @@
+//! LaTeX rendering of a parsed document.
+//!
+//! Headings become sectioning commands by their level, lists become
+//! `itemize` and `enumerate` environments and blockquotes `quote`.
+//! Code blocks are set `verbatim`, or as `lstlisting` when listings
+//! are enabled and know their language. Links and images read their
+//! destination from the source, as the tree doesn't store it.
+
+use super::{NodeWriter, children_text, link_destination, strip_markers, write_tree};
+use crate::ast::{AstNode, Value};
+use crate::lib::{String, Vec};
+use crate::source::Sources;
+use crate::tree::{Control, NodeId, TreeArena};
+use core::fmt::{self, Write};
+
+/// Options for rendering LaTeX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatexOptions {
+    standalone: bool,
+    listings: bool,
+    link_footnotes: bool,
+}
+
+impl Default for LatexOptions {
+    fn default() -> Self {
+        Self {
+            standalone: false,
+            listings: true,
+            link_footnotes: false,
+        }
+    }
+}
+
+impl LatexOptions {
+    /// Returns the default options, which render
+    /// a fragment using listings for code in a language.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps the document in a preamble loading the packages
+    /// it needs and `\begin{document}`/`\end{document}`.
+    #[must_use]
+    pub fn standalone(mut self, standalone: bool) -> Self {
+        self.standalone = standalone;
+        self
+    }
+
+    /// Sets code in a language with the `listings` package,
+    /// rather than as `verbatim`.
+    #[must_use]
+    pub fn listings(mut self, listings: bool) -> Self {
+        self.listings = listings;
+        self
+    }
+
+    /// Adds the destination of links as a footnote,
+    /// so that it isn't lost in print.
+    #[must_use]
+    pub fn link_footnotes(mut self, link_footnotes: bool) -> Self {
+        self.link_footnotes = link_footnotes;
+        self
+    }
+}
+
+/// Renders `tree` as LaTeX, appending it to `out`.
+pub fn push_latex<S>(
+    out: &mut String,
+    tree: &TreeArena<AstNode>,
+    sources: &S,
+    options: &LatexOptions,
+) where
+    S: Sources + ?Sized,
+{
+    write_latex(out, tree, sources, options).expect("writing to a `String` is infallible");
+}
+
+/// Renders `tree` as LaTeX into `out`.
+///
+/// # Errors
+/// if writing to `out` fails.
+pub fn write_latex<W, S>(
+    out: W,
+    tree: &TreeArena<AstNode>,
+    sources: &S,
+    options: &LatexOptions,
+) -> fmt::Result
+where
+    W: Write,
+    S: Sources + ?Sized,
+{
+    let mut writer = LatexWriter {
+        out,
+        tree,
+        sources,
+        options: *options,
+        started: false,
+        line_start: true,
+        first: false,
+        enumerations: 0,
+        lists: Vec::new(),
+        quotes: 0,
+    };
+
+    if options.standalone {
+        writer.out.write_str(concat!(
+            "\\documentclass{article}\n",
+            "\\usepackage[T1]{fontenc}\n",
+            "\\usepackage[utf8]{inputenc}\n",
+            "\\usepackage{graphicx}\n",
+        ))?;
+
+        if options.listings {
+            writer.out.write_str("\\usepackage{listings}\n")?;
+        }
+
+        writer.out.write_str(concat!(
+            "\\usepackage[normalem]{ulem}\n",
+            "\\usepackage{hyperref}\n",
+            "\n",
+            "\\begin{document}\n",
+            "\n",
+        ))?;
+    }
+
+    write_tree(tree, None, &mut writer)?;
+    writer.end_line()?;
+
+    if options.standalone {
+        writer.out.write_str("\n\\end{document}\n")?;
+    }
+
+    Ok(())
+}
+
+struct LatexWriter<'t, W, S: ?Sized> {
+    out: W,
+    tree: &'t TreeArena<AstNode>,
+    sources: &'t S,
+    options: LatexOptions,
+
+    /// Whether any block was written.
+    started: bool,
+
+    /// Whether nothing was written on the current line.
+    line_start: bool,
+
+    /// Whether the next block is the first one
+    /// of a list item or quote, following `\item`.
+    first: bool,
+
+    /// Amount of open `enumerate` environments.
+    enumerations: usize,
+
+    /// Whether the open lists are ordered.
+    lists: Vec<bool>,
+
+    quotes: usize,
+}
+
+impl<W: Write, S: Sources + ?Sized> LatexWriter<'_, W, S> {
+    // separates blocks with a blank line,
+    // except for the first one of a container
+    fn block(&mut self) -> fmt::Result {
+        if self.first {
+            self.first = false;
+        } else {
+            self.end_line()?;
+
+            if self.started {
+                self.out.write_char('\n')?;
+            }
+        }
+
+        self.started = true;
+        Ok(())
+    }
+
+    fn code(&mut self, id: NodeId, node: &AstNode) -> fmt::Result {
+        let code = children_text(self.tree, id, self.sources);
+
+        let lang = node
+            .value
+            .lang()
+            .and_then(|lang| lang.name())
+            .and_then(listings_language)
+            .filter(|_| self.options.listings);
+        let environment = match lang {
+            Some(_) => "lstlisting",
+            None => "verbatim",
+        };
+
+        let code = code.trim_matches('\n');
+
+        self.block()?;
+        self.line_start = true;
+
+        // verbatim text is taken as is, up to the end of the environment,
+        // so code which would end it early is set escaped instead
+        if ends_environment(code, environment) {
+            return self.escaped_code(code);
+        }
+
+        write!(self.out, "\\begin{{{environment}}}")?;
+
+        if let Some(lang) = lang {
+            write!(self.out, "[language={lang}]")?;
+        }
+
+        writeln!(self.out, "\n{code}\n\\end{{{environment}}}")
+    }
+
+    // sets the lines of `code` in a typewriter font,
+    // keeping their spaces
+    fn escaped_code(&mut self, code: &str) -> fmt::Result {
+        self.out.write_str("\\begin{flushleft}\n")?;
+
+        for line in code.split('\n') {
+            self.out.write_str("\\texttt{")?;
+
+            for (ix, part) in line.split(' ').enumerate() {
+                if ix > 0 {
+                    self.out.write_str("\\ ")?;
+                }
+                escape(&mut self.out, part)?;
+            }
+
+            self.out.write_str("}\\\\\n")?;
+        }
+
+        self.out.write_str("\\end{flushleft}\n")
+    }
+
+    // writes the lines of `text`, without
+    // the lazy markers of blockquotes
+    fn text(&mut self, text: &str) -> fmt::Result {
+        for (ix, line) in text.split('\n').enumerate() {
+            let line = if ix > 0 {
+                self.end_line()?;
+                strip_markers(line, self.quotes)
+            } else if self.line_start {
+                line.trim_start()
+            } else {
+                line
+            };
+
+            self.escaped(line)?;
+        }
+
+        Ok(())
+    }
+
+    fn escaped(&mut self, text: &str) -> fmt::Result {
+        if !text.is_empty() {
+            self.line_start = false;
+        }
+
+        escape(&mut self.out, text)
+    }
+
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.line_start = false;
+        self.out.write_str(text)
+    }
+
+    fn line(&mut self, line: &str) -> fmt::Result {
+        self.out.write_str(line)?;
+        self.out.write_char('\n')?;
+        self.line_start = true;
+
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> fmt::Result {
+        if !self.line_start {
+            self.line_start = true;
+            self.out.write_char('\n')?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write, S: Sources + ?Sized> NodeWriter for LatexWriter<'_, W, S> {
+    fn enter_node(&mut self, id: NodeId, node: &AstNode, _: usize) -> Result<Control, fmt::Error> {
+        match &node.value {
+            Value::Heading { level } => {
+                self.block()?;
+
+                let command = match level.get() {
+                    1 => "section",
+                    2 => "subsection",
+                    3 => "subsubsection",
+                    4 => "paragraph",
+                    _ => "subparagraph",
+                };
+                write!(self.out, "\\{command}{{")?;
+                self.line_start = false;
+            }
+            Value::Paragraph => self.block()?,
+            Value::Blockquote => {
+                self.block()?;
+                self.line("\\begin{quote}")?;
+                self.quotes += 1;
+                self.first = true;
+            }
+            Value::BulletList { .. } => {
+                self.block()?;
+                self.line("\\begin{itemize}")?;
+                self.lists.push(false);
+            }
+            Value::OrderedList { start_index, .. } => {
+                self.block()?;
+                self.line("\\begin{enumerate}")?;
+                self.lists.push(true);
+                self.enumerations += 1;
+
+                if *start_index != 1 {
+                    // enumerate counters are named enumi to enumiv by depth
+                    let counter = ["i", "ii", "iii", "iv"][(self.enumerations - 1).min(3)];
+                    let value = start_index.saturating_sub(1);
+                    writeln!(self.out, "\\setcounter{{enum{counter}}}{{{value}}}")?;
+                    self.line_start = true;
+                }
+            }
+            Value::ListItem => {
+                self.end_line()?;
+                // `{}` keeps a leading `[` of the item from being its label
+                self.out.write_str("\\item{} ")?;
+                self.line_start = false;
+                self.first = true;
+            }
+            Value::Code { .. } => {
+                self.code(id, node)?;
+                return Ok(Control::SkipChildren);
+            }
+            Value::HtmlBlock => return Ok(Control::SkipChildren),
+            Value::StyleBreak => {
+                self.block()?;
+                self.line("\\begin{center}\\rule{0.5\\linewidth}{0.4pt}\\end{center}")?;
+            }
+
+            Value::Emphasis { strong: true } => self.write_str("\\textbf{")?,
+            Value::Emphasis { strong: false } => self.write_str("\\emph{")?,
+            Value::Strikethrough => self.write_str("\\sout{")?,
+            Value::Link => {
+                if let Some(destination) = link_destination(node.as_str(self.sources)) {
+                    self.write_str("\\href{")?;
+                    escape_url(&mut self.out, destination)?;
+                    self.out.write_str("}{")?;
+                }
+            }
+            Value::Image => {
+                if let Some(destination) = link_destination(node.as_str(self.sources)) {
+                    self.write_str("\\includegraphics[width=\\linewidth]{")?;
+                    escape_url(&mut self.out, destination)?;
+                    self.out.write_char('}')?;
+
+                    return Ok(Control::SkipChildren);
+                }
+            }
+            Value::Text | Value::Emoji => self.text(node.as_str(self.sources))?,
+            Value::EscapeChar(ch) => self.escaped(ch.encode_utf8(&mut [0; 4]))?,
+            // `{}` keeps a `[` on the next line from being a spacing
+            Value::HardBreak => self.write_str("\\\\{}\n")?,
+            Value::SoftBreak => self.end_line()?,
+
+            Value::Root | Value::Macro { .. } => (),
+        }
+
+        Ok(Control::Continue)
+    }
+
+    fn exit_node(&mut self, _: NodeId, node: &AstNode, _: usize) -> fmt::Result {
+        match &node.value {
+            Value::Heading { .. } => self.line("}"),
+            Value::Paragraph => self.end_line(),
+            Value::Blockquote => {
+                self.quotes -= 1;
+                self.end_line()?;
+                self.line("\\end{quote}")
+            }
+            Value::BulletList { .. } | Value::OrderedList { .. } => {
+                self.end_line()?;
+
+                if self.lists.pop() == Some(true) {
+                    self.enumerations -= 1;
+                    self.line("\\end{enumerate}")
+                } else {
+                    self.line("\\end{itemize}")
+                }
+            }
+            Value::ListItem => {
+                self.first = false;
+                Ok(())
+            }
+
+            Value::Emphasis { .. } | Value::Strikethrough => self.write_str("}"),
+            Value::Link => {
+                let Some(destination) = link_destination(node.as_str(self.sources)) else {
+                    return Ok(());
+                };
+
+                self.out.write_char('}')?;
+
+                if self.options.link_footnotes {
+                    self.out.write_str("\\footnote{\\url{")?;
+                    escape_url(&mut self.out, destination)?;
+                    self.out.write_str("}}")?;
+                }
+
+                Ok(())
+            }
+
+            _ => Ok(()),
+        }
+    }
+}
+
+// whether `code` contains the end of `environment`,
+// which LaTeX allows spaces in
+fn ends_environment(code: &str, environment: &str) -> bool {
+    code.match_indices("\\end").any(|(ix, end)| {
+        code[ix + end.len()..]
+            .trim_start()
+            .strip_prefix('{')
+            .and_then(|rest| rest.trim_start().strip_prefix(environment))
+            .is_some_and(|rest| rest.trim_start().starts_with('}'))
+    })
+}
+
+// the name the stock listings package knows `lang` by,
+// it fails to load any other language
+fn listings_language(lang: &str) -> Option<&'static str> {
+    let name = match lang.to_ascii_lowercase().as_str() {
+        "bash" | "sh" | "shell" => "bash",
+        "c" => "C",
+        "c++" | "cpp" => "C++",
+        "fortran" => "Fortran",
+        "haskell" | "hs" => "Haskell",
+        "html" => "HTML",
+        "java" => "Java",
+        "lisp" => "Lisp",
+        "make" | "makefile" => "make",
+        "pascal" => "Pascal",
+        "perl" => "Perl",
+        "php" => "PHP",
+        "python" | "py" => "Python",
+        "r" => "R",
+        "ruby" | "rb" => "Ruby",
+        "sql" => "SQL",
+        "tex" | "latex" => "TeX",
+        "xml" => "XML",
+
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+// escapes the characters LaTeX treats specially in text
+fn escape<W: Write>(out: &mut W, text: &str) -> fmt::Result {
+    let special = ['\\', '{', '}', '$', '&', '%', '#', '_', '~', '^'];
+    let mut rest = text;
+
+    while let Some(ix) = rest.find(special) {
+        out.write_str(&rest[..ix])?;
+
+        match rest.as_bytes()[ix] {
+            b'\\' => out.write_str("\\textbackslash{}")?,
+            b'~' => out.write_str("\\textasciitilde{}")?,
+            b'^' => out.write_str("\\textasciicircum{}")?,
+            byte => {
+                out.write_char('\\')?;
+                out.write_char(char::from(byte))?;
+            }
+        }
+
+        rest = &rest[ix + 1..];
+    }
+
+    out.write_str(rest)
+}
+
+// escapes the characters `\href` and `\url` don't take as they are
+fn escape_url<W: Write>(out: &mut W, url: &str) -> fmt::Result {
+    for ch in url.chars() {
+        if matches!(ch, '\\' | '{' | '}' | '#' | '%') {
+            out.write_char('\\')?;
+        }
+        out.write_char(ch)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LatexOptions, push_latex};
+    use crate::ast::{AstNode, CodeMeta, Lang, Value};
+    use crate::block_parser::parse;
+    use crate::lib::String;
+    use crate::render::tests::code_block;
+    use crate::tree::Anchor;
+    use crate::tree::TreeArena;
+
+    fn render(src: &str, options: &LatexOptions) -> String {
+        let mut out = String::new();
+        push_latex(&mut out, &parse(src), src, options);
+
+        out
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(
+            render(
+                "> 50% of $5 & #1_a ~ b^2 \\o/ {x}\nlazy\n",
+                &LatexOptions::new()
+            ),
+            concat!(
+                "\\begin{quote}\n",
+                "50\\% of \\$5 \\& \\#1\\_a \\textasciitilde{} b\\textasciicircum{}2 ",
+                "\\textbackslash{}o/ \\{x\\}\n",
+                "lazy\n",
+                "\\end{quote}\n",
+            )
+        );
+        assert_eq!(
+            render("### Salt\n", &LatexOptions::new()),
+            "\\subsubsection{Salt}\n"
+        );
+        assert_eq!(
+            render("3. three\n4. four\n   - nested\n", &LatexOptions::new()),
+            concat!(
+                "\\begin{enumerate}\n",
+                "\\setcounter{enumi}{2}\n",
+                "\\item{} three\n",
+                "\\item{} four\n",
+                "\n",
+                "\\begin{itemize}\n",
+                "\\item{} nested\n",
+                "\\end{itemize}\n",
+                "\\end{enumerate}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn code() {
+        let mut tree = TreeArena::new();
+        let python = code_block(
+            &mut tree,
+            None,
+            Some(CodeMeta::new(Lang::recognize("python"), None)),
+            "print(\"{}\")\n",
+        );
+        code_block(&mut tree, Some(Anchor::After(python)), None, "$ run\n");
+
+        let mut out = String::new();
+        push_latex(&mut out, &tree, "", &LatexOptions::new());
+        assert_eq!(
+            out,
+            concat!(
+                "\\begin{lstlisting}[language=Python]\n",
+                "print(\"{}\")\n",
+                "\\end{lstlisting}\n",
+                "\n",
+                "\\begin{verbatim}\n",
+                "$ run\n",
+                "\\end{verbatim}\n",
+            )
+        );
+
+        out.clear();
+        push_latex(&mut out, &tree, "", &LatexOptions::new().listings(false));
+        assert!(out.starts_with("\\begin{verbatim}\nprint"));
+
+        // languages unknown to listings are set verbatim
+        let mut tree = TreeArena::new();
+        code_block(
+            &mut tree,
+            None,
+            Some(CodeMeta::new(Lang::Rust, None)),
+            "let _ = 1;\n",
+        );
+
+        out.clear();
+        push_latex(&mut out, &tree, "", &LatexOptions::new());
+        assert_eq!(out, "\\begin{verbatim}\nlet _ = 1;\n\\end{verbatim}\n");
+    }
+
+    #[test]
+    fn brackets_after_commands() {
+        assert_eq!(
+            render("- [note] salt\n", &LatexOptions::new()),
+            "\\begin{itemize}\n\\item{} [note] salt\n\\end{itemize}\n"
+        );
+
+        let mut tree = TreeArena::new();
+        let paragraph = tree
+            .attach_node(AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::Text, "salt"))
+            .unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::HardBreak, ""))
+            .unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::Text, "[2] water"))
+            .unwrap();
+
+        let mut out = String::new();
+        push_latex(&mut out, &tree, "", &LatexOptions::new());
+        assert_eq!(out, "salt\\\\{}\n[2] water\n");
+    }
+
+    #[test]
+    fn code_ending_its_environment() {
+        let mut tree = TreeArena::new();
+        code_block(
+            &mut tree,
+            None,
+            None,
+            "\\end {verbatim}\n  \\input{/etc/passwd}\n",
+        );
+
+        let mut out = String::new();
+        push_latex(&mut out, &tree, "", &LatexOptions::new());
+        assert_eq!(
+            out,
+            concat!(
+                "\\begin{flushleft}\n",
+                "\\texttt{\\textbackslash{}end\\ \\{verbatim\\}}\\\\\n",
+                "\\texttt{\\ \\ \\textbackslash{}input\\{/etc/passwd\\}}\\\\\n",
+                "\\end{flushleft}\n",
+            )
+        );
+
+        // the end of another environment is harmless
+        let mut tree = TreeArena::new();
+        code_block(&mut tree, None, None, "\\end{itemize}\n");
+
+        out.clear();
+        push_latex(&mut out, &tree, "", &LatexOptions::new());
+        assert_eq!(out, "\\begin{verbatim}\n\\end{itemize}\n\\end{verbatim}\n");
+    }
+
+    #[test]
+    fn links_and_images() {
+        let mut tree = TreeArena::new();
+        let paragraph = tree
+            .attach_node(AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        let link = tree
+            .append_child(
+                paragraph,
+                AstNode::with_text(
+                    Value::Link,
+                    "[the docs](https://example.com/#a%20b \"Docs\")",
+                ),
+            )
+            .unwrap();
+        tree.append_child(link, AstNode::with_text(Value::Text, "the docs"))
+            .unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::Text, " and "))
+            .unwrap();
+        let image = tree
+            .append_child(
+                paragraph,
+                AstNode::with_text(Value::Image, "![salt](<salt.png>)"),
+            )
+            .unwrap();
+        tree.append_child(image, AstNode::with_text(Value::Text, "salt"))
+            .unwrap();
+
+        let mut out = String::new();
+        push_latex(
+            &mut out,
+            &tree,
+            "",
+            &LatexOptions::new().link_footnotes(true),
+        );
+        assert_eq!(
+            out,
+            concat!(
+                "\\href{https://example.com/\\#a\\%20b}{the docs}",
+                "\\footnote{\\url{https://example.com/\\#a\\%20b}} and ",
+                "\\includegraphics[width=\\linewidth]{salt.png}\n",
+            )
+        );
+
+        out.clear();
+        push_latex(&mut out, &tree, "", &LatexOptions::new().standalone(true));
+        assert!(out.starts_with("\\documentclass{article}\n"));
+        assert!(out.contains("\\begin{document}\n\n\\href"));
+        assert!(out.ends_with("salt.png}\n\n\\end{document}\n"));
+    }
+}
@@ -0,0 +1,957 @@
+//! Markdown rendering of a parsed document.
+//!
+//! Writes a `TreeArena<AstNode>` back out as Markdown, normalizing
+//! its style along the way: list markers, numbering, headings and
+//! code fences all follow the given `MarkdownOptions`.
+//! Macro invocations are written back with their `<>=` operator
+//! and arguments as they appear in the source.
+
+use super::{NodeWriter, children_text, link_destination, strip_markers, write_tree};
+use crate::ast::{AstNode, TextSource, Value};
+use crate::lib::{String, Vec};
+use crate::source::Sources;
+use crate::tree::{Control, NodeId, TreeArena};
+use core::fmt::{self, Write};
+
+/// Marker used for the items of bullet lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Bullet {
+    /// `- item`
+    #[default]
+    Dash,
+
+    /// `* item`
+    Star,
+
+    /// `+ item`
+    Plus,
+}
+
+/// Delimiter following the number of ordered list items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Delimiter {
+    /// `1. item`
+    #[default]
+    Period,
+
+    /// `1) item`
+    Paren,
+}
+
+/// How the items of ordered lists are numbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Numbering {
+    /// Counts up from the start index of the list.
+    #[default]
+    Ascending,
+
+    /// Repeats the start index on every item.
+    Repeated,
+}
+
+/// Style of headings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeadingStyle {
+    /// `# Heading`
+    #[default]
+    Atx,
+
+    /// Underlined with `===` or `---`, headings
+    /// deeper than level 2 still use ATX.
+    Setext,
+}
+
+/// Character making up code fences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fence {
+    /// ` ``` `
+    #[default]
+    Backtick,
+
+    /// `~~~`
+    Tilde,
+}
+
+/// Style options of the Markdown renderer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MarkdownOptions {
+    bullet: Bullet,
+    delimiter: Delimiter,
+    numbering: Numbering,
+    headings: HeadingStyle,
+    fence: Fence,
+    underscore_emphasis: bool,
+}
+
+impl MarkdownOptions {
+    /// Creates the default options, producing
+    /// `-` bullets, `1.` numbers, ATX headings,
+    /// backtick fences and `*` emphasis.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the marker of bullet list items.
+    #[must_use]
+    pub fn bullet(mut self, bullet: Bullet) -> Self {
+        self.bullet = bullet;
+        self
+    }
+
+    /// Sets the delimiter of ordered list items.
+    #[must_use]
+    pub fn delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Sets how ordered list items are numbered.
+    #[must_use]
+    pub fn numbering(mut self, numbering: Numbering) -> Self {
+        self.numbering = numbering;
+        self
+    }
+
+    /// Sets the style of headings.
+    #[must_use]
+    pub fn headings(mut self, headings: HeadingStyle) -> Self {
+        self.headings = headings;
+        self
+    }
+
+    /// Sets the character of code fences.
+    #[must_use]
+    pub fn fence(mut self, fence: Fence) -> Self {
+        self.fence = fence;
+        self
+    }
+
+    /// Uses `_` instead of `*` for emphasis.
+    #[must_use]
+    pub fn underscore_emphasis(mut self, underscore_emphasis: bool) -> Self {
+        self.underscore_emphasis = underscore_emphasis;
+        self
+    }
+}
+
+/// Renders `tree` as Markdown, appending it to `out`.
+///
+/// The given `sources` should hold the data
+/// from which the `tree` was parsed.
+pub fn push_markdown<S>(
+    out: &mut String,
+    tree: &TreeArena<AstNode>,
+    sources: &S,
+    options: &MarkdownOptions,
+) where
+    S: Sources + ?Sized,
+{
+    write_markdown(out, tree, sources, options).expect("writing to a `String` is infallible");
+}
+
+/// Renders `tree` as Markdown into `out`.
+///
+/// # Errors
+/// if writing to `out` fails.
+pub fn write_markdown<W, S>(
+    out: W,
+    tree: &TreeArena<AstNode>,
+    sources: &S,
+    options: &MarkdownOptions,
+) -> fmt::Result
+where
+    W: Write,
+    S: Sources + ?Sized,
+{
+    let mut writer = MarkdownWriter {
+        out,
+        tree,
+        sources,
+        options,
+        containers: Vec::new(),
+        lists: Vec::new(),
+        blank: false,
+        leaf: None,
+    };
+
+    write_tree(tree, None, &mut writer)
+}
+
+/// A container whose lines are prefixed.
+struct Container {
+    /// Written at the start of the first line instead of `indent`.
+    marker: Option<String>,
+    indent: &'static str,
+    quote: bool,
+}
+
+/// An open list.
+struct List {
+    /// Next item number, `None` for bullet lists.
+    number: Option<u64>,
+
+    /// Bullet or delimiter of the items.
+    marker: char,
+
+    /// Items aren't separated by blank lines.
+    tight: bool,
+}
+
+/// The paragraph or heading being written.
+struct Leaf {
+    /// Written in front of the first content.
+    opening: String,
+    started: bool,
+
+    /// A line break is due before the next content.
+    broken: bool,
+
+    /// Line breaks are written as spaces.
+    single_line: bool,
+
+    /// Characters on the current line.
+    width: usize,
+}
+
+struct MarkdownWriter<'t, W, S: ?Sized> {
+    out: W,
+    tree: &'t TreeArena<AstNode>,
+    sources: &'t S,
+    options: &'t MarkdownOptions,
+
+    containers: Vec<Container>,
+    lists: Vec<List>,
+
+    /// Whether a blank line goes before the next block.
+    blank: bool,
+
+    leaf: Option<Leaf>,
+}
+
+impl<W: Write, S: Sources + ?Sized> MarkdownWriter<'_, W, S> {
+    // whether the list `id` directly follows a list of the same kind,
+    // which it would be merged into if it used the same marker
+    //
+    // a list right inside of another one comes after its items
+    fn follows_list(&self, id: NodeId, node: &AstNode) -> bool {
+        let same_kind = |other: Option<NodeId>| {
+            other
+                .and_then(|other| self.tree.get(other))
+                .is_some_and(|other| {
+                    core::mem::discriminant(&other.data.value)
+                        == core::mem::discriminant(&node.value)
+                })
+        };
+
+        self.tree
+            .get(id)
+            .is_some_and(|target| same_kind(target.prev_sibling()) || same_kind(target.parent()))
+    }
+
+    fn push(&mut self, indent: &'static str, quote: bool) {
+        self.containers.push(Container {
+            marker: None,
+            indent,
+            quote,
+        });
+        self.blank = false;
+    }
+
+    fn leaf(&mut self, opening: String, single_line: bool) {
+        self.leaf = Some(Leaf {
+            opening,
+            started: false,
+            broken: false,
+            single_line,
+            width: 0,
+        });
+    }
+
+    // ends the current line of a paragraph or heading,
+    // setext headings get underlined with `underline`
+    fn finish_leaf(&mut self, underline: Option<char>) -> fmt::Result {
+        let Some(leaf) = self.leaf.take() else {
+            return Ok(());
+        };
+
+        // empty paragraphs are dropped
+        if !leaf.started {
+            return Ok(());
+        }
+
+        self.out.write_char('\n')?;
+
+        if let Some(ch) = underline {
+            self.prefix()?;
+            for _ in 0..leaf.width.max(3) {
+                self.out.write_char(ch)?;
+            }
+            self.out.write_char('\n')?;
+        }
+
+        self.blank = true;
+        Ok(())
+    }
+
+    // writes inline content of the current leaf
+    fn content(&mut self, text: &str) -> fmt::Result {
+        let Some(leaf) = &mut self.leaf else {
+            return Ok(());
+        };
+
+        if !leaf.started {
+            leaf.started = true;
+            let opening = core::mem::take(&mut leaf.opening);
+
+            self.separate()?;
+            self.prefix()?;
+            self.out.write_str(&opening)?;
+        } else if leaf.broken {
+            leaf.broken = false;
+
+            if leaf.single_line {
+                self.out.write_char(' ')?;
+            } else {
+                leaf.width = 0;
+                self.out.write_char('\n')?;
+                self.prefix()?;
+            }
+        }
+
+        if let Some(leaf) = &mut self.leaf {
+            leaf.width += text.chars().count();
+        }
+
+        self.out.write_str(text)
+    }
+
+    fn soft_break(&mut self) {
+        if let Some(leaf) = &mut self.leaf
+            && leaf.started
+        {
+            leaf.broken = true;
+        }
+    }
+
+    // writes the lines of source text, leaving out the indentation and
+    // blockquote markers of continuation lines, which are written anew
+    //
+    // source text is Markdown already, `literal` text is escaped
+    fn text(&mut self, text: &str, literal: bool) -> fmt::Result {
+        let quotes = self.containers.iter().filter(|c| c.quote).count();
+        let mut escaped = String::new();
+
+        for (ix, line) in text.split('\n').enumerate() {
+            if ix > 0 {
+                self.soft_break();
+            }
+
+            let at_line_start = ix > 0 || self.leaf.as_ref().is_some_and(|leaf| !leaf.started);
+            let line = if at_line_start {
+                strip_markers(line, quotes)
+            } else {
+                line
+            };
+
+            if line.is_empty() {
+                continue;
+            }
+
+            escaped.clear();
+            escape(&mut escaped, line, at_line_start, literal);
+            self.content(&escaped)?;
+        }
+
+        Ok(())
+    }
+
+    fn emphasis(&mut self, strong: bool) -> fmt::Result {
+        let marker = match (self.options.underscore_emphasis, strong) {
+            (false, false) => "*",
+            (false, true) => "**",
+            (true, false) => "_",
+            (true, true) => "__",
+        };
+
+        self.content(marker)
+    }
+
+    fn code(&mut self, id: NodeId, node: &AstNode) -> fmt::Result {
+        let code = children_text(self.tree, id, self.sources);
+
+        let fence = match self.options.fence {
+            Fence::Backtick => '`',
+            Fence::Tilde => '~',
+        };
+
+        // the fence has to be longer than any run of its character inside
+        let mut longest = 0;
+        let mut run = 0;
+        for ch in code.chars() {
+            run = if ch == fence { run + 1 } else { 0 };
+            longest = longest.max(run);
+        }
+
+        let mut line = String::new();
+        (0..(longest + 1).max(3)).for_each(|_| line.push(fence));
+        let fence_len = line.len();
+
+        if let Some(name) = node.value.lang().and_then(|lang| lang.name()) {
+            line.push_str(name);
+        }
+
+        if let Value::Code {
+            meta: Some(meta), ..
+        } = &node.value
+            && let Some(info) = meta.info()
+        {
+            line.push(' ');
+            line.push_str(info);
+        }
+
+        self.separate()?;
+        self.prefix()?;
+        writeln!(self.out, "{}", line.trim_end())?;
+
+        let code = code.strip_suffix('\n').unwrap_or(&code);
+        if !code.is_empty() {
+            self.lines(code)?;
+        }
+
+        self.prefix()?;
+        writeln!(self.out, "{}", &line[..fence_len])?;
+
+        self.blank = true;
+        Ok(())
+    }
+
+    // writes `text` as is, line by line
+    fn lines(&mut self, text: &str) -> fmt::Result {
+        for line in text.split('\n') {
+            if line.is_empty() {
+                self.blank_line()?;
+            } else {
+                self.prefix()?;
+                writeln!(self.out, "{line}")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // writes a blank line if one is due before the next block
+    fn separate(&mut self) -> fmt::Result {
+        if core::mem::take(&mut self.blank) {
+            self.blank_line()?;
+        }
+
+        Ok(())
+    }
+
+    fn blank_line(&mut self) -> fmt::Result {
+        let mut prefix = String::new();
+        for container in &self.containers {
+            prefix.push_str(container.indent);
+        }
+
+        writeln!(self.out, "{}", prefix.trim_end())
+    }
+
+    // writes the prefixes of the open containers at the start of a line
+    fn prefix(&mut self) -> fmt::Result {
+        for container in &mut self.containers {
+            match container.marker.take() {
+                Some(marker) => self.out.write_str(&marker)?,
+                None => self.out.write_str(container.indent)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write, S: Sources + ?Sized> NodeWriter for MarkdownWriter<'_, W, S> {
+    fn enter_node(&mut self, id: NodeId, node: &AstNode, _: usize) -> Result<Control, fmt::Error> {
+        match &node.value {
+            Value::Root => (),
+            Value::Macro { name } => {
+                self.separate()?;
+                self.prefix()?;
+
+                // the operator and arguments are taken from the source
+                let source = node.as_str(self.sources);
+                match source.lines().next().map(str::trim_end) {
+                    Some(line) if line.starts_with("<>= ") && line.ends_with('(') => {
+                        self.out.write_str(line)?;
+                    }
+                    _ => write!(self.out, "<>= {name} () (")?,
+                }
+
+                self.out.write_char('\n')?;
+                self.push("", false);
+            }
+            Value::Paragraph => self.leaf(String::new(), false),
+            Value::Heading { level } => {
+                let level = level.get();
+
+                if level <= 2 && self.options.headings == HeadingStyle::Setext {
+                    self.leaf(String::new(), false);
+                } else {
+                    let mut opening = String::new();
+                    (0..level).for_each(|_| opening.push('#'));
+                    opening.push(' ');
+
+                    self.leaf(opening, true);
+                }
+            }
+            Value::Blockquote => {
+                self.separate()?;
+                self.push("> ", true);
+            }
+            Value::BulletList { tight } => {
+                let marker = match (self.options.bullet, self.follows_list(id, node)) {
+                    (Bullet::Dash, false) | (Bullet::Star | Bullet::Plus, true) => '-',
+                    (Bullet::Star, false) | (Bullet::Dash, true) => '*',
+                    (Bullet::Plus, false) => '+',
+                };
+
+                self.separate()?;
+                self.lists.push(List {
+                    number: None,
+                    marker,
+                    tight: *tight,
+                });
+            }
+            Value::OrderedList { tight, start_index } => {
+                let marker = match (self.options.delimiter, self.follows_list(id, node)) {
+                    (Delimiter::Period, false) | (Delimiter::Paren, true) => '.',
+                    (Delimiter::Paren, false) | (Delimiter::Period, true) => ')',
+                };
+
+                self.separate()?;
+                self.lists.push(List {
+                    number: Some(*start_index),
+                    marker,
+                    tight: *tight,
+                });
+            }
+            Value::ListItem => {
+                let mut marker = String::new();
+
+                if let Some(list) = self.lists.last_mut() {
+                    match &mut list.number {
+                        Some(number) => {
+                            write!(marker, "{number}{} ", list.marker)?;
+
+                            if self.options.numbering == Numbering::Ascending {
+                                *number = number.saturating_add(1);
+                            }
+                        }
+                        None => write!(marker, "{} ", list.marker)?,
+                    }
+                }
+
+                // only the items of loose lists are separated by blank lines
+                if self.lists.last().is_none_or(|list| list.tight) {
+                    self.blank = false;
+                }
+                let indent = &"          "[..marker.len().min(10)];
+                self.containers.push(Container {
+                    marker: Some(marker),
+                    indent,
+                    quote: false,
+                });
+            }
+            Value::Code { .. } => {
+                self.code(id, node)?;
+                return Ok(Control::SkipChildren);
+            }
+            Value::HtmlBlock => {
+                self.separate()?;
+                self.lines(node.as_str(self.sources).trim_end_matches('\n'))?;
+                self.blank = true;
+            }
+            Value::StyleBreak => {
+                self.separate()?;
+                self.prefix()?;
+                self.out.write_str("---\n")?;
+                self.blank = true;
+            }
+
+            Value::Emphasis { strong } => self.emphasis(*strong)?,
+            Value::Link => self.content("[")?,
+            Value::Image => self.content("![")?,
+            Value::Strikethrough => self.content("~~")?,
+            Value::Emoji => self.content(node.as_str(self.sources))?,
+            Value::EscapeChar(ch) => {
+                self.content("\\")?;
+                self.content(ch.encode_utf8(&mut [0; 4]))?;
+            }
+            Value::HardBreak => {
+                self.content("\\")?;
+                self.soft_break();
+            }
+            Value::SoftBreak => self.soft_break(),
+            Value::Text => {
+                let literal = matches!(node.text, TextSource::Owned(_));
+                self.text(node.as_str(self.sources), literal)?;
+            }
+        }
+
+        Ok(Control::Continue)
+    }
+
+    fn exit_node(&mut self, _: NodeId, node: &AstNode, _: usize) -> fmt::Result {
+        match &node.value {
+            Value::Macro { .. } => {
+                self.containers.pop();
+
+                // a blank line keeps the `)` out of a preceding paragraph
+                self.blank_line()?;
+                self.prefix()?;
+                self.out.write_str(")\n")?;
+                self.blank = true;
+            }
+            Value::Paragraph => self.finish_leaf(None)?,
+            Value::Heading { level } => {
+                let level = level.get();
+
+                if level <= 2 && self.options.headings == HeadingStyle::Setext {
+                    self.finish_leaf(Some(if level == 1 { '=' } else { '-' }))?;
+                } else {
+                    // empty headings are still written
+                    if let Some(leaf) = &mut self.leaf
+                        && !leaf.started
+                    {
+                        leaf.opening.pop();
+                        self.content("")?;
+                    }
+
+                    self.finish_leaf(None)?;
+                }
+            }
+            Value::Blockquote | Value::ListItem => {
+                self.containers.pop();
+                self.blank = true;
+            }
+            Value::BulletList { .. } | Value::OrderedList { .. } => {
+                self.lists.pop();
+                self.blank = true;
+            }
+
+            Value::Emphasis { strong } => self.emphasis(*strong)?,
+            Value::Link | Value::Image => {
+                let destination = link_destination(node.as_str(self.sources));
+
+                self.content("](")?;
+                self.content(destination.unwrap_or(""))?;
+                self.content(")")?;
+            }
+            Value::Strikethrough => self.content("~~")?,
+
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+// escapes what would start a block at the start of a line,
+// and in `literal` text every character with a meaning in Markdown
+fn escape(out: &mut String, line: &str, at_line_start: bool, literal: bool) {
+    let mut rest = line;
+
+    if at_line_start {
+        let digits = line.bytes().take_while(u8::is_ascii_digit).count();
+        let mut chars = line.chars();
+        let first = chars.next();
+        let after = chars.next();
+
+        // a lone marker, or one followed by whitespace, starts a list
+        // or a thematic break, `*` and `_` else start emphasis
+        let marker = |ch: char| {
+            after.is_none_or(char::is_whitespace) || line.chars().all(|c| c == ch || c == ' ')
+        };
+
+        match first {
+            Some(ch @ ('#' | '>' | '-' | '+' | '=')) => {
+                out.push('\\');
+                out.push(ch);
+                rest = &line[1..];
+            }
+            Some(ch @ ('*' | '_')) if marker(ch) => {
+                out.push('\\');
+                out.push(ch);
+                rest = &line[1..];
+            }
+            // literal text has these escaped anyway
+            Some('`' | '~') if !literal && (line.starts_with("```") || line.starts_with("~~~")) => {
+                out.push('\\');
+            }
+            Some('<') if !literal && line.starts_with("<>=") => out.push('\\'),
+            _ if digits > 0 && line[digits..].starts_with(['.', ')']) => {
+                out.push_str(&line[..digits]);
+                out.push('\\');
+                rest = &line[digits..];
+            }
+            _ => (),
+        }
+    }
+
+    for ch in rest.chars() {
+        if literal && matches!(ch, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '~') {
+            out.push('\\');
+        }
+
+        out.push(ch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Bullet, Delimiter, Fence, HeadingStyle, MarkdownOptions, Numbering, push_markdown,
+    };
+    use crate::ast::{AstNode, CodeMeta, Lang, Value};
+    use crate::block_parser::parse;
+    use crate::lib::{String, ToString, Vec};
+    use crate::render::tests::code_block;
+    use crate::tree::TreeArena;
+
+    fn format(src: &str, options: &MarkdownOptions) -> String {
+        let mut out = String::new();
+        push_markdown(&mut out, &parse(src), src, options);
+
+        out
+    }
+
+    // the text a CommonMark parser reads from backslash escapes
+    fn unescape(text: &str) -> String {
+        let mut out = String::new();
+        let mut chars = text.chars().peekable();
+
+        while let Some(ch) = chars.next() {
+            match chars.peek() {
+                Some(next) if ch == '\\' && next.is_ascii_punctuation() => (),
+                _ => out.push(ch),
+            }
+        }
+
+        out
+    }
+
+    // the nodes of `src` with their depth, leaving out
+    // what the renderer is free to change
+    fn shape(src: &str) -> Vec<(usize, String)> {
+        let tree = parse(src);
+        let mut shape = Vec::new();
+
+        for id in tree.nodes() {
+            let node = &tree.get(id).unwrap().data;
+            let depth = tree.ancestors(id).count();
+
+            let entry = match &node.value {
+                Value::Heading { level } => ["heading ", &level.to_string()].concat(),
+                Value::BulletList { tight } => ["bullet-list ", &tight.to_string()].concat(),
+                Value::OrderedList { tight, start_index } => [
+                    "ordered-list ",
+                    &start_index.to_string(),
+                    " ",
+                    &tight.to_string(),
+                ]
+                .concat(),
+                Value::Macro { name } => ["macro ", name].concat(),
+                Value::Text => {
+                    let text = unescape(node.as_str(src));
+                    let lines = text.split('\n').map(|line| super::strip_markers(line, 8));
+
+                    lines
+                        .filter(|line| !line.is_empty())
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                }
+                value => value.name().to_string(),
+            };
+
+            shape.push((depth, entry));
+        }
+
+        // empty paragraphs are dropped by the renderer
+        let mut ix = 0;
+        while ix < shape.len() {
+            if shape[ix].1 == "paragraph" && shape.get(ix + 1).is_some_and(|(_, t)| t.is_empty()) {
+                shape.drain(ix..ix + 2);
+            } else {
+                ix += 1;
+            }
+        }
+
+        shape
+    }
+
+    #[test]
+    fn round_trip() {
+        let documents = [
+            "# Salt water\n\nThe sea is *salty*,\n  and deep.\n",
+            "Intro.\n\n* First\n* Second\n  continued\n\n7) Seven\n8) Eight\n",
+            "> Quoted\n> text.\n",
+            "- > Quoted\n- Item\n",
+            "<>= note (kind=info) (\nInside the macro.\n\n- nested\n\n)\n\nAfter.\n",
+            "Setext heading\n===\n\n### Third level ###\n\nThe end.\n",
+            "***\n\nAfter a break.\n",
+            "- one\n\n- two\n- three\n",
+            "> 1. one\n>\n> 2. two\n",
+            "Text\n  > not a quote\n",
+        ];
+
+        for src in documents {
+            let formatted = format(src, &MarkdownOptions::new());
+
+            assert_eq!(
+                shape(src),
+                shape(&formatted),
+                "{src:?} became {formatted:?}"
+            );
+            assert_eq!(formatted, format(&formatted, &MarkdownOptions::new()));
+        }
+    }
+
+    #[test]
+    fn normalizes_style() {
+        let src = "Title\n===\n\n* salt\n* pepper\n\n3) three\n3) four\n";
+
+        assert_eq!(
+            format(src, &MarkdownOptions::new()),
+            "# Title\n\n- salt\n- pepper\n\n3. three\n4. four\n"
+        );
+
+        let options = MarkdownOptions::new()
+            .bullet(Bullet::Star)
+            .delimiter(Delimiter::Paren)
+            .numbering(Numbering::Repeated)
+            .headings(HeadingStyle::Setext);
+
+        assert_eq!(
+            format(src, &options),
+            "Title\n=====\n\n* salt\n* pepper\n\n3) three\n3) four\n"
+        );
+    }
+
+    #[test]
+    fn adjacent_lists_stay_apart() {
+        let src = "- salt\n+ pepper\n";
+        let formatted = format(src, &MarkdownOptions::new());
+
+        assert_eq!(formatted, "- salt\n\n* pepper\n");
+        assert_eq!(shape(src), shape(&formatted));
+    }
+
+    #[test]
+    fn loose_lists() {
+        assert_eq!(
+            format("- one\n\n- two\n", &MarkdownOptions::new()),
+            "- one\n\n- two\n"
+        );
+        assert_eq!(
+            format("- one\n- two\n", &MarkdownOptions::new()),
+            "- one\n- two\n"
+        );
+    }
+
+    #[test]
+    fn escapes_text() {
+        let src = "Salt\n  > water\n";
+        assert_eq!(format(src, &MarkdownOptions::new()), "Salt\n\\> water\n");
+
+        // source text is Markdown already
+        let src = "The *salty*, _deep_ sea.\n";
+        assert_eq!(format(src, &MarkdownOptions::new()), src);
+
+        // owned text is taken literally
+        let mut tree = TreeArena::new();
+        let paragraph = tree
+            .attach_node(AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        tree.append_child(
+            paragraph,
+            AstNode::with_text(Value::Text, "# *salt* [and] water_\n- sea\n12. sea"),
+        )
+        .unwrap();
+
+        let mut out = String::new();
+        push_markdown(&mut out, &tree, "", &MarkdownOptions::new());
+        assert_eq!(
+            out,
+            "\\# \\*salt\\* \\[and\\] water\\_\n\\- sea\n12\\. sea\n"
+        );
+    }
+
+    #[test]
+    fn macros_keep_their_arguments() {
+        let src = "<>= note (kind = warning)   (\nSalt.\n\n)\n";
+
+        assert_eq!(
+            format(src, &MarkdownOptions::new()),
+            "<>= note (kind = warning)   (\nSalt.\n\n)\n"
+        );
+    }
+
+    #[test]
+    fn code_fences() {
+        let mut tree = TreeArena::new();
+        let meta = CodeMeta::new(Lang::Rust, Some("ignore".to_string()));
+        code_block(&mut tree, None, Some(meta), "let s = \"```\";\n");
+
+        let mut out = String::new();
+        push_markdown(&mut out, &tree, "", &MarkdownOptions::new());
+        assert_eq!(out, "````rust ignore\nlet s = \"```\";\n````\n");
+
+        out.clear();
+        push_markdown(
+            &mut out,
+            &tree,
+            "",
+            &MarkdownOptions::new().fence(Fence::Tilde),
+        );
+        assert_eq!(out, "~~~rust ignore\nlet s = \"```\";\n~~~\n");
+    }
+
+    #[test]
+    fn links_keep_their_destination() {
+        let mut tree = TreeArena::new();
+        let paragraph = tree
+            .attach_node(AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        let link = tree
+            .append_child(
+                paragraph,
+                AstNode::with_text(Value::Link, "[the docs](<docs/salt.md> \"Salt\")"),
+            )
+            .unwrap();
+        tree.append_child(link, AstNode::with_text(Value::Text, "the docs"))
+            .unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::Text, " and "))
+            .unwrap();
+        let image = tree
+            .append_child(
+                paragraph,
+                AstNode::with_text(Value::Image, "![a map](map.png)"),
+            )
+            .unwrap();
+        tree.append_child(image, AstNode::with_text(Value::Text, "a map"))
+            .unwrap();
+        let generated = tree
+            .append_child(paragraph, AstNode::with_text(Value::Link, ""))
+            .unwrap();
+        tree.append_child(generated, AstNode::with_text(Value::Text, " salt"))
+            .unwrap();
+
+        let mut out = String::new();
+        push_markdown(&mut out, &tree, "", &MarkdownOptions::new());
+        assert_eq!(
+            out,
+            "[the docs](docs/salt.md) and ![a map](map.png)[ salt]()\n"
+        );
+    }
+}
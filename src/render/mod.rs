@@ -0,0 +1,210 @@
+//! Renderers turning a parsed document into other formats.
+
+pub mod ansi;
+pub mod custom;
+pub mod gemini;
+pub mod html;
+pub mod latex;
+pub mod markdown;
+pub mod roff;
+pub mod text;
+pub mod xml;
+
+use crate::ast::AstNode;
+use crate::lib::String;
+use crate::source::Sources;
+use crate::tree::{Control, NodeId, TreeArena, TreeVisitor};
+use core::fmt;
+
+/// Writes the nodes of a tree walked by `write_tree`.
+pub(crate) trait NodeWriter {
+    /// Writes the start of `node`, telling how the walk goes on.
+    fn enter_node(
+        &mut self,
+        id: NodeId,
+        node: &AstNode,
+        depth: usize,
+    ) -> Result<Control, fmt::Error>;
+
+    /// Writes the end of `node`, after its children.
+    fn exit_node(&mut self, id: NodeId, node: &AstNode, depth: usize) -> fmt::Result;
+}
+
+/// Walks `tree`, or only the subtree of `root`, with `writer`
+/// up to the first error.
+pub(crate) fn write_tree<N: NodeWriter>(
+    tree: &TreeArena<AstNode>,
+    root: Option<NodeId>,
+    writer: &mut N,
+) -> fmt::Result {
+    let mut walk = Walk {
+        writer,
+        result: Ok(()),
+    };
+
+    match root {
+        Some(id) => tree.walk_subtree(id, &mut walk),
+        None => tree.walk(&mut walk),
+    }
+
+    walk.result
+}
+
+// visits a tree for a `NodeWriter`
+struct Walk<'w, N> {
+    writer: &'w mut N,
+
+    /// First error hit while writing, which stops the walk.
+    result: fmt::Result,
+}
+
+impl<N: NodeWriter> TreeVisitor for Walk<'_, N> {
+    fn enter(&mut self, id: NodeId, node: &AstNode, depth: usize) -> Control {
+        if self.result.is_err() {
+            return Control::Stop;
+        }
+
+        match self.writer.enter_node(id, node, depth) {
+            Ok(control) => control,
+            Err(error) => {
+                self.result = Err(error);
+                Control::Stop
+            }
+        }
+    }
+
+    fn exit(&mut self, id: NodeId, node: &AstNode, depth: usize) {
+        if self.result.is_ok() {
+            self.result = self.writer.exit_node(id, node, depth);
+        }
+    }
+}
+
+// strips the indentation and up to `quotes`
+// blockquote markers from the start of a lazy line
+fn strip_markers(mut line: &str, quotes: usize) -> &str {
+    line = line.trim_start_matches([' ', '\t']);
+
+    for _ in 0..quotes {
+        match line.strip_prefix('>') {
+            Some(rest) => line = rest.trim_start_matches([' ', '\t']),
+            None => break,
+        }
+    }
+
+    line
+}
+
+// splits `text` into its words, along with whether whitespace comes
+// before each of them, leaving out the lazy blockquote markers of its
+// lines; whitespace at the end is an empty word, so it carries over
+// to the word after `text`
+pub(crate) fn words(text: &str, quotes: usize) -> impl Iterator<Item = (&str, bool)> {
+    text.split('\n').enumerate().flat_map(move |(ix, line)| {
+        let line = if ix > 0 {
+            strip_markers(line, quotes)
+        } else {
+            line
+        };
+
+        let leading = ix > 0 || line.starts_with(char::is_whitespace);
+        let trailing = line.is_empty() || line.ends_with(char::is_whitespace);
+
+        line.split_whitespace()
+            .enumerate()
+            .map(move |(n, word)| (word, n > 0 || leading))
+            .chain(trailing.then_some(("", true)))
+    })
+}
+
+// concatenates the text of the children of `id`,
+// like the lines of a code block
+pub(crate) fn children_text<S>(tree: &TreeArena<AstNode>, id: NodeId, sources: &S) -> String
+where
+    S: Sources + ?Sized,
+{
+    let mut text = String::new();
+
+    for child in tree.children(id) {
+        if let Some(child) = tree.get(child) {
+            text.push_str(child.data.as_str(sources));
+        }
+    }
+
+    text
+}
+
+// reads the delimiter after the number of the first item
+// of an ordered list from its source, `.` or `)`
+pub(crate) fn list_delimiter(text: &str) -> Option<char> {
+    let marker = text
+        .trim_start()
+        .trim_start_matches(|ch: char| ch.is_ascii_digit());
+
+    marker.chars().next().filter(|ch| matches!(ch, '.' | ')'))
+}
+
+// reads the destination of a link or image from its
+// source, `[text](destination "title")`, if it has one
+pub(crate) fn link_destination(text: &str) -> Option<&str> {
+    let inner = text.trim_end().strip_suffix(')')?;
+    let start = inner.rfind("](")? + 2;
+
+    let destination = inner[start..].trim();
+    let destination = destination
+        .split_once(char::is_whitespace)
+        .map_or(destination, |(destination, _title)| destination);
+    let destination = destination
+        .strip_prefix('<')
+        .and_then(|destination| destination.strip_suffix('>'))
+        .unwrap_or(destination);
+
+    (!destination.is_empty()).then_some(destination)
+}
+
+// reads the title of a link or image from its
+// source, `[text](destination "title")`, if it has one
+pub(crate) fn link_title(text: &str) -> Option<&str> {
+    let inner = text.trim_end().strip_suffix(')')?;
+    let start = inner.rfind("](")? + 2;
+
+    let (_destination, title) = inner[start..].trim().split_once(char::is_whitespace)?;
+    let title = title.trim_start();
+
+    let mut quotes = title.chars();
+    match (quotes.next()?, quotes.next_back()?) {
+        ('"', '"') | ('\'', '\'') => Some(quotes.as_str()),
+
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::ast::{AstNode, CodeMeta, Value};
+    use crate::tree::{Anchor, NodeId, TreeArena};
+
+    /// Builds a code block holding `code` without a source, placed at
+    /// `anchor` or attached after the current node if there is none.
+    pub(crate) fn code_block(
+        tree: &mut TreeArena<AstNode>,
+        anchor: Option<Anchor>,
+        meta: Option<CodeMeta>,
+        code: &str,
+    ) -> NodeId {
+        let node = AstNode::with_text(Value::Code { lang: None, meta }, "");
+        let id = match anchor {
+            Some(anchor) => {
+                let id = tree.new_node(node).unwrap();
+                assert!(tree.move_subtree(id, anchor));
+                id
+            }
+            None => tree.attach_node(node).unwrap(),
+        };
+
+        tree.append_child(id, AstNode::with_text(Value::Text, code))
+            .unwrap();
+
+        id
+    }
+}
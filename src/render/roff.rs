@@ -0,0 +1,534 @@
+//! roff rendering of a parsed document, for man pages.
+//!
+//! The first level 1 heading becomes the `.TH` title line, further
+//! level 1 and level 2 headings become `.SH` sections and deeper
+//! ones `.SS` subsections. Paragraphs start with `.PP`, list items
+//! with `.IP` and code blocks are set between `.nf` and `.fi`.
+//! Blockquotes and nested lists are indented with `.RS`/`.RE`.
+//! Raw HTML has no place in a man page and is left out.
+
+use super::text::{TextOptions, push_subtree_text};
+use super::{NodeWriter, children_text, strip_markers, write_tree};
+use crate::ast::{AstNode, Value};
+use crate::lib::{String, Vec};
+use crate::source::Sources;
+use crate::tree::{Control, NodeId, TreeArena};
+use core::fmt::{self, Write};
+
+/// Options for rendering a man page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoffOptions<'a> {
+    section: &'a str,
+    date: &'a str,
+    source: &'a str,
+    manual: &'a str,
+}
+
+impl Default for RoffOptions<'_> {
+    fn default() -> Self {
+        Self {
+            section: "1",
+            date: "",
+            source: "",
+            manual: "",
+        }
+    }
+}
+
+impl<'a> RoffOptions<'a> {
+    /// Returns the default options, for a page in section 1.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the manual section of the page, like `1` or `3p`.
+    #[must_use]
+    pub fn section(mut self, section: &'a str) -> Self {
+        self.section = section;
+        self
+    }
+
+    /// Sets the date the page was last changed.
+    #[must_use]
+    pub fn date(mut self, date: &'a str) -> Self {
+        self.date = date;
+        self
+    }
+
+    /// Sets the source of the page, usually the name
+    /// and version of the documented program.
+    #[must_use]
+    pub fn source(mut self, source: &'a str) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Sets the title of the manual the page belongs to.
+    #[must_use]
+    pub fn manual(mut self, manual: &'a str) -> Self {
+        self.manual = manual;
+        self
+    }
+}
+
+/// Renders `tree` as a man page, appending it to `out`.
+pub fn push_roff<S>(
+    out: &mut String,
+    tree: &TreeArena<AstNode>,
+    sources: &S,
+    options: &RoffOptions<'_>,
+) where
+    S: Sources + ?Sized,
+{
+    write_roff(out, tree, sources, options).expect("writing to a `String` is infallible");
+}
+
+/// Renders `tree` as a man page into `out`.
+///
+/// # Errors
+/// if writing to `out` fails.
+pub fn write_roff<W, S>(
+    out: W,
+    tree: &TreeArena<AstNode>,
+    sources: &S,
+    options: &RoffOptions<'_>,
+) -> fmt::Result
+where
+    W: Write,
+    S: Sources + ?Sized,
+{
+    let mut writer = RoffWriter {
+        out,
+        tree,
+        sources,
+        options,
+        titled: false,
+        line_start: true,
+        fonts: Vec::new(),
+        lists: Vec::new(),
+        items: Vec::new(),
+        quotes: 0,
+    };
+
+    write_tree(tree, None, &mut writer)
+}
+
+/// Font selected by emphasis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Font {
+    Roman,
+    Bold,
+    Italic,
+    BoldItalic,
+}
+
+impl Font {
+    fn escape(self) -> &'static str {
+        match self {
+            Self::Roman => "\\fR",
+            Self::Bold => "\\fB",
+            Self::Italic => "\\fI",
+            Self::BoldItalic => "\\f(BI",
+        }
+    }
+
+    fn with(self, strong: bool) -> Self {
+        match (self, strong) {
+            (Self::Roman | Self::Bold, true) => Self::Bold,
+            (Self::Roman | Self::Italic, false) => Self::Italic,
+
+            _ => Self::BoldItalic,
+        }
+    }
+}
+
+struct RoffWriter<'t, W, S: ?Sized> {
+    out: W,
+    tree: &'t TreeArena<AstNode>,
+    sources: &'t S,
+    options: &'t RoffOptions<'t>,
+
+    /// Whether the `.TH` line was written.
+    titled: bool,
+
+    /// Whether nothing was written on the current line.
+    line_start: bool,
+
+    /// Fonts of the open emphasis nodes.
+    fonts: Vec<Font>,
+
+    /// Next number of the open lists, `None` for bullet lists.
+    lists: Vec<Option<u64>>,
+
+    /// Whether the open list items are still waiting for their first block.
+    items: Vec<bool>,
+
+    quotes: usize,
+}
+
+impl<W: Write, S: Sources + ?Sized> RoffWriter<'_, W, S> {
+    // writes the `.TH` line, titled by the text of the heading
+    fn title(&mut self, id: NodeId) -> fmt::Result {
+        let mut title = String::new();
+        push_subtree_text(&mut title, self.tree, id, self.sources, &TextOptions::new());
+
+        self.end_line()?;
+        self.out.write_str(".TH")?;
+
+        let options = self.options;
+        let fields = [
+            title.as_str(),
+            options.section,
+            options.date,
+            options.source,
+            options.manual,
+        ];
+
+        // trailing empty fields are left out
+        let used = fields
+            .iter()
+            .rposition(|field| !field.is_empty())
+            .map_or(0, |last| last + 1);
+
+        for field in &fields[..used] {
+            self.out.write_str(" \"")?;
+            escape(&mut self.out, field, true)?;
+            self.out.write_char('"')?;
+        }
+
+        self.out.write_char('\n')?;
+        self.titled = true;
+
+        Ok(())
+    }
+
+    // starts a paragraph like block
+    fn block(&mut self) -> fmt::Result {
+        self.end_line()?;
+
+        match self.items.last_mut() {
+            // the first block of an item follows its tag
+            Some(first @ true) => {
+                *first = false;
+                Ok(())
+            }
+
+            // following blocks keep the indentation of the item
+            Some(false) => self.out.write_str(".IP\n"),
+            None => self.out.write_str(".PP\n"),
+        }
+    }
+
+    fn item(&mut self) -> fmt::Result {
+        self.end_line()?;
+
+        match self.lists.last_mut() {
+            Some(Some(number)) => {
+                let tag = *number;
+                *number = number.saturating_add(1);
+
+                let indent = tag.checked_ilog10().map_or(1, |log| log as usize + 1) + 2;
+                writeln!(self.out, ".IP \"{tag}.\" {}", indent.max(4))?;
+            }
+            _ => self.out.write_str(".IP \"\\(bu\" 2\n")?,
+        }
+
+        self.items.push(true);
+        Ok(())
+    }
+
+    fn code(&mut self, id: NodeId) -> fmt::Result {
+        let code = children_text(self.tree, id, self.sources);
+
+        self.block()?;
+        self.out.write_str(".nf\n")?;
+
+        for line in code.trim_matches('\n').split('\n') {
+            self.escaped(line)?;
+            self.end_line()?;
+        }
+
+        self.out.write_str(".fi\n")
+    }
+
+    // writes the lines of `text`, without the indentation roff
+    // would take as a break and the lazy markers of blockquotes
+    fn text(&mut self, text: &str) -> fmt::Result {
+        for (ix, line) in text.split('\n').enumerate() {
+            let line = if ix > 0 {
+                self.end_line()?;
+                strip_markers(line, self.quotes)
+            } else if self.line_start {
+                line.trim_start()
+            } else {
+                line
+            };
+
+            self.escaped(line)?;
+        }
+
+        Ok(())
+    }
+
+    fn escaped(&mut self, text: &str) -> fmt::Result {
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        // control characters are only special at the start of a line
+        if self.line_start && text.starts_with(['.', '\'']) {
+            self.out.write_str("\\&")?;
+        }
+
+        self.line_start = false;
+        escape(&mut self.out, text, false)
+    }
+
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.line_start = false;
+        self.out.write_str(text)
+    }
+
+    fn end_line(&mut self) -> fmt::Result {
+        if !self.line_start {
+            self.line_start = true;
+            self.out.write_char('\n')?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write, S: Sources + ?Sized> NodeWriter for RoffWriter<'_, W, S> {
+    fn enter_node(&mut self, id: NodeId, node: &AstNode, _: usize) -> Result<Control, fmt::Error> {
+        match &node.value {
+            Value::Heading { level } if level.get() == 1 && !self.titled => {
+                self.title(id)?;
+                return Ok(Control::SkipChildren);
+            }
+            Value::Heading { level } => {
+                self.end_line()?;
+                self.out
+                    .write_str(if level.get() <= 2 { ".SH\n" } else { ".SS\n" })?;
+            }
+            Value::Paragraph => self.block()?,
+            Value::Blockquote => {
+                self.end_line()?;
+                self.out.write_str(".RS\n")?;
+                self.quotes += 1;
+            }
+            Value::BulletList { .. } | Value::OrderedList { .. } => {
+                // nested lists are indented below their parent item
+                if !self.lists.is_empty() {
+                    self.end_line()?;
+                    self.out.write_str(".RS\n")?;
+                }
+
+                self.lists.push(match node.value {
+                    Value::OrderedList { start_index, .. } => Some(start_index),
+                    _ => None,
+                });
+            }
+            Value::ListItem => self.item()?,
+            Value::Code { .. } => {
+                self.code(id)?;
+                return Ok(Control::SkipChildren);
+            }
+            Value::HtmlBlock => return Ok(Control::SkipChildren),
+            Value::StyleBreak => {
+                self.end_line()?;
+                self.out.write_str(".sp\n")?;
+            }
+
+            Value::Emphasis { strong } => {
+                let font = self.fonts.last().copied().unwrap_or(Font::Roman);
+                let font = font.with(*strong);
+
+                self.fonts.push(font);
+                self.write_str(font.escape())?;
+            }
+            Value::Text | Value::Emoji => self.text(node.as_str(self.sources))?,
+            Value::EscapeChar(ch) => self.escaped(ch.encode_utf8(&mut [0; 4]))?,
+            Value::HardBreak => {
+                self.end_line()?;
+                self.out.write_str(".br\n")?;
+            }
+            Value::SoftBreak => self.end_line()?,
+
+            Value::Root
+            | Value::Macro { .. }
+            | Value::Link
+            | Value::Image
+            | Value::Strikethrough => (),
+        }
+
+        Ok(Control::Continue)
+    }
+
+    fn exit_node(&mut self, _: NodeId, node: &AstNode, _: usize) -> fmt::Result {
+        match &node.value {
+            Value::Emphasis { .. } => {
+                self.fonts.pop();
+                let font = self.fonts.last().copied().unwrap_or(Font::Roman);
+                self.write_str(font.escape())
+            }
+            Value::Heading { .. } | Value::Paragraph => self.end_line(),
+            Value::Blockquote => {
+                self.quotes -= 1;
+                self.end_line()?;
+                self.out.write_str(".RE\n")
+            }
+            Value::BulletList { .. } | Value::OrderedList { .. } => {
+                self.lists.pop();
+
+                if self.lists.is_empty() {
+                    return Ok(());
+                }
+
+                self.end_line()?;
+                self.out.write_str(".RE\n")
+            }
+            Value::ListItem => {
+                self.items.pop();
+                self.end_line()
+            }
+
+            _ => Ok(()),
+        }
+    }
+}
+
+// escapes backslashes and hyphens, and
+// double quotes inside of quoted arguments
+fn escape<W: Write>(out: &mut W, text: &str, quoted: bool) -> fmt::Result {
+    let mut rest = text;
+
+    while let Some(ix) = rest.find(['\\', '-', '"']) {
+        out.write_str(&rest[..ix])?;
+
+        match rest.as_bytes()[ix] {
+            b'\\' => out.write_str("\\e")?,
+            b'-' => out.write_str("\\-")?,
+            _ if quoted => out.write_str("\\(dq")?,
+            _ => out.write_char('"')?,
+        }
+
+        rest = &rest[ix + 1..];
+    }
+
+    out.write_str(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RoffOptions, push_roff};
+    use crate::ast::{AstNode, Value};
+    use crate::block_parser::parse;
+    use crate::lib::String;
+    use crate::render::tests::code_block;
+    use crate::tree::Anchor;
+    use crate::tree::TreeArena;
+
+    fn render(src: &str, options: &RoffOptions<'_>) -> String {
+        let mut out = String::new();
+        push_roff(&mut out, &parse(src), src, options);
+
+        out
+    }
+
+    #[test]
+    fn page() {
+        let src = "# noumea-cli\n";
+        let options = RoffOptions::new()
+            .section("1")
+            .date("2024-01-01")
+            .source("noumea 0.1");
+
+        assert_eq!(
+            render(src, &options),
+            ".TH \"noumea\\-cli\" \"1\" \"2024\\-01\\-01\" \"noumea 0.1\"\n"
+        );
+        assert_eq!(
+            render(
+                "Some text\n.  with a dot\n'and a quote\\ \"here\"\n",
+                &options
+            ),
+            ".PP\nSome text\n\\&.  with a dot\n\\&'and a quote\\e \"here\"\n"
+        );
+    }
+
+    #[test]
+    fn lists_and_code() {
+        assert_eq!(
+            render("* one\n* two\n  - nested\n", &RoffOptions::new()),
+            concat!(
+                ".IP \"\\(bu\" 2\n",
+                "one\n",
+                ".IP \"\\(bu\" 2\n",
+                "two\n",
+                ".RS\n",
+                ".IP \"\\(bu\" 2\n",
+                "nested\n",
+                ".RE\n",
+            )
+        );
+        assert_eq!(
+            render("9. nine\n10. ten\n", &RoffOptions::new()),
+            ".IP \"9.\" 4\nnine\n.IP \"10.\" 4\nten\n"
+        );
+
+        let mut tree = TreeArena::new();
+        let heading = tree
+            .attach_node(AstNode::with_text(
+                Value::Heading {
+                    level: 2.try_into().unwrap(),
+                },
+                "",
+            ))
+            .unwrap();
+        tree.append_child(heading, AstNode::with_text(Value::Text, "EXAMPLES"))
+            .unwrap();
+        let code = code_block(
+            &mut tree,
+            Some(Anchor::After(heading)),
+            None,
+            ".start\n  -v\n",
+        );
+        let paragraph = tree
+            .insert_after(code, AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        let strong = tree
+            .append_child(
+                paragraph,
+                AstNode::with_text(Value::Emphasis { strong: true }, ""),
+            )
+            .unwrap();
+        let emphasis = tree
+            .append_child(
+                strong,
+                AstNode::with_text(Value::Emphasis { strong: false }, ""),
+            )
+            .unwrap();
+        tree.append_child(emphasis, AstNode::with_text(Value::Text, "both"))
+            .unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::Text, " done"))
+            .unwrap();
+
+        let mut out = String::new();
+        push_roff(&mut out, &tree, "", &RoffOptions::new());
+        assert_eq!(
+            out,
+            concat!(
+                ".SH\n",
+                "EXAMPLES\n",
+                ".PP\n",
+                ".nf\n",
+                "\\&.start\n",
+                "  \\-v\n",
+                ".fi\n",
+                ".PP\n",
+                "\\fB\\f(BIboth\\fB\\fR done\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,396 @@
+//! Plain text rendering of a parsed document.
+//!
+//! Meant for summaries and search snippets: formatting is dropped,
+//! runs of whitespace collapse into single spaces, blocks are
+//! separated by blank lines and list items end up on lines of
+//! their own. Links and images keep their text, HTML is left out.
+
+use super::{NodeWriter, words, write_tree};
+use crate::ast::{AstNode, Value};
+use crate::lib::String;
+use crate::source::Sources;
+use crate::tree::{Control, NodeId, TreeArena};
+use core::fmt::{self, Write};
+
+/// Marks the end of truncated text.
+pub const ELLIPSIS: char = '…';
+
+/// Options for rendering plain text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TextOptions {
+    truncate: Option<usize>,
+}
+
+impl TextOptions {
+    /// Returns the default options, which keep the whole text.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Truncates the text to at most `chars` characters.
+    ///
+    /// Text is cut at a word boundary, or inside of a first word
+    /// which is too long, and ends with an `ELLIPSIS` counting
+    /// towards the `chars`.
+    #[must_use]
+    pub fn truncate(mut self, chars: usize) -> Self {
+        self.truncate = Some(chars);
+        self
+    }
+}
+
+/// Renders `tree` as plain text, appending it to `out`.
+pub fn push_text<S>(out: &mut String, tree: &TreeArena<AstNode>, sources: &S, options: &TextOptions)
+where
+    S: Sources + ?Sized,
+{
+    write_text(out, tree, sources, options).expect("writing to a `String` is infallible");
+}
+
+/// Renders `tree` as plain text into `out`.
+///
+/// # Errors
+/// if writing to `out` fails.
+pub fn write_text<W, S>(
+    out: W,
+    tree: &TreeArena<AstNode>,
+    sources: &S,
+    options: &TextOptions,
+) -> fmt::Result
+where
+    W: Write,
+    S: Sources + ?Sized,
+{
+    render(out, tree, None, sources, options)
+}
+
+/// Renders the node `id` and its descendants
+/// as plain text, appending it to `out`.
+pub fn push_subtree_text<S>(
+    out: &mut String,
+    tree: &TreeArena<AstNode>,
+    id: NodeId,
+    sources: &S,
+    options: &TextOptions,
+) where
+    S: Sources + ?Sized,
+{
+    write_subtree_text(out, tree, id, sources, options)
+        .expect("writing to a `String` is infallible");
+}
+
+/// Renders the node `id` and its descendants as plain text into `out`.
+///
+/// # Errors
+/// if writing to `out` fails.
+pub fn write_subtree_text<W, S>(
+    out: W,
+    tree: &TreeArena<AstNode>,
+    id: NodeId,
+    sources: &S,
+    options: &TextOptions,
+) -> fmt::Result
+where
+    W: Write,
+    S: Sources + ?Sized,
+{
+    render(out, tree, Some(id), sources, options)
+}
+
+fn render<W, S>(
+    mut out: W,
+    tree: &TreeArena<AstNode>,
+    root: Option<NodeId>,
+    sources: &S,
+    options: &TextOptions,
+) -> fmt::Result
+where
+    W: Write,
+    S: Sources + ?Sized,
+{
+    let Some(limit) = options.truncate else {
+        return TextWriter::new(out, tree, sources, None).run(root);
+    };
+
+    // the text is cut afterwards, so that
+    // the word at the limit can be checked
+    let mut text = String::new();
+    TextWriter::new(&mut text, tree, sources, Some(limit)).run(root)?;
+
+    if text.chars().nth(limit).is_none() {
+        return out.write_str(&text);
+    }
+
+    // leave room for the ellipsis
+    let Some((cut, _)) = limit
+        .checked_sub(1)
+        .and_then(|keep| text.char_indices().nth(keep))
+    else {
+        return Ok(());
+    };
+
+    // a word ends at the cut when whitespace follows it
+    let head = &text[..cut];
+    let head = if text[cut..].starts_with(char::is_whitespace) {
+        head
+    } else {
+        head.rfind(char::is_whitespace)
+            .map_or(head, |boundary| &head[..boundary])
+    };
+
+    out.write_str(head.trim_end())?;
+    out.write_char(ELLIPSIS)
+}
+
+/// Separator written before the next content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Gap {
+    None,
+    Line,
+    Blank,
+}
+
+struct TextWriter<'t, W, S: ?Sized> {
+    out: W,
+    tree: &'t TreeArena<AstNode>,
+    sources: &'t S,
+
+    /// Characters after which the walk stops.
+    limit: Option<usize>,
+
+    /// Characters written so far.
+    written: usize,
+
+    gap: Gap,
+
+    /// Whether a space goes before the next content on the line.
+    space: bool,
+
+    /// Whether the current line has content.
+    line: bool,
+
+    lists: usize,
+    quotes: usize,
+}
+
+impl<'t, W: Write, S: Sources + ?Sized> TextWriter<'t, W, S> {
+    fn new(out: W, tree: &'t TreeArena<AstNode>, sources: &'t S, limit: Option<usize>) -> Self {
+        Self {
+            out,
+            tree,
+            sources,
+            limit,
+            written: 0,
+            gap: Gap::None,
+            space: false,
+            line: false,
+            lists: 0,
+            quotes: 0,
+        }
+    }
+
+    fn run(mut self, root: Option<NodeId>) -> fmt::Result {
+        write_tree(self.tree, root, &mut self)
+    }
+
+    // writes `text` with its whitespace collapsed,
+    // without the lazy blockquote markers of its lines
+    fn words(&mut self, text: &str) -> fmt::Result {
+        for (word, space) in words(text, self.quotes) {
+            self.space |= space;
+
+            if !word.is_empty() {
+                self.content(word)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // writes the lines of a code block as they are
+    fn code(&mut self, id: NodeId) -> fmt::Result {
+        for child in self.tree.children(id) {
+            let Some(child) = self.tree.get(child) else {
+                continue;
+            };
+
+            let code = child.data.as_str(self.sources).trim_matches('\n');
+            if !code.is_empty() {
+                self.separate(Gap::Line);
+                self.content(code)?;
+            }
+        }
+
+        self.separate(if self.lists > 0 {
+            Gap::Line
+        } else {
+            Gap::Blank
+        });
+        Ok(())
+    }
+
+    fn content(&mut self, text: &str) -> fmt::Result {
+        if self.written > 0 {
+            match self.gap {
+                Gap::Blank => self.write("\n\n")?,
+                Gap::Line => self.write("\n")?,
+                Gap::None if self.space && self.line => self.write(" ")?,
+                Gap::None => (),
+            }
+        }
+
+        self.gap = Gap::None;
+        self.space = false;
+        self.line = true;
+        self.write(text)
+    }
+
+    fn write(&mut self, text: &str) -> fmt::Result {
+        self.written += text.chars().count();
+        self.out.write_str(text)
+    }
+
+    fn separate(&mut self, gap: Gap) {
+        self.gap = self.gap.max(gap);
+        self.space = false;
+        self.line = false;
+    }
+}
+
+impl<W: Write, S: Sources + ?Sized> NodeWriter for TextWriter<'_, W, S> {
+    fn enter_node(&mut self, id: NodeId, node: &AstNode, _: usize) -> Result<Control, fmt::Error> {
+        if self.limit.is_some_and(|limit| self.written > limit) {
+            return Ok(Control::Stop);
+        }
+
+        match &node.value {
+            Value::BulletList { .. } | Value::OrderedList { .. } => self.lists += 1,
+            Value::Blockquote => self.quotes += 1,
+            Value::HtmlBlock => return Ok(Control::SkipChildren),
+            Value::Code { .. } => {
+                self.code(id)?;
+                return Ok(Control::SkipChildren);
+            }
+            Value::Text | Value::Emoji => self.words(node.as_str(self.sources))?,
+            Value::EscapeChar(ch) => self.content(ch.encode_utf8(&mut [0; 4]))?,
+            Value::HardBreak => self.separate(Gap::Line),
+            Value::SoftBreak => self.space = true,
+
+            _ => (),
+        }
+
+        Ok(Control::Continue)
+    }
+
+    fn exit_node(&mut self, _: NodeId, node: &AstNode, _: usize) -> fmt::Result {
+        match &node.value {
+            Value::BulletList { .. } | Value::OrderedList { .. } => self.lists -= 1,
+            Value::Blockquote => self.quotes -= 1,
+            Value::Root
+            | Value::Macro { .. }
+            | Value::Paragraph
+            | Value::StyleBreak
+            | Value::Heading { .. }
+            | Value::ListItem => (),
+
+            // inlines run on
+            _ => return Ok(()),
+        }
+
+        self.separate(if self.lists > 0 {
+            Gap::Line
+        } else {
+            Gap::Blank
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TextOptions, push_subtree_text, push_text};
+    use crate::ast::{AstNode, Value};
+    use crate::block_parser::parse;
+    use crate::lib::String;
+    use crate::render::tests::code_block;
+    use crate::tree::Anchor;
+    use crate::tree::TreeArena;
+
+    fn render(src: &str, options: &TextOptions) -> String {
+        let mut out = String::new();
+        push_text(&mut out, &parse(src), src, options);
+
+        out
+    }
+
+    #[test]
+    fn blocks() {
+        let src = "> quoted\nlazy\n>  and\n\n# Salt\n\nThe sea\n  is   salty.\n\n1. one\n2. two\n   lines\n";
+
+        assert_eq!(
+            render(src, &TextOptions::new()),
+            "quoted lazy and\n\nSalt\n\nThe sea is salty.\n\none\ntwo lines"
+        );
+    }
+
+    #[test]
+    fn inlines() {
+        let mut tree = TreeArena::new();
+        let paragraph = tree
+            .attach_node(AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        let text = |text: &str| AstNode::with_text(Value::Text, text);
+
+        tree.append_child(paragraph, text("See ")).unwrap();
+        let link = tree
+            .append_child(paragraph, AstNode::with_text(Value::Link, ""))
+            .unwrap();
+        tree.append_child(link, text("the docs")).unwrap();
+        let emphasis = tree
+            .append_child(
+                paragraph,
+                AstNode::with_text(Value::Emphasis { strong: true }, ""),
+            )
+            .unwrap();
+        tree.append_child(emphasis, text("now")).unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::EscapeChar('!'), ""))
+            .unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::HardBreak, ""))
+            .unwrap();
+        tree.append_child(paragraph, text("Thanks")).unwrap();
+
+        let html = tree
+            .insert_after(paragraph, AstNode::with_text(Value::HtmlBlock, "<hr>"))
+            .unwrap();
+        code_block(
+            &mut tree,
+            Some(Anchor::After(html)),
+            None,
+            "let  x = 1;\nx\n",
+        );
+
+        let mut out = String::new();
+        push_text(&mut out, &tree, "", &TextOptions::new());
+        assert_eq!(out, "See the docsnow!\nThanks\n\nlet  x = 1;\nx");
+
+        out.clear();
+        push_subtree_text(&mut out, &tree, link, "", &TextOptions::new());
+        assert_eq!(out, "the docs");
+    }
+
+    #[test]
+    fn truncation() {
+        let src = "Salt water is\nsalty.\n\nFresh water isn't.";
+        let truncate = |chars| render(src, &TextOptions::new().truncate(chars));
+
+        assert_eq!(truncate(100), "Salt water is salty.\n\nFresh water isn't.");
+        assert_eq!(truncate(40), "Salt water is salty.\n\nFresh water isn't.");
+        assert_eq!(truncate(39), "Salt water is salty.\n\nFresh water…");
+        assert_eq!(truncate(14), "Salt water is…");
+        assert_eq!(truncate(13), "Salt water…");
+        assert_eq!(truncate(3), "Sa…");
+        assert_eq!(truncate(1), "…");
+        assert_eq!(truncate(0), "");
+    }
+}
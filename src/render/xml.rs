@@ -0,0 +1,569 @@
+//! CommonMark XML rendering of a parsed document.
+//!
+//! Follows the `CommonMark.dtd` used by the `cmark` reference
+//! implementation, so trees can be diffed against its output
+//! (`cmark -t xml --sourcepos`). Nodes the DTD has no element for
+//! use extension elements in the `noumea` namespace, e.g.
+//!
+//! ```xml
+//! <noumea:macro name="note" sourcepos="1:1-3:1">
+//! ```
+//!
+//! Strikethrough uses the `strikethrough` element of `cmark-gfm`.
+
+use super::html::escape;
+use super::{NodeWriter, children_text, link_destination, link_title, list_delimiter, write_tree};
+use crate::ast::{AstNode, Position, SourceId, TextSource, Value};
+use crate::lib::{String, Vec};
+use crate::source::Sources;
+use crate::tree::{Control, NodeId, TreeArena};
+use core::fmt::{self, Write};
+
+/// Namespace of the CommonMark XML elements.
+pub const NAMESPACE: &str = "http://commonmark.org/xml/1.0";
+
+/// Namespace of the extension elements.
+pub const EXTENSION_NAMESPACE: &str = "urn:noumea:xml:1.0";
+
+/// Renders `tree` as CommonMark XML, appending it to `out`.
+///
+/// The given `sources` should hold the data from which
+/// the `tree` was parsed, they are used for the text and
+/// the `sourcepos` attributes of the nodes.
+pub fn push_xml<S>(out: &mut String, tree: &TreeArena<AstNode>, sources: &S)
+where
+    S: Sources + ?Sized,
+{
+    write_xml(out, tree, sources).expect("writing to a `String` is infallible");
+}
+
+/// Renders `tree` as CommonMark XML into `out`.
+///
+/// # Errors
+/// if writing to `out` fails.
+pub fn write_xml<W, S>(out: W, tree: &TreeArena<AstNode>, sources: &S) -> fmt::Result
+where
+    W: Write,
+    S: Sources + ?Sized,
+{
+    let mut writer = XmlWriter {
+        out,
+        tree,
+        sources,
+        lines: Vec::new(),
+        inline: Vec::new(),
+    };
+
+    writer.out.write_str(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<!DOCTYPE document SYSTEM \"CommonMark.dtd\">\n",
+    ))?;
+    write!(
+        writer.out,
+        "<document xmlns=\"{NAMESPACE}\" xmlns:noumea=\"{EXTENSION_NAMESPACE}\""
+    )?;
+
+    // the document spans its main source, up to the last line with content
+    if let Some(src) = sources.text(SourceId::MAIN) {
+        let end = src.trim_end_matches('\n').len();
+
+        if end > 0 {
+            writer.sourcepos(Position::new(0, end))?;
+        }
+    }
+
+    if tree.root().is_none() {
+        return writer.out.write_str(" />\n");
+    }
+
+    writer.out.write_str(">\n")?;
+    write_tree(tree, None, &mut writer)?;
+
+    writer.out.write_str("</document>\n")
+}
+
+/// Byte offsets of the line starts of a source.
+struct Lines {
+    source: SourceId,
+    starts: Vec<usize>,
+}
+
+struct XmlWriter<'t, W, S: ?Sized> {
+    out: W,
+    tree: &'t TreeArena<AstNode>,
+    sources: &'t S,
+
+    /// Line starts of the sources seen so far.
+    lines: Vec<Lines>,
+
+    /// Whether the text of every entered node is
+    /// split into lines, like in paragraphs and headings.
+    inline: Vec<bool>,
+}
+
+impl<W: Write, S: Sources + ?Sized> XmlWriter<'_, W, S> {
+    // writes the text of a paragraph or heading,
+    // with its lines separated by soft breaks
+    fn text(&mut self, node: &AstNode, depth: usize) -> fmt::Result {
+        let text = node.as_str(self.sources);
+        let spanned = matches!(node.text, TextSource::Span);
+
+        let trimmed = text.trim_matches('\n');
+        let mut offset = node.pos.start + (text.len() - text.trim_start_matches('\n').len());
+
+        for (ix, line) in trimmed.split('\n').enumerate() {
+            if ix > 0 {
+                self.indent(depth)?;
+                self.out.write_str("<softbreak />\n")?;
+            }
+
+            // continuation lines lose their indentation,
+            // the last line its trailing whitespace
+            let content = line.trim_start_matches([' ', '\t']);
+            let start = offset + (line.len() - content.len());
+            let content = if ix == trimmed.matches('\n').count() {
+                content.trim_end_matches([' ', '\t'])
+            } else {
+                content
+            };
+
+            if !content.is_empty() {
+                self.indent(depth)?;
+                self.out.write_str("<text")?;
+
+                if spanned {
+                    let pos = Position::new(start, start + content.len());
+                    self.sourcepos(pos.with_source(node.pos.source))?;
+                }
+
+                self.out.write_str(" xml:space=\"preserve\">")?;
+                escape(&mut self.out, content)?;
+                self.out.write_str("</text>\n")?;
+            }
+
+            offset += line.len() + 1;
+        }
+
+        Ok(())
+    }
+
+    fn is_blank(&self, id: NodeId) -> bool {
+        self.tree.children(id).all(|child| {
+            self.tree.get(child).is_some_and(|child| {
+                child.data.value == Value::Text && child.data.as_str(self.sources).trim().is_empty()
+            })
+        })
+    }
+
+    // reads the delimiter of an ordered list from its source
+    fn delimiter(&self, node: &AstNode) -> Option<&'static str> {
+        match list_delimiter(node.as_str(self.sources))? {
+            ')' => Some("paren"),
+            _ => Some("period"),
+        }
+    }
+
+    // narrows `pos` to its content, without the blank lines
+    // before it and the line break after it, as cmark does
+    fn content(&self, pos: Position) -> Position {
+        let Some(text) = self
+            .sources
+            .text(pos.source)
+            .and_then(|src| src.get(pos.start..pos.end))
+        else {
+            return pos;
+        };
+
+        let content = text.trim_end();
+        let mut start = 0;
+
+        while let Some(len) = content[start..].find('\n') {
+            if !content[start..start + len].trim().is_empty() {
+                break;
+            }
+
+            start += len + 1;
+        }
+
+        if content.is_empty() {
+            return pos;
+        }
+
+        Position {
+            start: pos.start + start,
+            end: pos.start + content.len(),
+            source: pos.source,
+        }
+    }
+
+    // writes the `sourcepos` attribute, `line:column-line:column`
+    // of the first and last byte of `pos`, counted from 1
+    fn sourcepos(&mut self, pos: Position) -> fmt::Result {
+        if pos.is_generated() {
+            return Ok(());
+        }
+
+        let Some(src) = self.sources.text(pos.source) else {
+            return Ok(());
+        };
+
+        if pos.end > src.len() || pos.start > pos.end {
+            return Ok(());
+        }
+
+        let lines = match self
+            .lines
+            .iter()
+            .position(|lines| lines.source == pos.source)
+        {
+            Some(ix) => &self.lines[ix].starts,
+            None => {
+                let mut starts = Vec::from([0]);
+                starts.extend(src.match_indices('\n').map(|(ix, _)| ix + 1));
+
+                self.lines.push(Lines {
+                    source: pos.source,
+                    starts,
+                });
+                &self.lines[self.lines.len() - 1].starts
+            }
+        };
+
+        let locate = |offset: usize| {
+            let line = lines.partition_point(|&start| start <= offset);
+            (line, offset - lines[line - 1] + 1)
+        };
+
+        let (start_line, start_column) = locate(pos.start);
+        let (end_line, end_column) = locate(pos.end.saturating_sub(1).max(pos.start));
+
+        write!(
+            self.out,
+            " sourcepos=\"{start_line}:{start_column}-{end_line}:{end_column}\""
+        )
+    }
+
+    fn indent(&mut self, depth: usize) -> fmt::Result {
+        for _ in 0..=depth {
+            self.out.write_str("  ")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write, S: Sources + ?Sized> NodeWriter for XmlWriter<'_, W, S> {
+    fn enter_node(
+        &mut self,
+        id: NodeId,
+        node: &AstNode,
+        depth: usize,
+    ) -> Result<Control, fmt::Error> {
+        // the parser leaves empty paragraphs around blank lines
+        if node.value == Value::Paragraph && self.is_blank(id) {
+            return Ok(Control::SkipChildren);
+        }
+
+        let in_inline = self.inline.last().copied().unwrap_or(false);
+
+        let (name, leaf) = match &node.value {
+            Value::Root => ("noumea:root", false),
+            Value::Macro { .. } => ("noumea:macro", false),
+            Value::Paragraph => ("paragraph", false),
+            Value::Blockquote => ("block_quote", false),
+            Value::HtmlBlock if in_inline => ("html_inline", true),
+            Value::HtmlBlock => ("html_block", true),
+            Value::StyleBreak => ("thematic_break", true),
+            Value::Heading { .. } => ("heading", false),
+            Value::BulletList { .. } | Value::OrderedList { .. } => ("list", false),
+            Value::ListItem => ("item", false),
+            Value::Code { .. } => ("code_block", true),
+            Value::Emphasis { strong: true } => ("strong", false),
+            Value::Emphasis { strong: false } => ("emph", false),
+            Value::Link => ("link", false),
+            Value::Image => ("image", false),
+            Value::Strikethrough => ("strikethrough", false),
+            Value::Emoji => ("noumea:emoji", true),
+            Value::HardBreak => ("linebreak", true),
+            Value::SoftBreak => ("softbreak", true),
+            Value::EscapeChar(_) => ("text", true),
+            Value::Text if in_inline => {
+                self.text(node, depth)?;
+                return Ok(Control::SkipChildren);
+            }
+            Value::Text => ("text", true),
+        };
+
+        self.indent(depth)?;
+        write!(self.out, "<{name}")?;
+
+        match &node.value {
+            Value::Macro { name } => {
+                self.out.write_str(" name=\"")?;
+                escape(&mut self.out, name)?;
+                self.out.write_char('"')?;
+            }
+            Value::Heading { level } => write!(self.out, " level=\"{level}\"")?,
+            Value::BulletList { tight } => write!(self.out, " type=\"bullet\" tight=\"{tight}\"")?,
+            Value::OrderedList { tight, start_index } => {
+                write!(self.out, " type=\"ordered\" start=\"{start_index}\"")?;
+
+                if let Some(delim) = self.delimiter(node) {
+                    write!(self.out, " delim=\"{delim}\"")?;
+                }
+
+                write!(self.out, " tight=\"{tight}\"")?;
+            }
+            Value::Code { meta, .. } => {
+                let lang = node.value.lang().and_then(|lang| lang.name());
+                let info = meta.as_ref().and_then(|meta| meta.info());
+
+                if lang.is_some() || info.is_some() {
+                    self.out.write_str(" info=\"")?;
+                    escape(&mut self.out, lang.unwrap_or_default())?;
+
+                    if let Some(info) = info {
+                        if lang.is_some() {
+                            self.out.write_char(' ')?;
+                        }
+                        escape(&mut self.out, info)?;
+                    }
+
+                    self.out.write_char('"')?;
+                }
+            }
+            Value::Link | Value::Image => {
+                let text = node.as_str(self.sources);
+
+                self.out.write_str(" destination=\"")?;
+                escape(&mut self.out, link_destination(text).unwrap_or_default())?;
+                self.out.write_str("\" title=\"")?;
+                escape(&mut self.out, link_title(text).unwrap_or_default())?;
+                self.out.write_char('"')?;
+            }
+
+            _ => (),
+        }
+
+        if !matches!(node.value, Value::SoftBreak | Value::HardBreak) {
+            self.sourcepos(self.content(node.pos))?;
+        }
+
+        if !leaf {
+            self.out.write_str(">\n")?;
+            self.inline.push(matches!(
+                node.value,
+                Value::Paragraph
+                    | Value::Heading { .. }
+                    | Value::Emphasis { .. }
+                    | Value::Link
+                    | Value::Image
+                    | Value::Strikethrough
+            ));
+
+            return Ok(Control::Continue);
+        }
+
+        // leaves are written whole, along with their content
+        match &node.value {
+            Value::StyleBreak | Value::HardBreak | Value::SoftBreak => {
+                return self.out.write_str(" />\n").map(|()| Control::SkipChildren);
+            }
+            Value::Code { .. } => {
+                self.out.write_str(" xml:space=\"preserve\">")?;
+
+                let code = children_text(self.tree, id, self.sources);
+                escape(&mut self.out, &code)?;
+            }
+            Value::EscapeChar(ch) => {
+                self.out.write_str(" xml:space=\"preserve\">")?;
+                escape(&mut self.out, ch.encode_utf8(&mut [0; 4]))?;
+            }
+            _ => {
+                self.out.write_str(" xml:space=\"preserve\">")?;
+                escape(&mut self.out, node.as_str(self.sources))?;
+            }
+        }
+
+        writeln!(self.out, "</{name}>")?;
+        Ok(Control::SkipChildren)
+    }
+
+    fn exit_node(&mut self, id: NodeId, node: &AstNode, depth: usize) -> fmt::Result {
+        if node.value == Value::Paragraph && self.is_blank(id) {
+            return Ok(());
+        }
+
+        let name = match &node.value {
+            Value::Root => "noumea:root",
+            Value::Macro { .. } => "noumea:macro",
+            Value::Paragraph => "paragraph",
+            Value::Blockquote => "block_quote",
+            Value::Heading { .. } => "heading",
+            Value::BulletList { .. } | Value::OrderedList { .. } => "list",
+            Value::ListItem => "item",
+            Value::Emphasis { strong: true } => "strong",
+            Value::Emphasis { strong: false } => "emph",
+            Value::Link => "link",
+            Value::Image => "image",
+            Value::Strikethrough => "strikethrough",
+
+            // leaves are closed when they are entered
+            _ => return Ok(()),
+        };
+
+        self.inline.pop();
+        self.indent(depth)?;
+        writeln!(self.out, "</{name}>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::push_xml;
+    use crate::ast::{AstNode, Value};
+    use crate::block_parser::parse;
+    use crate::lib::String;
+    use crate::tree::TreeArena;
+
+    fn render(src: &str) -> String {
+        let mut out = String::new();
+        push_xml(&mut out, &parse(src), src);
+
+        out
+    }
+
+    const HEADER: &str = concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<!DOCTYPE document SYSTEM \"CommonMark.dtd\">\n",
+        "<document xmlns=\"http://commonmark.org/xml/1.0\" xmlns:noumea=\"urn:noumea:xml:1.0\"",
+    );
+
+    #[test]
+    fn blocks() {
+        assert_eq!(
+            render("# Salt & water\n\nThe sea\n  is <salty>.\n"),
+            [
+                HEADER,
+                " sourcepos=\"1:1-4:13\">\n",
+                "  <heading level=\"1\" sourcepos=\"1:1-1:14\">\n",
+                "    <text sourcepos=\"1:3-1:14\" xml:space=\"preserve\">Salt &amp; water</text>\n",
+                "  </heading>\n",
+                "  <paragraph sourcepos=\"3:1-4:13\">\n",
+                "    <text sourcepos=\"3:1-3:7\" xml:space=\"preserve\">The sea</text>\n",
+                "    <softbreak />\n",
+                "    <text sourcepos=\"4:3-4:13\" xml:space=\"preserve\">is &lt;salty&gt;.</text>\n",
+                "  </paragraph>\n",
+                "</document>\n",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn tight_lists() {
+        let tight = render("- salt\n- water\n");
+        assert!(tight.contains("<list type=\"bullet\" tight=\"true\" sourcepos=\"1:1-2:7\">\n"));
+
+        let loose = render("1. salt\n\n2. water\n");
+        assert!(loose.contains(
+            "<list type=\"ordered\" start=\"1\" delim=\"period\" tight=\"false\" sourcepos=\"1:1-3:8\">\n"
+        ));
+    }
+
+    #[test]
+    fn block_positions_skip_blank_lines() {
+        let out = render("\n\n- salt\n- water\n\n");
+
+        assert!(out.contains("<list type=\"bullet\" tight=\"true\" sourcepos=\"3:1-4:7\">\n"));
+        assert!(out.contains("<item sourcepos=\"3:1-3:6\">\n"));
+        assert!(out.contains("<paragraph sourcepos=\"4:3-4:7\">\n"));
+    }
+
+    #[test]
+    fn lists_and_macros() {
+        assert_eq!(
+            render("<>= note (x) (\n3) salt\n\n)"),
+            [
+                HEADER,
+                " sourcepos=\"1:1-4:1\">\n",
+                "  <noumea:macro name=\"note\" sourcepos=\"1:1-4:1\">\n",
+                "    <list type=\"ordered\" start=\"3\" delim=\"paren\" tight=\"true\" sourcepos=\"2:1-4:1\">\n",
+                "      <item sourcepos=\"2:1-2:7\">\n",
+                "        <paragraph sourcepos=\"2:4-2:7\">\n",
+                "          <text sourcepos=\"2:4-2:7\" xml:space=\"preserve\">salt</text>\n",
+                "        </paragraph>\n",
+                "      </item>\n",
+                "    </list>\n",
+                "  </noumea:macro>\n",
+                "</document>\n",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn links() {
+        let mut tree = TreeArena::new();
+        let paragraph = tree
+            .attach_node(AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        let link = tree
+            .append_child(
+                paragraph,
+                AstNode::with_text(Value::Link, "[salt](<salt.md> \"Salt & water\")"),
+            )
+            .unwrap();
+        tree.append_child(link, AstNode::with_text(Value::Text, "salt"))
+            .unwrap();
+        tree.append_child(
+            paragraph,
+            AstNode::with_text(Value::Image, "![a map](map.png)"),
+        )
+        .unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::Link, ""))
+            .unwrap();
+
+        let mut out = String::new();
+        push_xml(&mut out, &tree, "");
+
+        assert!(out.contains("<link destination=\"salt.md\" title=\"Salt &amp; water\">\n"));
+        assert!(out.contains("<image destination=\"map.png\" title=\"\">\n"));
+        assert!(out.contains("<link destination=\"\" title=\"\">\n"));
+    }
+
+    #[test]
+    fn generated_nodes() {
+        let mut tree = TreeArena::new();
+        let paragraph = tree
+            .attach_node(AstNode::with_text(Value::Paragraph, ""))
+            .unwrap();
+        tree.append_child(paragraph, AstNode::with_text(Value::Text, "a\nb"))
+            .unwrap();
+        tree.insert_after(paragraph, AstNode::with_text(Value::StyleBreak, ""))
+            .unwrap();
+
+        let mut out = String::new();
+        push_xml(&mut out, &tree, "");
+
+        assert_eq!(
+            out,
+            [
+                HEADER,
+                ">\n",
+                "  <paragraph>\n",
+                "    <text xml:space=\"preserve\">a</text>\n",
+                "    <softbreak />\n",
+                "    <text xml:space=\"preserve\">b</text>\n",
+                "  </paragraph>\n",
+                "  <thematic_break />\n",
+                "</document>\n",
+            ]
+            .concat()
+        );
+
+        out.clear();
+        push_xml(&mut out, &TreeArena::new(), "");
+        assert_eq!(out, [HEADER, " />\n"].concat());
+    }
+}
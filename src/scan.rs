@@ -18,7 +18,7 @@ impl<'i> Input<'i> {
 
     // gives a reference to the byte slice offset by `consumed`
     pub(crate) fn leftover(&self) -> &'i [u8] {
-        &self.bytes[self.consumed..]
+        self.bytes.get(self.consumed..).unwrap_or_default()
     }
 
     // checks if we're at the end of the input
@@ -117,71 +117,65 @@ impl<'i> Input<'i> {
     // ^^^ ^^^  ^---------^ x
     // and the returned usize is the index after the brace (marked as x)
     pub(crate) fn scan_macro(&self) -> Option<(MacroSpan, usize)> {
-        if self.eof() {
-            return None;
-        }
-
         let bytes = self.leftover();
-        if bytes.get(0..4).is_none_or(|arr| arr != b"<>= ") {
+        if !bytes.starts_with(b"<>= ") {
             return None;
         }
 
-        let mut ix = 4;
-        let mut span = MacroSpan {
-            operator: (0, 4),
-            name: (4, 0),
-            args: (0, 0),
-        };
-
-        // name
-        for byte in &bytes[ix..] {
-            if *byte == b' ' {
-                span.name.1 = ix + 1;
-                ix += 2;
-                span.args.0 = ix;
-                break;
-            }
-
-            ix += 1
-        }
+        // name, up to the space before the arguments
+        let name_start = 4;
+        let name_end = name_start
+            + bytes[name_start..]
+                .iter()
+                .position(|byte| matches!(byte, b' ' | b'\n'))?;
 
-        if ix == 3 || bytes.get(ix - 1).is_none_or(|byte| *byte != b'(') {
+        if name_end == name_start
+            || bytes.get(name_end) != Some(&b' ')
+            || str::from_utf8(&bytes[name_start..name_end]).is_err()
+        {
             return None;
         }
 
         // arg braces
-        for byte in &bytes[ix + 1..] {
-            ix += 1;
-            if *byte == b')' {
-                span.args.1 = ix;
-                break;
-            }
+        if bytes.get(name_end + 1) != Some(&b'(') {
+            return None;
         }
 
-        // skip whitespace
-        loop {
-            if bytes.get(ix).is_none_or(|x| !x.is_ascii_whitespace()) {
-                ix += 2;
-                break;
-            }
+        let args_start = name_end + 2;
+        let args_end = args_start + bytes[args_start..].iter().position(|byte| *byte == b')')?;
 
+        // skip whitespace
+        let mut ix = args_end + 1;
+        while bytes
+            .get(ix)
+            .is_some_and(|byte| matches!(byte, b' ' | b'\t'))
+        {
             ix += 1;
         }
 
-        if bytes.get(ix).copied() != Some(b'(') {
-            None
-        } else {
-            Some((span, ix + 1))
+        if bytes.get(ix) != Some(&b'(') {
+            return None;
         }
+
+        let span = MacroSpan {
+            operator: (0, 3),
+            name: (name_start, name_end),
+            args: (args_start, args_end),
+        };
+
+        Some((span, ix + 1))
     }
 
-    pub(crate) fn scan_macro_end(&self) -> bool {
-        if let Some(arr) = self.bytes.get(self.consumed..) {
-            return arr.first().is_some_and(|x| *x == b')')
-                && arr.get(1).is_none_or(|x| *x == b'\n');
-        }
+    // scans for the `)` closing a macro invocation,
+    // on a line of its own after any empty lines
+    //
+    // returns index after the brace if it succeeds
+    pub(crate) fn scan_macro_end(&self) -> Option<usize> {
+        let arr = self.bytes.get(self.consumed..)?;
+        let ix = arr.iter().take_while(|x| **x == b'\n').count();
 
-        false
+        (arr.get(ix).is_some_and(|x| *x == b')') && arr.get(ix + 1).is_none_or(|x| *x == b'\n'))
+            .then_some(ix + 1)
     }
 
     // scans for two consecutive newlines like `\n\n`
@@ -201,19 +195,19 @@ impl<'i> Input<'i> {
 
     // scans for a bullet list start `<char> ` where char is `+` or `-` or `*`
     //
-    // if it succeeds, it returns (index after marker, character used, tightness)
+    // if it succeeds, it returns (index after marker, character used, whether it follows a blank line)
     pub(crate) fn scan_bullet_list(&self) -> Option<(usize, char, bool)> {
-        let tight = self.scan_two_newlines();
+        let blank = self.scan_two_newlines();
         let mut relative_index = 2;
         if self.eof() {
             return None;
         }
 
-        // dbg!((tight,));
+        // dbg!((blank,));
         let mut bytes = self.leftover();
 
         // dbg!((core::str::from_utf8(bytes).unwrap()));
-        bytes = if tight {
+        bytes = if blank {
             relative_index = 4;
 
             &bytes[2..]
@@ -229,7 +223,7 @@ impl<'i> Input<'i> {
                 .copied()
                 .is_some_and(|byte| matches!(byte as char, ' '))
         {
-            Some((relative_index, list_marker_byte?, tight))
+            Some((relative_index, list_marker_byte?, blank))
         } else {
             None
         }
@@ -237,46 +231,36 @@ impl<'i> Input<'i> {
 
     // scans for a ordered list start `<num><char> ` where char is `(` or `.`.
     //
-    // if it succeeds, it returns (index after marker, character used, index, tightness)
+    // if it succeeds, it returns (index after marker, character used, index, whether it follows a blank line)
     pub(crate) fn scan_ordered_list(&self) -> Option<(usize, char, u64, bool)> {
-        let tight = self.scan_two_newlines();
-        let mut ix = if tight { 2 } else { 0 };
-
-        let bytes = &self.bytes.get(self.consumed..)?.get(ix..)?;
-
-        for (i, byte) in bytes.iter().enumerate() {
-            if !byte.is_ascii_digit() {
-                if *byte != b'.' && *byte != b')' {
-                    return None;
-                }
-
-                ix += 1;
+        let blank = self.scan_two_newlines();
+        let offset = if blank { 2 } else { 0 };
 
-                break;
-            }
+        let bytes = self.leftover().get(offset..)?;
+        let digits = bytes
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count();
 
-            ix = i;
+        // at most 9 digits, so the number always fits
+        if digits == 0 || digits > 9 {
+            return None;
         }
 
-        let marker_char: char = bytes.get(ix).copied().map(Into::into)?;
+        let marker_char = bytes
+            .get(digits)
+            .copied()
+            .filter(|byte| matches!(byte, b'.' | b')'))?;
 
-        if ix == 0
-            || ix >= 9
-            || bytes
-                .get(ix + 1)
-                .copied()
-                .is_none_or(|bytechar| bytechar != b' ')
-        {
+        if bytes.get(digits + 1) != Some(&b' ') {
             return None;
         }
 
-        let start_num = unsafe {
-            str::from_utf8_unchecked(&bytes[..ix])
-                .parse::<u64>()
-                .expect("infallible")
-        };
+        let start_num = bytes[..digits]
+            .iter()
+            .fold(0, |num, digit| num * 10 + u64::from(digit - b'0'));
 
-        Some((ix + 2, marker_char, start_num, tight))
+        Some((digits + 2, marker_char.into(), start_num, blank))
     }
 
     // scans for a blockquote marker `> ` or `> `
@@ -308,8 +292,8 @@ impl<'i> Input<'i> {
 
     // scans for a start of an atx heading
     //
-    // if it succeeds, returns the index after the marker and all the whitespace
-    pub(crate) fn scan_atx_heading(&self) -> Option<usize> {
+    // if it succeeds, returns (level of heading, index after the marker and all the whitespace)
+    pub(crate) fn scan_atx_heading(&self) -> Option<(NonZeroU8, usize)> {
         if self.eof() {
             return None;
         }
@@ -322,20 +306,93 @@ impl<'i> Input<'i> {
             ix += 1;
         }
 
-        if ix == 0
-            || ix > 6
-            || bytes.get(ix).is_none()
-            || bytes.get(ix).copied().is_some_and(|x| x != b' ')
-        {
+        if ix > 6 || bytes.get(ix).copied().is_none_or(|x| x != b' ') {
             return None;
         }
 
+        #[allow(clippy::cast_possible_truncation)]
+        let level = NonZero::new(ix as u8)?;
+
         // consume all the white space
         while bytes.get(ix).copied().is_some_and(|byte| byte == b' ') {
             ix += 1;
         }
 
-        Some(ix)
+        Some((level, ix))
+    }
+
+    // scans for the opening fence of a code block, three or more '`' or '~'
+    // followed by an info string, which can't contain '`' after a '`' fence
+    //
+    // the `FenceSpan` contains the positions of
+    //
+    // ```rust ignore
+    // ^-^^---------^
+    // and the returned usize is the index after the line
+    pub(crate) fn scan_code_fence(&self) -> Option<(FenceSpan, usize)> {
+        let bytes = self.leftover();
+        let fence_char = bytes
+            .first()
+            .copied()
+            .filter(|byte| matches!(byte, b'`' | b'~'))?;
+
+        let fence_len = bytes.iter().take_while(|byte| **byte == fence_char).count();
+        if fence_len < 3 {
+            return None;
+        }
+
+        let line_len = bytes[fence_len..]
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .unwrap_or(bytes.len() - fence_len);
+        let line = &bytes[fence_len..fence_len + line_len];
+
+        if fence_char == b'`' && line.contains(&b'`') {
+            return None;
+        }
+
+        // the info string, without the whitespace around it
+        let info_start = fence_len + line.len() - line.trim_ascii_start().len();
+        let info_end = fence_len + line.trim_ascii_end().len();
+
+        let span = FenceSpan {
+            fence_char,
+            fence: (0, fence_len),
+            info: (info_start, info_end.max(info_start)),
+        };
+        let end = (fence_len + line_len + 1).min(bytes.len());
+
+        Some((span, end))
+    }
+
+    // scans for a fence closing a code block, at least `len` times
+    // `fence_char` on a line of its own
+    //
+    // if it succeeds, returns (length of the fence, index after the line)
+    pub(crate) fn scan_closing_fence(&self, fence_char: u8, len: usize) -> Option<(usize, usize)> {
+        let bytes = self.leftover();
+        let fence_len = bytes.iter().take_while(|byte| **byte == fence_char).count();
+
+        if fence_len < len {
+            return None;
+        }
+
+        let rest = bytes[fence_len..]
+            .iter()
+            .take_while(|byte| matches!(byte, b' ' | b'\t'))
+            .count();
+
+        match bytes.get(fence_len + rest) {
+            None => Some((fence_len, fence_len + rest)),
+            Some(b'\n') => Some((fence_len, fence_len + rest + 1)),
+
+            Some(_) => None,
+        }
+    }
+
+    // checks if the input is at the start of a line
+    pub(crate) fn at_line_start(&self) -> bool {
+        self.consumed == 0 || self.bytes.get(self.consumed - 1) == Some(&b'\n')
     }
 
     // scans for a condition that would mean the interruption of a md paragraph
@@ -344,9 +401,17 @@ impl<'i> Input<'i> {
             || self.scan_ordered_list().is_some()
             || self.scan_atx_heading().is_some()
             || self.scan_two_newlines()
+            || (self.at_line_start() && self.scan_code_fence().is_some())
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq)]
+pub(crate) struct FenceSpan {
+    pub fence_char: u8,
+    pub fence: (usize, usize),
+    pub info: (usize, usize),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq)]
 pub(crate) struct MacroSpan {
     pub operator: (usize, usize),
@@ -0,0 +1,202 @@
+//! Source data the positions of a document point into.
+
+use crate::ast::SourceId;
+use crate::lib::{String, Vec};
+
+/// Source data which positions can be resolved against.
+///
+/// Implemented by `str`, for documents parsed from a single string,
+/// and by `SourceMap`, for documents made out of several sources.
+pub trait Sources {
+    /// Returns the text of `source`,
+    /// or `None` if it's unknown or generated.
+    fn text(&self, source: SourceId) -> Option<&str>;
+
+    /// Returns the name of `source`, such as its file name, if known.
+    fn name(&self, source: SourceId) -> Option<&str> {
+        let _ = source;
+        None
+    }
+}
+
+impl Sources for str {
+    fn text(&self, source: SourceId) -> Option<&str> {
+        (source == SourceId::MAIN).then_some(self)
+    }
+}
+
+impl Sources for String {
+    fn text(&self, source: SourceId) -> Option<&str> {
+        self.as_str().text(source)
+    }
+}
+
+/// Borrowed source data of a document,
+/// accepted wherever a document is read.
+#[derive(Debug, Clone, Copy)]
+pub enum SourceText<'a> {
+    /// The single string a document was parsed from.
+    Str(&'a str),
+
+    /// Every source of a document.
+    Map(&'a SourceMap),
+}
+
+impl Sources for SourceText<'_> {
+    fn text(&self, source: SourceId) -> Option<&str> {
+        match self {
+            Self::Str(text) => text.text(source),
+            Self::Map(map) => map.text(source),
+        }
+    }
+
+    fn name(&self, source: SourceId) -> Option<&str> {
+        match self {
+            Self::Str(_) => None,
+            Self::Map(map) => map.name(source).filter(|name| !name.is_empty()),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for SourceText<'a> {
+    fn from(text: &'a str) -> Self {
+        Self::Str(text)
+    }
+}
+
+impl<'a> From<&'a String> for SourceText<'a> {
+    fn from(text: &'a String) -> Self {
+        Self::Str(text)
+    }
+}
+
+impl<'a> From<&'a SourceMap> for SourceText<'a> {
+    fn from(map: &'a SourceMap) -> Self {
+        Self::Map(map)
+    }
+}
+
+/// A single source registered in a `SourceMap`.
+#[derive(Debug, Default)]
+struct Source {
+    name: String,
+    text: String,
+}
+
+/// Holds every text contributing to a document,
+/// such as the main file, included files or front matter.
+///
+/// The first source added is `SourceId::MAIN`.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    sources: Vec<Source>,
+
+    /// Amount of sources in use,
+    /// the ones past it are kept around for their memory.
+    len: usize,
+}
+
+impl SourceMap {
+    /// Creates an empty `SourceMap`.
+    pub fn new() -> Self {
+        Self {
+            sources: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Adds a copy of `text` named `name`, returning its `SourceId`.
+    ///
+    /// # Panics
+    /// if the map can't hold any more sources.
+    pub fn add(&mut self, name: &str, text: &str) -> SourceId {
+        let id = u32::try_from(self.len)
+            .ok()
+            .map(SourceId::new)
+            .filter(|&id| id != SourceId::GENERATED)
+            .expect("the source map can't hold any more sources");
+
+        if self.sources.len() == self.len {
+            self.sources.push(Source::default());
+        }
+
+        let source = &mut self.sources[self.len];
+        source.name.clear();
+        source.name.push_str(name);
+        source.text.clear();
+        source.text.push_str(text);
+
+        self.len += 1;
+
+        id
+    }
+
+    /// Returns the text of `source`.
+    pub fn get(&self, source: SourceId) -> Option<&str> {
+        self.source(source).map(|source| source.text.as_str())
+    }
+
+    /// Returns the name of `source`.
+    pub fn name(&self, source: SourceId) -> Option<&str> {
+        self.source(source).map(|source| source.name.as_str())
+    }
+
+    /// Returns the amount of sources.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if there are no sources.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns an iterator over the ids of the sources.
+    pub fn ids(&self) -> impl Iterator<Item = SourceId> + use<> {
+        (0..self.len).map(|index| SourceId::new(index as u32))
+    }
+
+    /// Removes every source, keeping the allocated memory for reuse.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn source(&self, source: SourceId) -> Option<&Source> {
+        self.sources[..self.len].get(source.index() as usize)
+    }
+}
+
+impl Sources for SourceMap {
+    fn text(&self, source: SourceId) -> Option<&str> {
+        self.get(source)
+    }
+
+    fn name(&self, source: SourceId) -> Option<&str> {
+        SourceMap::name(self, source).filter(|name| !name.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SourceMap, Sources};
+    use crate::ast::SourceId;
+
+    #[test]
+    fn source_map() {
+        let mut map = SourceMap::new();
+
+        assert_eq!(map.add("main.md", "Salt."), SourceId::MAIN);
+        let include = map.add("water.md", "Water.");
+
+        assert_eq!(map.get(include), Some("Water."));
+        assert_eq!(map.name(SourceId::MAIN), Some("main.md"));
+        assert_eq!(map.text(SourceId::GENERATED), None);
+        assert_eq!("Salt.".text(include), None);
+
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.get(include), None);
+        assert_eq!(map.add("other.md", "Other."), SourceId::MAIN);
+        assert_eq!(map.get(SourceId::MAIN), Some("Other."));
+    }
+}
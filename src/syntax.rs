@@ -0,0 +1,415 @@
+//! Lossless view of a parsed document.
+//!
+//! The AST leaves out the markers and whitespace around its nodes.
+//! When parsing with `Parser::lossless`, the document additionally
+//! gets a `Syntax`: the source cut into consecutive `SyntaxToken`s,
+//! so that every byte belongs to exactly one token. Tokens point to
+//! the node they belong to, e.g. the marker of a list item to the item,
+//! while line endings, blank lines and other whitespace between
+//! nodes are trivia.
+
+use crate::ast::{AstNode, Position, SourceId, Value};
+use crate::lib::{String, Vec};
+use crate::source::Sources;
+use crate::tree::{NodeId, TreeArena};
+use core::fmt::{self, Write};
+
+/// Kind of a `SyntaxToken`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// Content of a leaf node, like the text of a paragraph.
+    Content,
+
+    /// `>` starting a blockquote, with the whitespace after it.
+    BlockquoteMarker,
+
+    /// Marker of a bullet list item, with the whitespace after it.
+    BulletMarker(char),
+
+    /// Number and delimiter of an ordered list item,
+    /// with the whitespace after them.
+    OrderedMarker(char),
+
+    /// `#`s starting an ATX heading, with the whitespace after them.
+    AtxMarker,
+
+    /// Line of `=` or `-` underlining a setext heading.
+    SetextUnderline(char),
+
+    /// The `<>=` operator starting a macro invocation.
+    MacroOperator,
+
+    /// Name of an invoked macro.
+    MacroName,
+
+    /// Arguments of a macro invocation, without their parentheses.
+    MacroArguments,
+
+    /// Parentheses of a macro invocation.
+    MacroParen,
+
+    /// The `)` on its own line closing a macro invocation.
+    MacroClose,
+
+    /// Run of `` ` `` or `~` opening a fenced code block.
+    FenceOpen(char),
+
+    /// Info string after the opening fence of a code block,
+    /// without the whitespace around it.
+    FenceInfo,
+
+    /// Run of `` ` `` or `~` closing a fenced code block.
+    FenceClose(char),
+
+    /// Line ending of a line with content.
+    LineEnding,
+
+    /// Line ending of a blank line.
+    BlankLine,
+
+    /// Whitespace which isn't part of any node.
+    Whitespace,
+}
+
+impl TokenKind {
+    /// Returns `true` for line endings and whitespace.
+    pub fn is_trivia(self) -> bool {
+        matches!(self, Self::LineEnding | Self::BlankLine | Self::Whitespace)
+    }
+}
+
+/// A range of the source, along with what it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SyntaxToken {
+    pub kind: TokenKind,
+    pub pos: Position,
+
+    /// The node the token belongs to, `None` for trivia
+    /// and content outside of every node.
+    pub node: Option<NodeId>,
+}
+
+impl SyntaxToken {
+    /// Returns the text of this token.
+    pub fn as_str<'a, S>(&self, sources: &'a S) -> &'a str
+    where
+        S: Sources + ?Sized,
+    {
+        sources
+            .text(self.pos.source)
+            .and_then(|text| self.pos.view_substring(text))
+            .unwrap_or_default()
+    }
+}
+
+/// The tokens of a document parsed with `Parser::lossless`.
+///
+/// Reflects the tree as it was parsed, later edits
+/// of the tree aren't taken into account.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Syntax {
+    tokens: Vec<SyntaxToken>,
+}
+
+impl Syntax {
+    /// Returns the tokens in source order.
+    pub fn tokens(&self) -> &[SyntaxToken] {
+        &self.tokens
+    }
+
+    /// Returns the tokens belonging to the node `id`,
+    /// not including those of its descendants.
+    pub fn tokens_of(&self, id: NodeId) -> impl Iterator<Item = &SyntaxToken> {
+        self.tokens
+            .iter()
+            .filter(move |token| token.node == Some(id))
+    }
+
+    /// Writes the source back out, token by token.
+    ///
+    /// # Errors
+    /// if writing to `out` fails.
+    pub fn write_source<W, S>(&self, mut out: W, sources: &S) -> fmt::Result
+    where
+        W: Write,
+        S: Sources + ?Sized,
+    {
+        for token in &self.tokens {
+            out.write_str(token.as_str(sources))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the source, put back together from the tokens.
+    pub fn to_source<S>(&self, sources: &S) -> String
+    where
+        S: Sources + ?Sized,
+    {
+        let mut out = String::new();
+        self.write_source(&mut out, sources)
+            .expect("writing to a `String` is infallible");
+
+        out
+    }
+
+    // cuts `src` into tokens, from the `markers` recorded
+    // by the parser and the leaves of the `tree`
+    pub(crate) fn build(
+        mut tokens: Vec<SyntaxToken>,
+        markers: &[SyntaxToken],
+        tree: &TreeArena<AstNode>,
+        src: &str,
+        source: SourceId,
+    ) -> Self {
+        tokens.clear();
+
+        let leaves = tree.nodes().filter_map(|id| {
+            let node = &tree.get(id)?.data;
+
+            matches!(
+                node.value,
+                Value::Text
+                    | Value::StyleBreak
+                    | Value::HtmlBlock
+                    | Value::Emoji
+                    | Value::EscapeChar(_)
+                    | Value::HardBreak
+                    | Value::SoftBreak
+            )
+            .then_some(SyntaxToken {
+                kind: TokenKind::Content,
+                pos: node.pos,
+                node: Some(id),
+            })
+        });
+
+        // markers win over leaves starting at the same byte
+        let mut ranges = markers.iter().copied().chain(leaves).collect::<Vec<_>>();
+        ranges.sort_by_key(|token| token.pos.start);
+
+        let mut builder = Builder {
+            tokens,
+            bytes: src.as_bytes(),
+            source,
+            tree,
+        };
+        let mut cursor = 0;
+
+        for token in ranges {
+            let start = token.pos.start.max(cursor);
+            let end = token.pos.end.min(src.len());
+
+            if start >= end {
+                continue;
+            }
+
+            builder.gap(cursor, start);
+            builder.token(token.kind, start, end, token.node);
+            cursor = end;
+        }
+
+        builder.gap(cursor, src.len());
+
+        Self {
+            tokens: builder.tokens,
+        }
+    }
+
+    pub(crate) fn into_tokens(self) -> Vec<SyntaxToken> {
+        self.tokens
+    }
+}
+
+struct Builder<'s> {
+    tokens: Vec<SyntaxToken>,
+    bytes: &'s [u8],
+    source: SourceId,
+    tree: &'s TreeArena<AstNode>,
+}
+
+impl Builder<'_> {
+    fn push(&mut self, kind: TokenKind, start: usize, end: usize, node: Option<NodeId>) {
+        if start < end {
+            self.tokens.push(SyntaxToken {
+                kind,
+                pos: Position::new(start, end).with_source(self.source),
+                node,
+            });
+        }
+    }
+
+    // pushes a token, with the line endings around it as trivia
+    fn token(&mut self, kind: TokenKind, start: usize, end: usize, node: Option<NodeId>) {
+        let newline = |ix: &usize| self.bytes.get(*ix) == Some(&b'\n');
+        let inner_start = (start..end).find(|ix| !newline(ix)).unwrap_or(end);
+        let inner_end = (inner_start..end)
+            .rev()
+            .find(|ix| !newline(ix))
+            .map_or(inner_start, |ix| ix + 1);
+
+        self.line_endings(start, inner_start);
+        self.push(kind, inner_start, inner_end, node);
+        self.line_endings(inner_end, end);
+    }
+
+    // pushes the bytes between tokens as trivia
+    fn gap(&mut self, start: usize, end: usize) {
+        let mut ix = start;
+
+        while ix < end {
+            let class = |byte: u8| match byte {
+                b'\n' => 0,
+                b' ' | b'\t' | b'\r' => 1,
+                _ => 2,
+            };
+
+            let current = class(self.bytes[ix]);
+            let run_end = (ix..end)
+                .find(|&next| class(self.bytes[next]) != current)
+                .unwrap_or(end);
+
+            match current {
+                0 => self.line_endings(ix, run_end),
+                1 => self.push(TokenKind::Whitespace, ix, run_end, None),
+                _ => {
+                    let node = self.owner(ix, run_end);
+                    self.push(TokenKind::Content, ix, run_end, node);
+                }
+            }
+
+            ix = run_end;
+        }
+    }
+
+    // innermost node spanning the bytes between `start` and `end`
+    fn owner(&self, start: usize, end: usize) -> Option<NodeId> {
+        self.tree
+            .nodes()
+            .filter(|&id| {
+                self.tree
+                    .get(id)
+                    .is_some_and(|node| node.data.pos.start <= start && end <= node.data.pos.end)
+            })
+            .last()
+    }
+
+    fn line_endings(&mut self, start: usize, end: usize) {
+        for ix in start..end {
+            let kind = if ix == 0 || self.bytes.get(ix - 1) == Some(&b'\n') {
+                TokenKind::BlankLine
+            } else {
+                TokenKind::LineEnding
+            };
+
+            self.push(kind, ix, ix + 1, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenKind;
+    use crate::ast::Value;
+    use crate::block_parser::Parser;
+    use crate::lib::Vec;
+
+    #[test]
+    fn reproduces_the_source() {
+        let documents = [
+            "# Salt\n\nThe sea.\n",
+            "* salt\n* water\n\n\n3) three\n4) four",
+            "> quoted\n>   text\n\n  indented\n",
+            "<>= note (kind = info)  (\nInside.\n\n)\n\nAfter.\n",
+            "Setext\n===\n\n## ATX ##\n\n---\n",
+            "\n\n\t \n",
+            "Text\n```rust ignore \nfn x\n\n```  \nAfter\n",
+            "~~~~\n```\n~~~",
+            "",
+        ];
+
+        for src in documents {
+            let document = Parser::new().lossless(true).parse_document(src);
+            let syntax = document.syntax().unwrap();
+
+            assert_eq!(syntax.to_source(&document), src);
+
+            // tokens are consecutive
+            let mut end = 0;
+            for token in syntax.tokens() {
+                assert_eq!(token.pos.start, end, "{token:?} in {src:?}");
+                end = token.pos.end;
+            }
+        }
+    }
+
+    #[test]
+    fn markers() {
+        let src = "<>= note (x) (\n- salt\n\n)\n\nSetext\n---\n";
+        let document = Parser::new().lossless(true).parse_document(src);
+        let syntax = document.syntax().unwrap();
+
+        let tokens = syntax
+            .tokens()
+            .iter()
+            .filter(|token| !token.kind.is_trivia())
+            .map(|token| (token.kind, token.as_str(&document)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            [
+                (TokenKind::MacroOperator, "<>="),
+                (TokenKind::MacroName, "note"),
+                (TokenKind::MacroParen, "("),
+                (TokenKind::MacroArguments, "x"),
+                (TokenKind::MacroParen, ")"),
+                (TokenKind::MacroParen, "("),
+                (TokenKind::BulletMarker('-'), "- "),
+                (TokenKind::Content, "salt"),
+                (TokenKind::MacroClose, ")"),
+                (TokenKind::Content, "Setext"),
+                (TokenKind::SetextUnderline('-'), "---"),
+            ]
+        );
+
+        let item = document
+            .tree()
+            .nodes()
+            .find(|&id| document.tree().get(id).unwrap().data.value == Value::ListItem)
+            .unwrap();
+        let marker = syntax.tokens_of(item).next().unwrap();
+        assert_eq!(marker.kind, TokenKind::BulletMarker('-'));
+    }
+
+    #[test]
+    fn fences() {
+        let src = "```rust ignore\nfn x() {}\n```\n";
+        let document = Parser::new().lossless(true).parse_document(src);
+        let syntax = document.syntax().unwrap();
+
+        let tokens = syntax
+            .tokens()
+            .iter()
+            .filter(|token| !token.kind.is_trivia())
+            .map(|token| (token.kind, token.as_str(&document)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            [
+                (TokenKind::FenceOpen('`'), "```"),
+                (TokenKind::FenceInfo, "rust ignore"),
+                (TokenKind::Content, "fn x() {}"),
+                (TokenKind::FenceClose('`'), "```"),
+            ]
+        );
+    }
+
+    #[test]
+    fn without_lossless() {
+        let document = Parser::new().parse_document("# Salt\n");
+
+        assert!(document.syntax().is_none());
+    }
+}
@@ -1,6 +1,6 @@
-use super::ast::AstNode;
+use super::ast::{AstNode, SourceId};
 use crate::lib::Vec;
-use core::fmt::Debug;
+use core::fmt::{self, Debug};
 use core::num::NonZero;
 
 extern crate std;
@@ -11,46 +11,96 @@ type Index = u16;
 #[cfg(target_pointer_width = "64")]
 type Index = u32;
 
+/// Index of a bucket in the `TreeArena`.
+type BucketIndex = u8;
+
+/// Generation of a node slot in the `TreeArena`,
+/// only its low `NodeId::GENERATION_BITS` bits are used.
+#[cfg(target_pointer_width = "32")]
+type Generation = u8;
+
+#[cfg(target_pointer_width = "64")]
+type Generation = u32;
+
 /// Allows to index into the `TreeArena`
 /// internally contains an index to
 /// the current chunk of memory used to allocate nodes
 /// and the index of the node inside that chunk.
-#[derive(Eq, PartialEq, PartialOrd, Ord, Copy, Clone)]
-#[repr(C)]
-pub struct NodeId {
-    node_index: Index,
-    vec_index: NonZero<Index>,
-}
+///
+/// It also carries the generation of the node it points to,
+/// so ids of removed nodes and ids from other arenas
+/// are rejected instead of aliasing a different node.
+#[derive(Eq, PartialEq, PartialOrd, Ord, Copy, Clone, Hash)]
+#[repr(transparent)]
+pub struct NodeId(NonZero<usize>);
+
+// `NodeId` fits in a `usize`, and so does `Option<NodeId>`.
+const _: () = assert!(size_of::<NodeId>() == size_of::<usize>());
+const _: () = assert!(size_of::<Option<NodeId>>() == size_of::<NodeId>());
 
 impl NodeId {
-    #[cfg(target_pointer_width = "32")]
-    const MASK: usize = (2_usize.pow((size_of::<usize>() / 2) as u16) << 16) - 1;
+    const VEC_SHIFT: u32 = Index::BITS;
+    const GENERATION_SHIFT: u32 = Index::BITS + BucketIndex::BITS;
 
-    #[cfg(target_pointer_width = "64")]
-    const MASK: usize = (2_usize.pow((size_of::<usize>() / 2) as u32) << 32) - 1;
+    /// Amount of bits of the generation, whatever is left of a `usize`.
+    const GENERATION_BITS: u32 = usize::BITS - Self::GENERATION_SHIFT;
 
+    /// Largest generation an id can carry.
+    const MAX_GENERATION: Generation =
+        Generation::MAX >> (Generation::BITS - Self::GENERATION_BITS);
+
+    /// Creates a `NodeId` from the value returned by `NodeId::into_raw`.
+    ///
+    /// Returns `None` if the value can't represent a `NodeId`.
     pub fn new(val: usize) -> Option<Self> {
-        if val & Self::MASK == 0 {
-            None
-        } else {
-            Some(NodeId::new_unchecked(val))
-        }
+        let id = Self(NonZero::new(val)?);
+
+        // the first bucket is never used
+        (id.vec_index() != 0).then_some(id)
     }
 
+    /// Creates a `NodeId` from the value returned by `NodeId::into_raw`.
+    ///
+    /// # Panics
+    /// if `val` can't represent a `NodeId`.
+    #[deprecated(note = "ids are validated now, use `NodeId::new` and handle `None`")]
     pub fn new_unchecked(val: usize) -> Self {
-        unsafe { core::mem::transmute::<usize, NodeId>(val) }
+        Self::new(val).expect("`val` should be returned by `NodeId::into_raw`")
     }
 
-    pub fn from_indexes(node_index: Index, vec_index: Index) -> Option<Self> {
-        if vec_index == 0 {
-            return None;
-        };
+    /// Packs this `NodeId` into a `usize`.
+    pub fn into_raw(self) -> usize {
+        self.0.get()
+    }
 
-        Self {
-            vec_index: unsafe { NonZero::new_unchecked(vec_index) },
-            node_index,
-        }
-        .into()
+    // only the arena hands out ids, so every id carries
+    // the generation of the slot it was made for
+    pub(crate) fn from_indexes(
+        node_index: Index,
+        vec_index: BucketIndex,
+        generation: Generation,
+    ) -> Option<Self> {
+        Self::new(
+            node_index as usize
+                | (vec_index as usize) << Self::VEC_SHIFT
+                | ((generation & Self::MAX_GENERATION) as usize) << Self::GENERATION_SHIFT,
+        )
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn node_index(self) -> Index {
+        self.0.get() as Index
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn vec_index(self) -> BucketIndex {
+        (self.0.get() >> Self::VEC_SHIFT) as BucketIndex
+    }
+
+    /// Returns the generation of the node this id points to.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn generation(self) -> Generation {
+        (self.0.get() >> Self::GENERATION_SHIFT) as Generation
     }
 }
 
@@ -58,8 +108,10 @@ impl Debug for NodeId {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
-            "NodeId {{ node_index: {}, vec_index: {} }}",
-            self.node_index, self.vec_index
+            "NodeId {{ node_index: {}, vec_index: {}, generation: {} }}",
+            self.node_index(),
+            self.vec_index(),
+            self.generation()
         )
     }
 }
@@ -69,48 +121,99 @@ pub struct Node<T> {
 
     next: Option<NodeId>,
     child: Option<NodeId>,
+
+    parent: Option<NodeId>,
+    prev: Option<NodeId>,
+
+    /// Generation of the slot, ids with a different one are stale.
+    generation: Generation,
 }
 
 impl<T: Debug> Debug for Node<T> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
-            "Node {{ data: {:?}, next: {:?}, child: {:?}}}",
-            &self.data, self.next, self.child
+            "Node {{ data: {:?}, next: {:?}, child: {:?}, parent: {:?}, prev: {:?}}}",
+            &self.data, self.next, self.child, self.parent, self.prev
         )
     }
 }
 
 impl<T> Node<T> {
-    pub fn next_node(&mut self) -> Option<&mut NodeId> {
-        self.next.as_mut()
+    /// Returns the id of the next sibling, if any.
+    #[inline]
+    pub fn next_sibling(&self) -> Option<NodeId> {
+        self.next
     }
 
-    pub fn child(&mut self) -> Option<&mut NodeId> {
-        self.child.as_mut()
+    /// Returns the id of the first child, if any.
+    #[inline]
+    pub fn first_child(&self) -> Option<NodeId> {
+        self.child
     }
 
-    pub fn add_child(&mut self, arena: &mut TreeArena<T>, child: T) {
-        match self.child {
-            Some(id) => {
-                let child_id = arena.isolated_node(child);
+    /// Returns the id of the parent, if any.
+    ///
+    /// Top-level nodes and detached nodes don't have one.
+    #[inline]
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
 
-                unsafe {
-                    arena.private_add_child_to_parent(id, child_id);
-                }
-            }
-            None => self.child = Some(arena.isolated_node(child)),
-        }
+    /// Returns the id of the previous sibling, if any.
+    #[inline]
+    pub fn prev_sibling(&self) -> Option<NodeId> {
+        self.prev
+    }
+
+    /// Returns the id of the next sibling, like `Node::next_sibling`.
+    ///
+    /// Links can only be changed through the `TreeArena`,
+    /// which keeps the parent and sibling links in step.
+    #[inline]
+    pub fn next_node(&self) -> Option<NodeId> {
+        self.next
+    }
+
+    /// Returns the id of the first child, like `Node::first_child`.
+    #[inline]
+    pub fn child(&self) -> Option<NodeId> {
+        self.child
+    }
+}
+
+/// Error returned when a `TreeArena` can't index any more nodes.
+///
+/// That takes about a trillion nodes on 64-bit targets,
+/// and 16 million on 32-bit ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityError;
+
+impl fmt::Display for CapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the arena can't hold any more nodes")
     }
 }
 
+impl core::error::Error for CapacityError {}
+
 pub struct TreeArena<T> {
     /// Index of currently used `Vec`.
-    tracker: Index,
+    tracker: BucketIndex,
+
+    /// Generation of slots used for the first time.
+    generation: Generation,
+
+    /// Generation of every slot freed so far, given to
+    /// the next node allocated in it, indexed like `storage`.
+    slot_generations: Vec<Vec<Generation>>,
 
     /// Points to the current `Node` being processed.
     cursor: Option<NodeId>,
 
+    /// Points to the first top-level `Node`.
+    root: Option<NodeId>,
+
     /// Stores the `NodeId`s pointing to nodes
     /// as a path to the current node
     pub right_edge: Vec<NodeId>,
@@ -122,73 +225,41 @@ pub struct TreeArena<T> {
 
 impl<T: Debug> Debug for TreeArena<T> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        use crate::lib::String;
-        fn visit_disk<T: Debug>(
-            tree: &TreeArena<T>,
-            id: NodeId,
-            f: &mut core::fmt::Formatter<'_>,
-            ident: String,
-        ) -> core::fmt::Result {
-            let disk = tree.get(id).unwrap();
-
-            let mut new_ident = String::from(ident.as_str());
-            new_ident.push_str("  ");
+        // nodes left to print, along with their depth,
+        // kept on the heap so deep or long trees can't overflow the stack
+        let mut pending: Vec<(NodeId, usize)> = self.root().map(|id| (id, 1)).into_iter().collect();
 
-            match (disk.next, disk.child) {
-                (Some(next), Some(child)) => {
-                    writeln!(
-                        f,
-                        "{ident}Node: (child: {:?}, next: {:?}, val: {:?})",
-                        child, next, disk.data
-                    )?;
+        while let Some((id, depth)) = pending.pop() {
+            let Some(disk) = self.get(id) else { continue };
 
-                    visit_disk(tree, child, f, new_ident.clone())?;
+            write!(f, "{:width$}", "", width = depth * 2)?;
 
-                    visit_disk(tree, next, f, new_ident)?;
-                }
+            match (disk.next, disk.child) {
+                (Some(next), Some(child)) => writeln!(
+                    f,
+                    "Node: (child: {:?}, next: {:?}, val: {:?})",
+                    child, next, disk.data
+                )?,
                 (Some(next), None) => {
-                    writeln!(f, "{ident}Next: (next: {:?}, val: {:?})\n", next, disk.data)?;
-
-                    visit_disk(tree, next, f, new_ident)?;
+                    writeln!(f, "Next: (next: {:?}, val: {:?})\n", next, disk.data)?;
                 }
                 (_, Some(child)) => {
-                    writeln!(
-                        f,
-                        "{ident}Child: (child: {:?}, val: {:?})\n",
-                        child, disk.data
-                    )?;
-
-                    visit_disk(tree, child, f, new_ident)?;
+                    writeln!(f, "Child: (child: {:?}, val: {:?})\n", child, disk.data)?;
                 }
 
-                _ => {
-                    write!(f, "{ident}Leaf: (val: {:?})\n\n", disk.data)?;
-                }
+                _ => write!(f, "Leaf: (val: {:?})\n\n", disk.data)?,
             }
 
-            Ok(())
-        }
-
-        fn debug_tree<T: Debug>(
-            tree: &TreeArena<T>,
-            f: &mut core::fmt::Formatter<'_>,
-        ) -> core::fmt::Result {
-            match tree.storage.first() {
-                None => Ok(()),
+            if let Some(next) = disk.next {
+                pending.push((next, depth));
+            }
 
-                Some(_) => visit_disk(
-                    tree,
-                    NodeId {
-                        node_index: 0,
-                        vec_index: NonZero::new(1).unwrap(),
-                    },
-                    f,
-                    String::from("  "),
-                ),
+            if let Some(child) = disk.child {
+                pending.push((child, depth + 1));
             }
         }
 
-        debug_tree(self, f)
+        Ok(())
     }
 }
 
@@ -198,76 +269,153 @@ impl<T> Default for TreeArena<T> {
     }
 }
 
-#[allow(dead_code)]
+/// Amount of buckets the `TreeArena` reserves room for up front,
+/// as buckets grow geometrically this is plenty for most documents.
 const AMOUNT_OF_BUCKETS: usize = 40;
 
+/// Generation of the slots of removed nodes,
+/// never handed out to a slot so no id matches it.
+const DEAD: Generation = NodeId::MAX_GENERATION;
+
+// hands out a different generation to every arena, so ids
+// from one arena are stale in the next `MAX_GENERATION` ones
+fn next_arena_generation() -> Generation {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    loop {
+        #[allow(clippy::cast_possible_truncation)]
+        let generation =
+            NEXT.fetch_add(1, Ordering::Relaxed) as Generation & NodeId::MAX_GENERATION;
+
+        if generation != DEAD {
+            return generation;
+        }
+    }
+}
+
 impl<T> TreeArena<T> {
-    const DEFAULT_BASE_SIZE: usize = (8 * 1024) / size_of::<T>();
-    // const DEFAULT_BASE_SIZE_MINUS_ONE: usize = (8 * 1024) / size_of::<T>() - 1;
+    /// Capacity of the first bucket, about 8 KiB worth of nodes.
+    const DEFAULT_BASE_SIZE: usize = {
+        let size = (8 * 1024) / size_of::<Node<T>>();
 
-    pub fn new() -> TreeArena<T> {
-        let mut storage = Vec::with_capacity(AMOUNT_OF_BUCKETS);
-        // "canary" to ensure our indexes start from 1
-        // so they are eligible for `NonZero`
-        // therefore the size of `Option<NodeId>`
-        // becomes the same as `NodeId`
-        storage.push(Vec::new());
+        if size == 0 { 1 } else { size }
+    };
 
-        // actual first "bucket"
-        storage.push(Vec::with_capacity(Self::DEFAULT_BASE_SIZE));
+    /// Largest bucket, so that every node can be indexed by `Index`.
+    const MAX_BUCKET_SIZE: usize = Index::MAX as usize + 1;
 
+    /// Creates an empty `TreeArena`.
+    ///
+    /// Doesn't allocate until the first node is added.
+    pub fn new() -> TreeArena<T> {
         Self {
-            tracker: 1,
+            tracker: 0,
+            generation: next_arena_generation(),
+            slot_generations: Vec::new(),
             cursor: None,
-            right_edge: Vec::with_capacity(32),
-            storage,
+            root: None,
+            right_edge: Vec::new(),
+            storage: Vec::new(),
         }
     }
 
-    pub fn get(&self, id: NodeId) -> Option<&Node<T>> {
-        let ix: usize = NonZero::get(id.vec_index) as usize;
-        let vec_ix: Index = NonZero::get(id.vec_index);
+    /// Removes every node from the arena,
+    /// keeping the allocated memory for reuse.
+    ///
+    /// Every `NodeId` pointing into the arena becomes stale.
+    pub fn clear(&mut self) {
+        for bucket in 0..self.storage.len() {
+            for index in 0..self.storage[bucket].len() {
+                let generation = self.storage[bucket][index].generation;
+
+                // removed slots were freed already
+                if generation != DEAD {
+                    self.free_slot(bucket, index, generation);
+                }
+            }
 
-        if vec_ix > self.tracker || id.node_index as usize >= self.storage[ix].len() {
-            return None;
+            self.storage[bucket].clear();
         }
 
-        Some(unsafe { self.get_unchecked(id) })
+        self.tracker = 0;
+        self.cursor = None;
+        self.root = None;
+        self.right_edge.clear();
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node<T>> {
+        self.storage
+            .get(id.vec_index() as usize)?
+            .get(id.node_index() as usize)
+            .filter(|node| node.generation == id.generation())
     }
 
+    /// Returns the node under `id` without bounds and generation checks.
+    ///
+    /// # Safety
+    /// `id` must point to a node allocated in this arena.
     pub unsafe fn get_unchecked(&self, id: NodeId) -> &Node<T> {
         unsafe {
             self.storage
-                .get_unchecked(NonZero::get(id.vec_index) as usize)
-                .get_unchecked(id.node_index as usize)
+                .get_unchecked(id.vec_index() as usize)
+                .get_unchecked(id.node_index() as usize)
         }
     }
 
     pub fn get_mut(&mut self, id: NodeId) -> Option<&mut Node<T>> {
-        if NonZero::get(id.vec_index) > self.tracker
-            || id.node_index > Self::DEFAULT_BASE_SIZE as Index
-        {
-            None
-        } else {
-            Some(unsafe { self.get_unchecked_mut(id) })
-        }
+        self.storage
+            .get_mut(id.vec_index() as usize)?
+            .get_mut(id.node_index() as usize)
+            .filter(|node| node.generation == id.generation())
     }
 
+    /// Returns the node under `id` without bounds and generation checks.
+    ///
+    /// # Safety
+    /// `id` must point to a node allocated in this arena.
     pub unsafe fn get_unchecked_mut(&mut self, id: NodeId) -> &mut Node<T> {
         unsafe {
             self.storage
-                .get_unchecked_mut(NonZero::get(id.vec_index) as usize)
-                .get_unchecked_mut(id.node_index as usize)
+                .get_unchecked_mut(id.vec_index() as usize)
+                .get_unchecked_mut(id.node_index() as usize)
         }
     }
 
+    /// Returns the amount of nodes allocated in this arena,
+    /// including detached and removed ones.
+    pub fn len(&self) -> usize {
+        self.storage.iter().map(Vec::len).sum()
+    }
+
+    /// Returns an iterator over the data of every node allocated
+    /// in this arena, in allocation order.
+    pub(crate) fn data_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.storage.iter_mut().flatten().map(|node| &mut node.data)
+    }
+
+    /// Returns `true` if no node was allocated in this arena.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the id of the first node of the tree,
+    /// from which every traversal starts.
+    #[inline]
+    pub fn root(&self) -> Option<NodeId> {
+        self.root
+    }
+
     #[inline]
     pub fn storage(&self) -> &[Node<T>] {
-        &self.storage[self.tracker as usize]
+        self.storage
+            .get(self.tracker as usize)
+            .map_or(&[], Vec::as_slice)
     }
 
     #[inline]
-    pub fn tracker(&self) -> Index {
+    pub fn tracker(&self) -> BucketIndex {
         self.tracker
     }
 
@@ -281,18 +429,40 @@ impl<T> TreeArena<T> {
         &self.right_edge
     }
 
-    pub fn attach_node(&mut self, item: T) -> NodeId {
-        let ix = self.isolated_node(item);
+    /// Attaches a node holding `item` after the current node,
+    /// making it the current node.
+    ///
+    /// # Errors
+    /// if the arena can't index any more nodes.
+    pub fn attach_node(&mut self, item: T) -> Result<NodeId, CapacityError> {
+        let ix = self.isolated_node(item)?;
+
+        let prev = self.cursor;
+        let parent = match prev {
+            Some(cur) => self.get(cur).and_then(|node| node.parent),
+            None => self.right_edge.last().copied(),
+        };
+
+        if let Some(node) = self.get_mut(ix) {
+            node.prev = prev;
+            node.parent = parent;
+        }
 
         if let Some(cur) = self.cursor {
-            self.get_mut(cur).unwrap().next = Some(ix)
+            if let Some(node) = self.get_mut(cur) {
+                node.next = Some(ix);
+            }
         } else if let Some(&parent) = self.right_edge.last() {
-            self.get_mut(parent).unwrap().child = Some(ix)
+            if let Some(node) = self.get_mut(parent) {
+                node.child = Some(ix);
+            }
+        } else {
+            self.root = Some(ix)
         }
 
         self.cursor = Some(ix);
 
-        ix
+        Ok(ix)
     }
 
     pub fn go_up(&mut self) -> Option<NodeId> {
@@ -302,8 +472,12 @@ impl<T> TreeArena<T> {
         Some(ix)
     }
 
+    /// Makes the current node the parent of the nodes attached next.
+    ///
+    /// # Panics
+    /// if no node was attached yet.
     pub fn go_down(&mut self) -> NodeId {
-        let ix = self.cursor.unwrap();
+        let ix = self.cursor.expect("`go_down` needs an attached node");
         self.right_edge.push(ix);
 
         self.cursor = self.get(ix).and_then(|x| x.child);
@@ -315,35 +489,83 @@ impl<T> TreeArena<T> {
         self.cursor = self.right_edge.last().copied();
     }
 
-    fn isolated_node(&mut self, data: T) -> NodeId {
+    fn isolated_node(&mut self, data: T) -> Result<NodeId, CapacityError> {
         let item = Node {
             data,
             next: None,
             child: None,
+            parent: None,
+            prev: None,
+            generation: self.generation,
         };
 
-        let ix = if self.storage_mut().len() == AMOUNT_OF_BUCKETS {
-            self.slow_alloc(item, self.storage.len())
+        let ix = if self.storage().len() >= Self::bucket_capacity(self.tracker) {
+            self.slow_alloc(item)?
         } else {
             self.fast_alloc(item)
         };
 
-        NodeId::from_indexes(ix, self.tracker)
-            .unwrap_or_else(|| unreachable!("vec indices should be larger than 0"))
+        // a slot which was freed before goes on with its own generation
+        let generation = self
+            .slot_generations
+            .get(self.tracker as usize)
+            .and_then(|bucket| bucket.get(ix as usize))
+            .copied()
+            .unwrap_or(self.generation);
+
+        if let Some(node) = self.storage_mut().last_mut() {
+            node.generation = generation;
+        }
+
+        Ok(NodeId::from_indexes(ix, self.tracker, generation)
+            .unwrap_or_else(|| unreachable!("vec indices should be larger than 0")))
+    }
+
+    /// Returns how many nodes fit in the given bucket.
+    ///
+    /// Every bucket is twice as large as the previous one,
+    /// up to `MAX_BUCKET_SIZE`, so a bucket is never reallocated
+    /// and the amount of buckets stays logarithmic.
+    fn bucket_capacity(bucket: BucketIndex) -> usize {
+        // the canary never holds any nodes
+        let Some(shift) = bucket.checked_sub(1) else {
+            return 0;
+        };
+        let shift = u32::from(shift).min(usize::BITS - 1);
+
+        Self::DEFAULT_BASE_SIZE
+            .saturating_mul(1 << shift)
+            .min(Self::MAX_BUCKET_SIZE)
     }
 
-    fn slow_alloc(&mut self, item: Node<T>, cap: usize) -> Index {
-        self.tracker += 1;
+    // the current bucket is full, moves on to the next, larger one
+    fn slow_alloc(&mut self, item: Node<T>) -> Result<Index, CapacityError> {
+        if self.storage.is_empty() {
+            self.storage.reserve(AMOUNT_OF_BUCKETS);
 
-        let mut new_vec = Vec::with_capacity(cap);
-        new_vec.push(item);
+            // "canary" to ensure our indexes start from 1
+            // so they are eligible for `NonZero`
+            // therefore the size of `Option<NodeId>`
+            // becomes the same as `NodeId`
+            self.storage.push(Vec::new());
+        }
+
+        self.tracker = self.tracker.checked_add(1).ok_or(CapacityError)?;
+
+        // buckets left over by `TreeArena::clear` are reused
+        if self.storage.len() <= self.tracker as usize {
+            let capacity = Self::bucket_capacity(self.tracker);
+            self.storage.push(Vec::with_capacity(capacity));
+        }
 
-        self.storage.push(new_vec);
+        self.storage_mut().push(item);
 
-        0
+        Ok(0)
     }
 
     fn fast_alloc(&mut self, item: Node<T>) -> Index {
+        // buckets never hold more than `MAX_BUCKET_SIZE` nodes
+        #[allow(clippy::cast_possible_truncation)]
         let len = self.storage_mut().len() as Index;
         self.storage_mut().push(item);
 
@@ -355,83 +577,1464 @@ impl<T> TreeArena<T> {
         &mut self.storage[self.tracker as usize]
     }
 
-    #[inline]
-    unsafe fn private_add_child_to_parent(&mut self, parent: NodeId, child: NodeId) {
-        let patient = unsafe { self.get_unchecked_mut(parent) };
+    // records the generation the slot gets once it is reused,
+    // so the ids of its previous node stay stale
+    fn free_slot(&mut self, bucket: usize, index: usize, generation: Generation) {
+        if self.slot_generations.len() <= bucket {
+            self.slot_generations.resize_with(bucket + 1, Vec::new);
+        }
 
-        match patient.child {
-            None => {
-                patient.child = Some(child);
-            }
-            Some(actual_child) => unsafe {
-                self.private_add_child_to_parent(actual_child, child);
-            },
+        let slots = &mut self.slot_generations[bucket];
+        if slots.len() <= index {
+            slots.resize(index + 1, self.generation);
+        }
+
+        let mut next = generation.wrapping_add(1) & NodeId::MAX_GENERATION;
+        if next == DEAD {
+            next = 0;
+        }
+
+        slots[index] = next;
+    }
+
+    // follows the `next` links from `id` to the last sibling
+    fn last_sibling(&self, id: NodeId) -> NodeId {
+        let mut last = id;
+
+        while let Some(next) = self.get(last).and_then(|node| node.next) {
+            last = next;
         }
+
+        last
     }
 }
 
-impl TreeArena<AstNode> {
-    pub fn preorder_visit<V>(&self, visitor: &mut V)
-    where
-        V: Visitor,
-    {
-        self.inner_preorder(
-            Some(NodeId {
-                node_index: 0,
-                vec_index: NonZero::new(1).expect("infallible"),
-            }),
-            visitor,
-        );
+impl<T> TreeArena<T> {
+    // checks if `id` is `ancestor` or one of its descendants
+    fn contains(&self, ancestor: NodeId, id: NodeId) -> bool {
+        id == ancestor || self.ancestors(id).any(|id| id == ancestor)
     }
 
-    fn inner_preorder<V>(&self, node: Option<NodeId>, visitor: &mut V)
-    where
-        V: Visitor,
-    {
-        let Some(id) = node else { return };
+    // checks if something links to `id`,
+    // which is true for every node except detached ones
+    fn is_linked(&self, id: NodeId) -> bool {
+        self.get(id)
+            .is_some_and(|node| node.parent.is_some() || node.prev.is_some())
+            || self.root == Some(id)
+    }
+
+    // links the detached node `id` between `prev` and `next` under `parent`
+    fn link(
+        &mut self,
+        id: NodeId,
+        parent: Option<NodeId>,
+        prev: Option<NodeId>,
+        next: Option<NodeId>,
+    ) {
+        let Some(node) = self.get_mut(id) else { return };
+        node.parent = parent;
+        node.prev = prev;
+        node.next = next;
+
+        match (prev, parent) {
+            (Some(prev), _) => {
+                if let Some(node) = self.get_mut(prev) {
+                    node.next = Some(id);
+                }
+            }
+            (None, Some(parent)) => {
+                if let Some(node) = self.get_mut(parent) {
+                    node.child = Some(id);
+                }
+            }
+            (None, None) => self.root = Some(id),
+        }
+
+        if let Some(next) = next
+            && let Some(node) = self.get_mut(next)
+        {
+            node.prev = Some(id);
+        }
+    }
+
+    // unlinks `id` from its parent and siblings
+    fn unlink(&mut self, id: NodeId) -> bool {
+        if !self.is_linked(id) {
+            return false;
+        }
 
-        let Some(target) = self.get(id) else {
-            return;
+        let Some(node) = self.get_mut(id) else {
+            return false;
         };
 
-        visitor.visit_node(&target.data);
+        let parent = node.parent.take();
+        let prev = node.prev.take();
+        let next = node.next.take();
+
+        match (prev, parent) {
+            (Some(prev), _) => {
+                if let Some(node) = self.get_mut(prev) {
+                    node.next = next;
+                }
+            }
+            (None, Some(parent)) => {
+                if let Some(node) = self.get_mut(parent) {
+                    node.child = next;
+                }
+            }
+            (None, None) => self.root = next,
+        }
+
+        if let Some(next) = next
+            && let Some(node) = self.get_mut(next)
+        {
+            node.prev = prev;
+        }
 
-        self.inner_preorder(target.child, visitor);
-        self.inner_preorder(target.next, visitor);
+        true
     }
 
-    pub fn preorder_visit_mut<V>(&mut self, visitor: &mut V)
-    where
-        V: MutVisitor,
-    {
-        self.inner_preorder_mut(
-            Some(NodeId {
-                node_index: 0,
-                vec_index: NonZero::new(1).expect("infallible"),
-            }),
-            visitor,
-        );
+    // checks if a node can be attached at `to`
+    fn can_place(&self, to: Anchor) -> bool {
+        match to {
+            Anchor::Before(sibling) | Anchor::After(sibling) => self.is_linked(sibling),
+            Anchor::FirstChild(parent) | Anchor::LastChild(parent) => self.get(parent).is_some(),
+        }
     }
 
-    fn inner_preorder_mut<V>(&mut self, node: Option<NodeId>, visitor: &mut V)
-    where
-        V: MutVisitor,
-    {
-        let Some(id) = node else { return };
+    // links the detached node `id` at `to`
+    fn place(&mut self, id: NodeId, to: Anchor) -> bool {
+        let Some(anchor) = self.get(to.node()) else {
+            return false;
+        };
+
+        let (parent, prev, next) = match to {
+            Anchor::Before(sibling) => (anchor.parent, anchor.prev, Some(sibling)),
+            Anchor::After(sibling) => (anchor.parent, Some(sibling), anchor.next),
+            Anchor::FirstChild(parent) => (Some(parent), None, anchor.child),
+            Anchor::LastChild(parent) => (
+                Some(parent),
+                anchor.child.map(|first| self.last_sibling(first)),
+                None,
+            ),
+        };
+
+        self.link(id, parent, prev, next);
+
+        true
+    }
+
+    /// Allocates a node holding `data`, detached from the tree.
+    ///
+    /// It can be attached later on with `TreeArena::move_subtree`.
+    ///
+    /// # Errors
+    /// if the arena can't index any more nodes.
+    pub fn new_node(&mut self, data: T) -> Result<NodeId, CapacityError> {
+        self.isolated_node(data)
+    }
+
+    /// Inserts a node holding `data` as the previous sibling of `sibling`.
+    ///
+    /// Returns `None` if `sibling` isn't attached to the tree,
+    /// or if the arena can't index any more nodes.
+    pub fn insert_before(&mut self, sibling: NodeId, data: T) -> Option<NodeId> {
+        self.insert(data, Anchor::Before(sibling))
+    }
+
+    /// Inserts a node holding `data` as the next sibling of `sibling`.
+    ///
+    /// Returns `None` if `sibling` isn't attached to the tree,
+    /// or if the arena can't index any more nodes.
+    pub fn insert_after(&mut self, sibling: NodeId, data: T) -> Option<NodeId> {
+        self.insert(data, Anchor::After(sibling))
+    }
+
+    /// Inserts a node holding `data` as the first child of `parent`.
+    ///
+    /// Returns `None` if `parent` doesn't exist,
+    /// or if the arena can't index any more nodes.
+    pub fn prepend_child(&mut self, parent: NodeId, data: T) -> Option<NodeId> {
+        self.insert(data, Anchor::FirstChild(parent))
+    }
+
+    /// Inserts a node holding `data` as the last child of `parent`.
+    ///
+    /// Returns `None` if `parent` doesn't exist,
+    /// or if the arena can't index any more nodes.
+    pub fn append_child(&mut self, parent: NodeId, data: T) -> Option<NodeId> {
+        self.insert(data, Anchor::LastChild(parent))
+    }
+
+    fn insert(&mut self, data: T, to: Anchor) -> Option<NodeId> {
+        // don't allocate a node which would be left dangling
+        if !self.can_place(to) {
+            return None;
+        }
+
+        let id = self.isolated_node(data).ok()?;
+        self.place(id, to);
+
+        Some(id)
+    }
+
+    /// Detaches the node `id`, along with its descendants, from the tree.
+    ///
+    /// The detached nodes stay allocated and can be attached again
+    /// with `TreeArena::move_subtree`.
+    ///
+    /// Returns `false` if `id` wasn't attached to the tree.
+    pub fn detach(&mut self, id: NodeId) -> bool {
+        self.unlink(id)
+    }
+
+    /// Detaches the node `id` and removes it, along with its descendants,
+    /// from the arena.
+    ///
+    /// Every `NodeId` pointing to the removed nodes becomes stale,
+    /// `TreeArena::get` returns `None` for them.
+    ///
+    /// Returns `false` if `id` doesn't exist.
+    pub fn remove(&mut self, id: NodeId) -> bool {
+        if self.get(id).is_none() {
+            return false;
+        }
+
+        self.unlink(id);
+
+        let mut removed = self.descendants(id).collect::<Vec<_>>();
+        removed.push(id);
+
+        for id in removed {
+            if let Some(node) = self.get_mut(id) {
+                let generation = core::mem::replace(&mut node.generation, DEAD);
+                self.free_slot(id.vec_index().into(), id.node_index() as usize, generation);
+            }
+        }
+
+        true
+    }
+
+    /// Puts the node `with`, along with its descendants,
+    /// in place of the node `id`, which gets detached.
+    ///
+    /// Returns `false` if `id` isn't attached to the tree
+    /// or `id` is a descendant of `with`.
+    pub fn replace(&mut self, id: NodeId, with: NodeId) -> bool {
+        if self.get(with).is_none() || self.contains(with, id) || !self.is_linked(id) {
+            return false;
+        }
 
-        if let Some(target) = self.get_mut(id) {
-            visitor.visit_node_mut(&mut target.data);
+        self.unlink(with);
+
+        let Some(node) = self.get(id) else {
+            return false;
+        };
+
+        let (parent, prev, next) = (node.parent, node.prev, node.next);
+
+        self.unlink(id);
+        self.link(with, parent, prev, next);
+
+        true
+    }
+
+    /// Moves the node `id`, along with its descendants, to `to`.
+    ///
+    /// Returns `false` if the anchor of `to` doesn't exist
+    /// or is a part of the moved subtree.
+    pub fn move_subtree(&mut self, id: NodeId, to: Anchor) -> bool {
+        let anchor = to.node();
+
+        if self.get(id).is_none() || self.contains(id, anchor) {
+            return false;
+        }
+
+        if !self.can_place(to) {
+            return false;
+        }
+
+        self.unlink(id);
+        self.place(id, to)
+    }
+
+    // applies the queued edits in order, returning the ids
+    // of the nodes whose edit couldn't be applied
+    fn apply(&mut self, edits: Edits<T>) -> Vec<NodeId> {
+        let mut failed = Vec::new();
+
+        for edit in edits.queue {
+            let (id, applied) = match edit {
+                Edit::Remove(id) => (id, self.remove(id)),
+
+                // don't allocate a node which would be left dangling
+                Edit::Replace(id, data) if self.is_linked(id) => {
+                    let replaced = self
+                        .isolated_node(data)
+                        .is_ok_and(|new| self.replace(id, new));
+                    (id, replaced)
+                }
+                Edit::Replace(id, _) => (id, false),
+
+                Edit::InsertBefore(id, data) => (id, self.insert_before(id, data).is_some()),
+                Edit::InsertAfter(id, data) => (id, self.insert_after(id, data).is_some()),
+                Edit::AppendChild(id, data) => (id, self.append_child(id, data).is_some()),
+            };
+
+            if !applied {
+                failed.push(id);
+            }
         }
 
-        self.inner_preorder_mut(self.get(id).and_then(|x| x.child), visitor);
-        self.inner_preorder_mut(self.get(id).and_then(|x| x.child), visitor);
+        failed
     }
 }
 
-pub trait Visitor {
-    fn visit_node(&mut self, value: &AstNode);
+impl TreeArena<AstNode> {
+    /// Copies the node `root` of `other`, along with its descendants,
+    /// as the last child of `at`.
+    ///
+    /// `source` is the id under which the text `other` was parsed from
+    /// is known to this tree, so the copies of nodes pointing into
+    /// `SourceId::MAIN` of `other` point into `source` instead.
+    /// Positions into other sources, and generated ones, stay the same.
+    ///
+    /// Returns the id of the copy of `root`,
+    /// or `None` if `at` or `root` doesn't exist.
+    pub fn graft(
+        &mut self,
+        at: NodeId,
+        other: &TreeArena<AstNode>,
+        root: NodeId,
+        source: SourceId,
+    ) -> Option<NodeId> {
+        // don't copy nodes which would be left dangling
+        if !self.can_place(Anchor::LastChild(at)) {
+            return None;
+        }
+
+        let copy = self.copy_subtree(other, root, |node| {
+            let mut node = node.clone();
+
+            if node.pos.source == SourceId::MAIN {
+                node.pos.source = source;
+            }
+
+            node
+        })?;
+        self.place(copy, Anchor::LastChild(at));
+
+        Some(copy)
+    }
 }
 
-pub trait MutVisitor {
-    fn visit_node_mut(&mut self, value: &mut AstNode);
+/// Where to attach a node, relative to another one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    /// As the previous sibling of the node.
+    Before(NodeId),
+
+    /// As the next sibling of the node.
+    After(NodeId),
+
+    /// As the first child of the node.
+    FirstChild(NodeId),
+
+    /// As the last child of the node.
+    LastChild(NodeId),
+}
+
+impl Anchor {
+    /// Returns the node relative to which the attaching happens.
+    pub fn node(self) -> NodeId {
+        match self {
+            Self::Before(id) | Self::After(id) | Self::FirstChild(id) | Self::LastChild(id) => id,
+        }
+    }
+}
+
+impl<T> TreeArena<T> {
+    /// Returns the parent of `id`, if any.
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.get(id)?.parent
+    }
+
+    /// Returns an iterator over the children of `id`.
+    pub fn children(&self, id: NodeId) -> Siblings<'_, T> {
+        Siblings {
+            tree: self,
+            next: self.get(id).and_then(|node| node.child),
+            forward: true,
+        }
+    }
+
+    /// Returns an iterator over the ancestors of `id`,
+    /// starting with its parent.
+    pub fn ancestors(&self, id: NodeId) -> Ancestors<'_, T> {
+        Ancestors {
+            tree: self,
+            next: self.parent(id),
+        }
+    }
+
+    /// Returns an iterator over the descendants of `id` in preorder,
+    /// not including `id` itself.
+    pub fn descendants(&self, id: NodeId) -> Descendants<'_, T> {
+        Descendants {
+            tree: self,
+            root: Some(id),
+            next: self.get(id).and_then(|node| node.child),
+        }
+    }
+
+    /// Returns an iterator over every node of the tree in preorder.
+    pub fn nodes(&self) -> Descendants<'_, T> {
+        Descendants {
+            tree: self,
+            root: None,
+            next: self.root,
+        }
+    }
+
+    /// Returns an iterator over the siblings after `id`.
+    pub fn following_siblings(&self, id: NodeId) -> Siblings<'_, T> {
+        Siblings {
+            tree: self,
+            next: self.get(id).and_then(|node| node.next),
+            forward: true,
+        }
+    }
+
+    /// Returns an iterator over the siblings before `id`,
+    /// starting with the closest one.
+    pub fn preceding_siblings(&self, id: NodeId) -> Siblings<'_, T> {
+        Siblings {
+            tree: self,
+            next: self.get(id).and_then(|node| node.prev),
+            forward: false,
+        }
+    }
+}
+
+impl<T: Clone> TreeArena<T> {
+    /// Copies the node `id`, along with its descendants,
+    /// into a new arena where the copy of `id` is the root.
+    ///
+    /// Returns an empty arena if `id` doesn't exist.
+    pub fn clone_subtree(&self, id: NodeId) -> TreeArena<T> {
+        let mut tree = TreeArena::new();
+
+        if let Some(copy) = tree.copy_subtree(self, id, T::clone) {
+            tree.root = Some(copy);
+            tree.cursor = Some(copy);
+        }
+
+        tree
+    }
+
+    // copies the subtree of `other` under `root` as a detached subtree,
+    // turning the data of every node into its copy with `copy_data`
+    fn copy_subtree<F>(
+        &mut self,
+        other: &TreeArena<T>,
+        root: NodeId,
+        mut copy_data: F,
+    ) -> Option<NodeId>
+    where
+        F: FnMut(&T) -> T,
+    {
+        let copy = self.isolated_node(copy_data(&other.get(root)?.data)).ok()?;
+
+        // copied ancestors of the current node
+        // as `(original, copy, last copied child)`
+        let mut path = Vec::from([(root, copy, None)]);
+
+        for id in other.descendants(root) {
+            let Some(node) = other.get(id) else { continue };
+
+            while path
+                .last()
+                .is_some_and(|&(original, _, _)| Some(original) != node.parent)
+            {
+                path.pop();
+            }
+
+            let Some((_, parent, last_child)) = path.last_mut() else {
+                break;
+            };
+
+            // a partial copy would be left unreachable
+            let Ok(child) = self.isolated_node(copy_data(&node.data)) else {
+                self.remove(copy);
+                return None;
+            };
+            let (parent, prev) = (*parent, last_child.replace(child));
+            self.link(child, Some(parent), prev, None);
+
+            path.push((id, child, None));
+        }
+
+        Some(copy)
+    }
+}
+
+/// Iterator over sibling nodes.
+///
+/// Created by `TreeArena::children`, `TreeArena::following_siblings`
+/// and `TreeArena::preceding_siblings`.
+pub struct Siblings<'t, T> {
+    tree: &'t TreeArena<T>,
+    next: Option<NodeId>,
+    forward: bool,
+}
+
+impl<T> Iterator for Siblings<'_, T> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next?;
+        let node = self.tree.get(current)?;
+
+        self.next = if self.forward { node.next } else { node.prev };
+
+        Some(current)
+    }
+}
+
+/// Iterator over the ancestors of a node.
+///
+/// Created by `TreeArena::ancestors`.
+pub struct Ancestors<'t, T> {
+    tree: &'t TreeArena<T>,
+    next: Option<NodeId>,
+}
+
+impl<T> Iterator for Ancestors<'_, T> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next?;
+        self.next = self.tree.parent(current);
+
+        Some(current)
+    }
+}
+
+/// Preorder iterator over nodes.
+///
+/// Created by `TreeArena::descendants` and `TreeArena::nodes`.
+pub struct Descendants<'t, T> {
+    tree: &'t TreeArena<T>,
+
+    /// Node whose descendants are iterated over,
+    /// `None` if it's the whole tree.
+    root: Option<NodeId>,
+
+    next: Option<NodeId>,
+}
+
+impl<T> Iterator for Descendants<'_, T> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.take()?;
+        let node = self.tree.get(current)?;
+
+        if node.child.is_some() {
+            self.next = node.child;
+
+            return Some(current);
+        }
+
+        // climb up until a node with a next sibling is found
+        let mut climb = current;
+        while Some(climb) != self.root {
+            let Some(node) = self.tree.get(climb) else {
+                break;
+            };
+
+            if node.next.is_some() {
+                self.next = node.next;
+                break;
+            }
+
+            let Some(parent) = node.parent else { break };
+            climb = parent;
+        }
+
+        Some(current)
+    }
+}
+
+enum Edit<T> {
+    Remove(NodeId),
+    Replace(NodeId, T),
+    InsertBefore(NodeId, T),
+    InsertAfter(NodeId, T),
+    AppendChild(NodeId, T),
+}
+
+/// Structural edits queued up during `TreeArena::walk_mut`,
+/// applied in order once the walk is over.
+pub struct Edits<T> {
+    queue: Vec<Edit<T>>,
+}
+
+impl<T> Default for Edits<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Edits<T> {
+    pub fn new() -> Self {
+        Self { queue: Vec::new() }
+    }
+
+    /// Removes the node `id`, along with its children, from the tree.
+    pub fn remove(&mut self, id: NodeId) {
+        self.queue.push(Edit::Remove(id));
+    }
+
+    /// Replaces the node `id` with a childless node holding `data`.
+    pub fn replace(&mut self, id: NodeId, data: T) {
+        self.queue.push(Edit::Replace(id, data));
+    }
+
+    /// Inserts a node holding `data` as the previous sibling of `id`.
+    pub fn insert_before(&mut self, id: NodeId, data: T) {
+        self.queue.push(Edit::InsertBefore(id, data));
+    }
+
+    /// Inserts a node holding `data` as the next sibling of `id`.
+    pub fn insert_after(&mut self, id: NodeId, data: T) {
+        self.queue.push(Edit::InsertAfter(id, data));
+    }
+
+    /// Inserts a node holding `data` as the last child of `id`.
+    pub fn append_child(&mut self, id: NodeId, data: T) {
+        self.queue.push(Edit::AppendChild(id, data));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl TreeArena<AstNode> {
+    /// Walks the whole tree, calling `TreeVisitor::enter` on the way down
+    /// and `TreeVisitor::exit` on the way up of every node.
+    pub fn walk<V>(&self, visitor: &mut V)
+    where
+        V: TreeVisitor,
+    {
+        self.inner_walk(self.root(), false, visitor);
+    }
+
+    /// Walks the node `id` and its descendants,
+    /// without continuing to its siblings.
+    pub fn walk_subtree<V>(&self, id: NodeId, visitor: &mut V)
+    where
+        V: TreeVisitor,
+    {
+        self.inner_walk(Some(id), true, visitor);
+    }
+
+    fn inner_walk<V>(&self, start: Option<NodeId>, bounded: bool, visitor: &mut V)
+    where
+        V: TreeVisitor,
+    {
+        // nodes which were entered but not yet exited
+        let mut path: Vec<NodeId> = Vec::new();
+        let mut cursor = start;
+
+        loop {
+            let (id, target) = if let Some(id) = cursor {
+                let Some(target) = self.get(id) else { return };
+
+                match visitor.enter(id, &target.data, path.len()) {
+                    Control::Stop => return,
+
+                    Control::Continue => {
+                        path.push(id);
+                        cursor = target.child;
+
+                        continue;
+                    }
+
+                    Control::SkipChildren => (id, target),
+                }
+            } else {
+                let Some(id) = path.pop() else { return };
+                let Some(target) = self.get(id) else { return };
+
+                (id, target)
+            };
+
+            visitor.exit(id, &target.data, path.len());
+
+            cursor = if bounded && path.is_empty() {
+                None
+            } else {
+                target.next
+            };
+        }
+    }
+
+    pub fn preorder_visit<V>(&self, visitor: &mut V)
+    where
+        V: Visitor,
+    {
+        struct Preorder<'v, V>(&'v mut V);
+
+        impl<V: Visitor> TreeVisitor for Preorder<'_, V> {
+            fn enter(&mut self, _: NodeId, node: &AstNode, _: usize) -> Control {
+                self.0.visit_node(node);
+
+                Control::Continue
+            }
+        }
+
+        self.walk(&mut Preorder(visitor));
+    }
+
+    /// Walks the whole tree like `TreeArena::walk`, handing out
+    /// mutable access to every node.
+    ///
+    /// Structural edits queued up in the `Edits` given to the visitor
+    /// are applied once the walk is over.
+    ///
+    /// Returns the ids of the nodes whose queued edit couldn't be applied,
+    /// e.g. because an earlier edit removed them.
+    pub fn walk_mut<V>(&mut self, visitor: &mut V) -> Vec<NodeId>
+    where
+        V: TreeVisitorMut,
+    {
+        let mut edits = Edits::new();
+        let mut path: Vec<NodeId> = Vec::new();
+        let mut cursor = self.root;
+
+        loop {
+            let id = if let Some(id) = cursor {
+                let Some(target) = self.get_mut(id) else {
+                    break;
+                };
+
+                match visitor.enter(id, &mut target.data, path.len(), &mut edits) {
+                    Control::Stop => break,
+
+                    Control::Continue => {
+                        path.push(id);
+                        cursor = target.child;
+
+                        continue;
+                    }
+
+                    Control::SkipChildren => id,
+                }
+            } else {
+                let Some(id) = path.pop() else { break };
+
+                id
+            };
+
+            let Some(target) = self.get_mut(id) else {
+                break;
+            };
+            visitor.exit(id, &mut target.data, path.len(), &mut edits);
+
+            cursor = target.next;
+        }
+
+        self.apply(edits)
+    }
+
+    pub fn preorder_visit_mut<V>(&mut self, visitor: &mut V)
+    where
+        V: MutVisitor,
+    {
+        struct Preorder<'v, V>(&'v mut V);
+
+        impl<V: MutVisitor> TreeVisitorMut for Preorder<'_, V> {
+            fn enter(
+                &mut self,
+                _: NodeId,
+                node: &mut AstNode,
+                _: usize,
+                _: &mut Edits<AstNode>,
+            ) -> Control {
+                self.0.visit_node_mut(node);
+
+                Control::Continue
+            }
+        }
+
+        // a `MutVisitor` can't queue edits
+        self.walk_mut(&mut Preorder(visitor));
+    }
+}
+
+pub trait Visitor {
+    fn visit_node(&mut self, value: &AstNode);
+}
+
+/// Tells a walk how to proceed after entering a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// Descend into the children of the node.
+    Continue,
+
+    /// Do not descend into the children of the node,
+    /// it is still exited.
+    SkipChildren,
+
+    /// Stop the walk, no more nodes are entered or exited.
+    Stop,
+}
+
+/// Visitor notified when a walk enters and exits a node.
+///
+/// `depth` is the amount of ancestors of the node
+/// relative to where the walk started.
+pub trait TreeVisitor {
+    fn enter(&mut self, id: NodeId, node: &AstNode, depth: usize) -> Control {
+        let _ = (id, node, depth);
+
+        Control::Continue
+    }
+
+    fn exit(&mut self, id: NodeId, node: &AstNode, depth: usize) {
+        let _ = (id, node, depth);
+    }
+}
+
+pub trait MutVisitor {
+    fn visit_node_mut(&mut self, value: &mut AstNode);
+}
+
+/// Visitor notified when a walk enters and exits a node,
+/// with mutable access to it.
+///
+/// Structural changes to the tree are queued up in `edits`.
+pub trait TreeVisitorMut {
+    fn enter(
+        &mut self,
+        id: NodeId,
+        node: &mut AstNode,
+        depth: usize,
+        edits: &mut Edits<AstNode>,
+    ) -> Control {
+        let _ = (id, node, depth, edits);
+
+        Control::Continue
+    }
+
+    fn exit(&mut self, id: NodeId, node: &mut AstNode, depth: usize, edits: &mut Edits<AstNode>) {
+        let _ = (id, node, depth, edits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Anchor, Control, Edits, MutVisitor, NodeId, TreeArena, TreeVisitor, TreeVisitorMut, Visitor,
+    };
+    use crate::ast::{AstNode, Position, SourceId, Value};
+    use crate::block_parser::{Parser, parse};
+    use crate::events::Events;
+    use crate::lib::{String, Vec};
+    use crate::render::html::push_html;
+    use crate::source::SourceMap;
+
+    #[derive(Default)]
+    struct Trace {
+        log: Vec<String>,
+        skip_lists: bool,
+        stop_at_heading: bool,
+    }
+
+    impl TreeVisitor for Trace {
+        fn enter(&mut self, _: NodeId, node: &AstNode, depth: usize) -> Control {
+            self.log.push(format!("{depth}>{:?}", node.value));
+
+            match node.value {
+                Value::OrderedList { .. } if self.skip_lists => Control::SkipChildren,
+                Value::Heading { .. } if self.stop_at_heading => Control::Stop,
+
+                _ => Control::Continue,
+            }
+        }
+
+        fn exit(&mut self, _: NodeId, node: &AstNode, depth: usize) {
+            self.log.push(format!("{depth}<{:?}", node.value));
+        }
+    }
+
+    #[test]
+    fn walk_enter_exit() {
+        let tree = parse("> Salt water.");
+        let mut trace = Trace::default();
+
+        tree.walk(&mut trace);
+
+        assert_eq!(
+            trace.log,
+            [
+                "0>Blockquote",
+                "1>Paragraph",
+                "2>Text",
+                "2<Text",
+                "1<Paragraph",
+                "0<Blockquote"
+            ]
+        );
+    }
+
+    #[test]
+    fn walk_skip_children() {
+        let tree = parse("1. One\n2. Two\n");
+        let mut trace = Trace {
+            skip_lists: true,
+            ..Trace::default()
+        };
+
+        tree.walk(&mut trace);
+
+        assert_eq!(
+            trace.log,
+            [
+                "0>OrderedList { tight: true, start_index: 1 }",
+                "0<OrderedList { tight: true, start_index: 1 }"
+            ]
+        );
+    }
+
+    #[test]
+    fn walk_stop() {
+        let tree = parse("# Title\n\nSome text.");
+        let mut trace = Trace {
+            stop_at_heading: true,
+            ..Trace::default()
+        };
+
+        tree.walk(&mut trace);
+
+        assert_eq!(trace.log, ["0>Heading { level: 1 }"]);
+    }
+
+    #[test]
+    fn traversals_of_long_documents() {
+        struct Count(usize);
+
+        impl MutVisitor for Count {
+            fn visit_node_mut(&mut self, _: &mut AstNode) {
+                self.0 += 1;
+            }
+        }
+
+        impl TreeVisitor for Count {
+            fn enter(&mut self, _: NodeId, _: &AstNode, _: usize) -> Control {
+                self.0 += 1;
+
+                Control::Continue
+            }
+        }
+
+        let src = "Salt water.\n\n".repeat(50_000);
+        let mut tree = parse(&src);
+
+        let mut count = Count(0);
+        tree.walk(&mut count);
+        assert_eq!(count.0, 100_000);
+
+        let mut count = Count(0);
+        tree.preorder_visit_mut(&mut count);
+        assert_eq!(count.0, 100_000);
+
+        assert!(!format!("{tree:?}").is_empty());
+    }
+
+    #[test]
+    fn traversals_of_deep_documents() {
+        struct Depth(usize);
+
+        impl TreeVisitor for Depth {
+            fn enter(&mut self, _: NodeId, _: &AstNode, depth: usize) -> Control {
+                self.0 = self.0.max(depth);
+
+                Control::Continue
+            }
+        }
+
+        struct Sink;
+
+        impl core::fmt::Write for Sink {
+            fn write_str(&mut self, _: &str) -> core::fmt::Result {
+                Ok(())
+            }
+        }
+
+        let src = ">".repeat(10_000);
+        let tree = Parser::new().max_depth(usize::MAX).parse(&src);
+
+        let mut depth = Depth(0);
+        tree.walk(&mut depth);
+        assert_eq!(depth.0, 10_000 - 1);
+
+        core::fmt::write(&mut Sink, format_args!("{tree:?}")).unwrap();
+    }
+
+    #[test]
+    fn preorder_visit_mut_visits_every_node() {
+        struct Count(usize);
+
+        impl Visitor for Count {
+            fn visit_node(&mut self, _: &AstNode) {
+                self.0 += 1;
+            }
+        }
+
+        impl MutVisitor for Count {
+            fn visit_node_mut(&mut self, _: &mut AstNode) {
+                self.0 += 1;
+            }
+        }
+
+        let mut tree = parse("# Title\n\n- One\n- Two\n\n> Salt water.");
+
+        let mut count = Count(0);
+        tree.preorder_visit(&mut count);
+        let expected = count.0;
+
+        let mut count = Count(0);
+        tree.preorder_visit_mut(&mut count);
+        assert_eq!(count.0, expected);
+    }
+
+    #[test]
+    fn walk_mut_edits() {
+        struct Transform {
+            removed: bool,
+        }
+
+        impl TreeVisitorMut for Transform {
+            fn enter(
+                &mut self,
+                id: NodeId,
+                node: &mut AstNode,
+                _: usize,
+                edits: &mut Edits<AstNode>,
+            ) -> Control {
+                match node.value {
+                    Value::Heading { ref mut level } => {
+                        *level = level.saturating_add(1);
+                        edits.insert_after(
+                            id,
+                            AstNode::new(Value::StyleBreak, Position::ZERO_ZERO, 0),
+                        );
+                    }
+                    Value::Paragraph if !self.removed => {
+                        self.removed = true;
+                        edits.remove(id);
+                    }
+
+                    _ => (),
+                }
+
+                Control::Continue
+            }
+        }
+
+        let src = "Salt water.\n\n# Title\n\nSome text.";
+        let mut tree = parse(src);
+        assert!(tree.walk_mut(&mut Transform { removed: false }).is_empty());
+
+        let mut html = String::new();
+        crate::render::html::push_html(&mut html, tree.events(src));
+
+        assert_eq!(html, "<h2>Title</h2>\n<hr />\n<p>Some text.</p>\n");
+    }
+
+    #[test]
+    fn walk_mut_reports_failed_edits() {
+        struct Conflict;
+
+        impl TreeVisitorMut for Conflict {
+            fn enter(
+                &mut self,
+                id: NodeId,
+                _: &mut AstNode,
+                depth: usize,
+                edits: &mut Edits<AstNode>,
+            ) -> Control {
+                if depth == 0 {
+                    edits.remove(id);
+                    edits.replace(id, AstNode::new(Value::StyleBreak, Position::ZERO_ZERO, 0));
+                    edits.append_child(id, AstNode::new(Value::Text, Position::ZERO_ZERO, 0));
+                }
+
+                Control::SkipChildren
+            }
+        }
+
+        let src = "Salt water.";
+        let mut tree = parse(src);
+        let paragraph = tree.root().unwrap();
+        let allocated = tree.len();
+
+        assert_eq!(tree.walk_mut(&mut Conflict), [paragraph, paragraph]);
+        assert_eq!(tree.len(), allocated);
+        assert_eq!(tree.root(), None);
+    }
+
+    fn html(tree: &TreeArena<AstNode>, src: &str) -> String {
+        let mut html = String::new();
+        crate::render::html::push_html(&mut html, tree.events(src));
+
+        html
+    }
+
+    #[test]
+    fn insert_nodes() {
+        let src = "First.\n\nSecond.";
+        let mut tree = parse(src);
+
+        let first = tree.root().unwrap();
+        let second = tree.get(first).unwrap().next_sibling().unwrap();
+        let style_break = || AstNode::new(Value::StyleBreak, Position::ZERO_ZERO, 0);
+        let text = || AstNode::new(Value::Text, Position::new(0, 5), 0);
+
+        assert!(tree.insert_before(first, style_break()).is_some());
+        assert!(tree.insert_after(first, style_break()).is_some());
+        assert!(tree.prepend_child(second, text()).is_some());
+        assert!(tree.append_child(second, text()).is_some());
+
+        assert_eq!(
+            html(&tree, src),
+            "<hr />\n<p>First.</p>\n<hr />\n<p>FirstSecond.First</p>\n"
+        );
+    }
+
+    #[test]
+    fn move_and_replace_subtrees() {
+        let src = "- One\n\n- Two\n\n- Three\n";
+        let mut tree = parse(src);
+
+        let list = tree.root().unwrap();
+        let one = tree.get(list).unwrap().first_child().unwrap();
+        let two = tree.get(one).unwrap().next_sibling().unwrap();
+        let three = tree.get(two).unwrap().next_sibling().unwrap();
+
+        assert!(tree.move_subtree(three, Anchor::Before(one)));
+        assert!(!tree.move_subtree(list, Anchor::FirstChild(one)));
+
+        let style_break = tree
+            .new_node(AstNode::new(Value::StyleBreak, Position::ZERO_ZERO, 0))
+            .unwrap();
+        assert!(tree.replace(two, style_break));
+
+        assert_eq!(
+            html(&tree, src),
+            "<ul>\n<li><p>Three</p>\n</li>\n<li><p>One</p>\n</li>\n<hr />\n</ul>\n"
+        );
+
+        assert!(tree.detach(one));
+        assert!(!tree.detach(one));
+        assert!(tree.move_subtree(one, Anchor::After(list)));
+
+        assert_eq!(
+            html(&tree, src),
+            "<ul>\n<li><p>Three</p>\n</li>\n<hr />\n</ul>\n<li><p>One</p>\n</li>\n"
+        );
+    }
+
+    #[test]
+    fn navigation() {
+        let tree = parse("- One\n- Two\n");
+
+        let list = tree.root().unwrap();
+        let children = tree.children(list).collect::<Vec<_>>();
+        let [one, two] = children[..] else {
+            panic!("expected two list items, got {children:?}")
+        };
+
+        let paragraph = tree.get(one).unwrap().first_child().unwrap();
+        let text = tree.get(paragraph).unwrap().first_child().unwrap();
+
+        assert_eq!(tree.parent(text), Some(paragraph));
+        assert_eq!(tree.parent(list), None);
+        assert_eq!(
+            tree.ancestors(text).collect::<Vec<_>>(),
+            [paragraph, one, list]
+        );
+        assert_eq!(tree.following_siblings(one).collect::<Vec<_>>(), [two]);
+        assert_eq!(tree.preceding_siblings(two).collect::<Vec<_>>(), [one]);
+        assert_eq!(tree.descendants(list).count(), 6);
+        assert_eq!(tree.descendants(one).count(), 2);
+        assert_eq!(tree.nodes().count(), 7);
+        assert!(
+            tree.ancestors(text)
+                .any(|id| matches!(tree.get(id).unwrap().data.value, Value::ListItem))
+        );
+    }
+
+    #[test]
+    fn navigation_after_edits() {
+        let src = "- One\n- Two\n\nSalt water.";
+        let mut tree = parse(src);
+
+        let list = tree.root().unwrap();
+        let one = tree.children(list).next().unwrap();
+        let two = tree.following_siblings(one).next().unwrap();
+        let paragraph = tree.children(list).nth(2).unwrap();
+
+        assert!(tree.move_subtree(two, Anchor::Before(one)));
+        assert!(tree.move_subtree(paragraph, Anchor::LastChild(one)));
+
+        assert_eq!(tree.children(list).collect::<Vec<_>>(), [two, one]);
+        assert_eq!(tree.preceding_siblings(one).collect::<Vec<_>>(), [two]);
+        assert_eq!(tree.parent(paragraph), Some(one));
+        assert_eq!(tree.following_siblings(one).count(), 0);
+
+        let order = tree.nodes().collect::<Vec<_>>();
+        assert_eq!(order.len(), tree.descendants(list).count() + 1);
+        assert_eq!(order.last().copied(), tree.descendants(paragraph).last());
+    }
+
+    #[test]
+    fn node_id_layout() {
+        assert_eq!(size_of::<Option<NodeId>>(), size_of::<usize>());
+
+        let tree = parse("Salt water.");
+        let id = tree.root().unwrap();
+
+        assert_eq!(NodeId::new(id.into_raw()), Some(id));
+        assert_eq!(NodeId::new(0), None);
+    }
+
+    #[test]
+    fn stale_ids() {
+        let src = "# Salt
+
+Water.";
+        let mut tree = parse(src);
+
+        let heading = tree.root().unwrap();
+        let text = tree.children(heading).next().unwrap();
+        let paragraph = tree.following_siblings(heading).next().unwrap();
+
+        assert!(tree.remove(heading));
+        assert!(tree.get(heading).is_none());
+        assert!(tree.get(text).is_none());
+        assert!(!tree.remove(heading));
+        assert!(tree.get(paragraph).is_some());
+        assert_eq!(tree.root(), Some(paragraph));
+
+        // ids can't be used with a different document
+        let other = parse(src);
+        assert!(other.get(paragraph).is_none());
+    }
+
+    #[test]
+    fn removed_slots_stay_stale() {
+        let src = "# Salt\n\nWater.";
+        let mut tree = parse(src);
+        let heading = tree.root().unwrap();
+        assert!(tree.remove(heading));
+
+        // the same slot in the next arenas doesn't resolve to the removed node
+        for _ in 0..1_000 {
+            let other = parse(src);
+            let id = other.root().unwrap();
+
+            assert_eq!(
+                (id.node_index(), id.vec_index()),
+                (heading.node_index(), heading.vec_index())
+            );
+            assert!(tree.get(id).is_none());
+        }
+
+        // and neither does it in a reused one
+        let parser = Parser::new();
+        let mut document = crate::document::Document::new();
+
+        for _ in 0..1_000 {
+            parser.parse_into(&mut document, src);
+            let id = document.tree().root().unwrap();
+
+            assert!(tree.get(id).is_none());
+        }
+    }
+
+    #[test]
+    fn reused_slots_get_new_generations() {
+        let mut tree = TreeArena::<u32>::new();
+        let mut first = tree.new_node(0).unwrap();
+        let mut second = tree.new_node(1).unwrap();
+        assert!(tree.remove(second));
+
+        for n in 0..3 {
+            tree.clear();
+
+            let reused = tree.new_node(n).unwrap();
+            assert_eq!(
+                (reused.node_index(), reused.vec_index()),
+                (first.node_index(), first.vec_index())
+            );
+            assert_ne!(reused.generation(), first.generation());
+            assert!(tree.get(first).is_none());
+
+            // as is the slot of a removed node
+            let other = tree.new_node(n).unwrap();
+            assert_ne!(other.generation(), second.generation());
+            assert!(tree.get(second).is_none());
+
+            (first, second) = (reused, other);
+        }
+    }
+
+    #[test]
+    fn buckets_grow_geometrically() {
+        let mut tree = TreeArena::<u32>::new();
+        let first = tree.new_node(0).unwrap();
+        let first_bucket = tree.storage[1].as_ptr();
+
+        let ids = (1..1_000_000)
+            .map(|n| tree.new_node(n).unwrap())
+            .collect::<Vec<_>>();
+
+        // filled buckets are never reallocated
+        assert_eq!(tree.storage[1].as_ptr(), first_bucket);
+        assert_eq!(tree.get(first).map(|node| node.data), Some(0));
+        assert_eq!(tree.len(), 1_000_000);
+        assert!(tree.storage.len() < 20);
+
+        for (bucket, nodes) in tree.storage.iter().enumerate().skip(1) {
+            let capacity = TreeArena::<u32>::bucket_capacity(bucket.try_into().unwrap());
+            assert!(nodes.len() <= capacity);
+        }
+
+        for (n, id) in (1..).zip(ids) {
+            assert_eq!(tree.get(id).map(|node| node.data), Some(n));
+        }
+    }
+
+    #[test]
+    fn bucket_capacity_is_bounded() {
+        assert_eq!(
+            TreeArena::<u32>::bucket_capacity(1),
+            TreeArena::<u32>::DEFAULT_BASE_SIZE
+        );
+        assert_eq!(
+            TreeArena::<u32>::bucket_capacity(2),
+            TreeArena::<u32>::DEFAULT_BASE_SIZE * 2
+        );
+        assert_eq!(
+            TreeArena::<u32>::bucket_capacity(super::BucketIndex::MAX),
+            TreeArena::<u32>::MAX_BUCKET_SIZE
+        );
+
+        // nodes larger than the first bucket still get one slot
+        assert_eq!(TreeArena::<[u8; 16 * 1024]>::bucket_capacity(1), 1);
+    }
+
+    #[test]
+    fn parse_multi_megabyte_documents() {
+        let block = "# Salt\n\nSome *salt* and `water`.\n\n- One\n- Two\n\n> Quote\n\n";
+        let src = block.repeat((4 * 1024 * 1024) / block.len());
+        let tree = parse(&src);
+
+        let nodes = tree.nodes().collect::<Vec<_>>();
+        assert_eq!(nodes.len(), tree.len());
+        assert!(nodes.iter().all(|&id| tree.get(id).is_some()));
+
+        let mut out = String::new();
+        crate::render::html::push_html(&mut out, tree.events(&src));
+        assert!(out.len() > src.len());
+    }
+
+    #[test]
+    fn clear_keeps_the_buckets() {
+        let mut tree = TreeArena::<u32>::new();
+        assert!(tree.storage.is_empty());
+
+        let ids = (0..100_000)
+            .map(|n| tree.new_node(n).unwrap())
+            .collect::<Vec<_>>();
+        let buckets = tree.storage.len();
+        let first_bucket = tree.storage[1].as_ptr();
+
+        tree.clear();
+        assert!(tree.is_empty());
+        assert!(ids.iter().all(|&id| tree.get(id).is_none()));
+
+        let id = tree.new_node(7).unwrap();
+        assert_eq!(tree.get(id).map(|node| node.data), Some(7));
+        assert_eq!(tree.storage[1].as_ptr(), first_bucket);
+
+        for n in 1..100_000 {
+            tree.new_node(n).unwrap();
+        }
+        assert_eq!(tree.storage.len(), buckets);
+    }
+
+    #[test]
+    fn clone_subtree() {
+        let src = "# Salt\n\n- One\n- Two\n";
+        let tree = parse(src);
+
+        let list = tree
+            .nodes()
+            .find(|&id| matches!(tree.get(id).unwrap().data.value, Value::BulletList { .. }))
+            .unwrap();
+
+        let copy = tree.clone_subtree(list);
+        assert_eq!(copy.nodes().count(), tree.descendants(list).count() + 1);
+
+        let mut expected = String::new();
+        push_html(&mut expected, Events::subtree(&tree, src, list));
+        assert_eq!(html(&copy, src), expected);
+
+        assert!(TreeArena::<AstNode>::new().clone_subtree(list).is_empty());
+    }
+
+    #[test]
+    fn graft_from_another_source() {
+        let src = "> Salt.\n";
+        let mut tree = parse(src);
+
+        let mut sources = SourceMap::new();
+        sources.add("main.md", src);
+        let include = sources.add("water.md", "- *Water*\n");
+        let other = Parser::new().source(include).parse("- *Water*\n");
+
+        let quote = tree.root().unwrap();
+        let list = other.root().unwrap();
+        let grafted = tree.graft(quote, &other, list, include).unwrap();
+
+        assert_eq!(tree.parent(grafted), Some(quote));
+        assert_eq!(tree.children(quote).last(), Some(grafted));
+        assert_eq!(
+            tree.descendants(grafted).count(),
+            other.descendants(list).count()
+        );
+
+        // grafted nodes still point into their own source
+        for id in tree.descendants(grafted) {
+            let node = &tree.get(id).unwrap().data;
+            assert_eq!(node.pos.source, include);
+
+            if let Value::Text = node.value {
+                assert_eq!(node.as_str(&sources), "*Water*\n");
+                assert_eq!(node.as_str(src), "");
+            }
+        }
+
+        assert!(
+            tree.descendants(quote)
+                .take(2)
+                .all(|id| tree.get(id).unwrap().data.pos.source == SourceId::MAIN)
+        );
+        assert!(tree.graft(list, &other, list, include).is_none());
+    }
 }
@@ -1,10 +1,7 @@
-use core::str;
-
 pub struct Walker<'w> {
     data: &'w [u8],
     len: usize,
     position: usize,
-    last: Option<u8>,
 }
 
 #[derive(Debug)]
@@ -22,19 +19,10 @@ impl StrRange {
         (self.start, self.end)
     }
 
-    pub fn resolve<'a, 'b>(&'b self, data: &'a [u8]) -> &'a str
-    where
-        'a: 'b,
-    {
-        let bytes = data
-            .get(self.start..self.end)
-            .expect("out of bounds access");
-
-        // Safety:
-        //
-        // The slice and it's bounds point to a valid subslice
-        // of utf-8 characters.
-        unsafe { core::str::from_utf8_unchecked(bytes) }
+    /// Returns the range of `data`, `None` if it's out of
+    /// bounds or doesn't lie on character boundaries.
+    pub fn resolve<'a>(&self, data: &'a str) -> Option<&'a str> {
+        data.get(self.start..self.end)
     }
 
     pub fn adjust<F>(&mut self, func: F)
@@ -49,63 +37,19 @@ impl<'w> Walker<'w> {
     /// Creates a new `Walker`
     pub fn new(data: &'w str) -> Self {
         Self {
-            last: None,
             position: 0,
             len: data.len(),
             data: data.as_bytes(),
         }
     }
 
-    /// Obtains a `&str` between `start` and `end`
-    ///
-    /// # Panics
-    /// if `end` is bigger or equal to the length of data stored
-    /// and/or if the data doesn't create a proper utf-8 string
-    pub(crate) fn get(&self, start: usize, end: usize) -> &str {
-        debug_assert!(end <= self.len);
-
-        unsafe {
-            let data = self.data.get_unchecked(start..end);
-
-            debug_assert!(core::str::from_utf8(data).is_ok());
-            str::from_utf8_unchecked(data)
-        }
-    }
-
     /// Returns the data as a `&[u8]`
-    pub(crate) fn data(&self) -> &[u8] {
+    pub fn data(&self) -> &[u8] {
         self.data
     }
 
-    /// Returns data as a `&str`
-    pub(crate) fn data_str(&self) -> &str {
-        unsafe { core::str::from_utf8_unchecked(self.data()) }
-    }
-
-    /// Returns section of data between `initial` and `self.position()`
-    pub(crate) fn string_from_offset(&self, initial: usize) -> &str {
-        debug_assert!(
-            self.position() <= self.data().len(),
-            "position of cursor is further than the data's length"
-        );
-
-        debug_assert!(
-            initial <= self.position(),
-            "offset is bigger than the current position"
-        );
-
-        self.get(initial, self.position())
-    }
-
-    /// Creates a `Walker` from the sections of data between `offset` and `self.position()`
-    pub(crate) fn walker_from_initial(&self, offset: usize) -> Walker<'_> {
-        let data = self.string_from_offset(offset);
-
-        Walker::new(data)
-    }
-
     /// Goes one character forward.
-    pub(crate) fn next(&mut self) -> Option<u8> {
+    pub fn next_byte(&mut self) -> Option<u8> {
         if self.position >= self.len {
             return None;
         }
@@ -113,66 +57,52 @@ impl<'w> Walker<'w> {
         let val = self.data[self.position];
         self.position += 1;
 
-        self.last = Some(val);
-
         Some(val)
     }
 
     /// Goes `steps` steps of characters back
-    pub(crate) fn peek_back(&mut self, steps: usize) -> Option<u8> {
-        if (self.position + steps > self.len) | (steps > self.len) {
-            return None;
-        }
+    pub fn peek_back(&self, steps: usize) -> Option<u8> {
+        let ix = self.position.checked_sub(steps)?;
 
-        Some(self.data[self.position - steps])
+        self.data.get(ix).copied()
     }
 
     /// Peeks `chars` forward
     /// Note: `peek`ing 0 characters, will give the character
     /// at the current position
-    pub(crate) fn peek(&self, chars: usize) -> Option<u8> {
+    pub fn peek(&self, chars: usize) -> Option<u8> {
         self.data.get(self.position() + chars).copied()
     }
 
     /// Returns the position
-    pub(crate) fn position(&self) -> usize {
+    pub fn position(&self) -> usize {
         self.position
     }
 
-    /// Advances the position by `chars`
-    pub(crate) fn advance(&mut self, chars: usize) {
-        self.position += chars
-    }
-
     /// Retreats the position by `chars`
-    pub(crate) fn retreat(&mut self, chars: usize) {
+    fn retreat(&mut self, chars: usize) {
         self.position -= chars
     }
 
     /// Sets the position
-    pub(crate) fn set_position(&mut self, pos: usize) {
+    fn set_position(&mut self, pos: usize) {
         self.position = pos
     }
 
     /// Checks if the next char is equal to `target`
-    pub(crate) fn is_next_char(&mut self, target: u8) -> bool {
+    pub fn is_next_char(&self, target: u8) -> bool {
         self.peek(0) == Some(target)
     }
 
     /// Returns the remainder of bytes
-    pub(crate) fn remaining(&self) -> usize {
+    fn remaining(&self) -> usize {
         self.data.len() - self.position()
     }
 
-    /// Checks if the walker is at the end
-    pub(crate) fn end(&self) -> bool {
-        self.position() > self.data().len()
-    }
-
     /// Executes the given closure, using the next character as an argument
     /// returning a boolean
     /// If it's EOF, returns false anyway
-    pub(crate) fn is_next_pred<F>(&mut self, pred: F) -> bool
+    pub fn is_next_pred<F>(&self, pred: F) -> bool
     where
         F: FnOnce(u8) -> bool,
     {
@@ -181,19 +111,19 @@ impl<'w> Walker<'w> {
 
     /// Goes forward till it hits a character
     /// as in:
-    /// ```rust,ignore
-    /// use oska::walker::Walker;
+    /// ```rust
+    /// use noumea::walker::Walker;
     ///
     /// let text = "Haha!";
-    /// let mut w = Walker::new(text.as_bytes());
+    /// let mut w = Walker::new(text);
     ///
-    /// assert!(w.till(b'!') == Some("Haha"));
+    /// assert!(w.till(b'!').unwrap().resolve(text) == Some("Haha"));
     /// ```
-    pub(crate) fn till(&mut self, target: u8) -> Option<StrRange> {
+    pub fn till(&mut self, target: u8) -> Option<StrRange> {
         let start = self.position();
         let mut found = false;
 
-        while let Some(char) = self.next() {
+        while let Some(char) = self.next_byte() {
             if char == target {
                 found = true;
                 break;
@@ -214,48 +144,21 @@ impl<'w> Walker<'w> {
         }
     }
 
-    /// Goes forward till it hits a character
-    /// doesn't care if it doesn't find the actual target
-    /// as in:
-    /// ```rust,ignore
-    /// use oska::walker::Walker;
-    ///
-    /// let text = "Haha!";
-    /// let mut w = Walker::new(text.as_bytes());
-    ///
-    /// assert!(w.till(b'!') == Some("Haha"));
-    /// ```
-    pub(crate) fn till_inclusive(&mut self, target: u8) -> &str {
-        let start = self.position();
-
-        while let Some(char) = self.next() {
-            if char == target {
-                break;
-            }
-
-            if self.is_next_char(target) {
-                break;
-            }
-        }
-
-        self.get(start, self.position())
-    }
-
     /// Goes forward till it stops finding a character
     /// as in:
-    /// ```rust,ignore
-    /// use oska::walker::Walker;
+    /// ```rust
+    /// use noumea::walker::Walker;
     ///
     /// let text = "***A";
-    /// let mut w = Walker::new(text.as_bytes());
+    /// let mut w = Walker::new(text);
     ///
     /// assert!(w.till_not(b'*') == 3);
-    /// assert!(w.next().unwrap() == b'A');
+    /// assert!(w.next_byte().unwrap() == b'A');
     /// ```
-    pub(crate) fn till_not(&mut self, target: u8) -> usize {
+    pub fn till_not(&mut self, target: u8) -> usize {
         let mut count = 0;
 
-        while let Some(val) = self.next() {
+        while let Some(val) = self.next_byte() {
             if val == target {
                 count += 1;
             } else {
@@ -270,7 +173,7 @@ impl<'w> Walker<'w> {
     /// Attempts to find `pat` in the remaining part of the `Walker`'s data
     /// if it succeeds, it returns `true`
     /// else, it comes back to the original position and returns `false`
-    pub(crate) fn find_string(&mut self, pat: &str) -> bool {
+    pub fn find_string(&mut self, pat: &str) -> bool {
         if pat.len() > self.remaining() {
             return false;
         }
@@ -280,7 +183,7 @@ impl<'w> Walker<'w> {
         let mut found = true;
 
         for pat_byte in pat.bytes() {
-            let byte = match self.next() {
+            let byte = match self.next_byte() {
                 None => break,
                 Some(val) => val,
             };
@@ -305,9 +208,9 @@ mod tests {
         let text = "******";
         let mut w = Walker::new(text);
 
-        while let Some(_) = w.next() {}
+        while w.next_byte().is_some() {}
 
-        assert!(w.next().is_none());
+        assert!(w.next_byte().is_none());
 
         assert!(w.position() == 6);
     }
@@ -318,11 +221,13 @@ mod tests {
 
         let mut w = Walker::new(text);
 
-        assert!(w.next().unwrap() == b'A');
+        assert!(w.next_byte().unwrap() == b'A');
 
-        assert!(w.next().unwrap() == b'B');
+        assert!(w.next_byte().unwrap() == b'B');
 
         assert!(w.peek_back(1).unwrap() == b'B');
+
+        assert!(w.peek_back(3).is_none());
     }
 
     #[test]
@@ -341,7 +246,7 @@ mod tests {
 
         let mut w = Walker::new(text);
 
-        assert!(w.next().unwrap() == b'H');
+        assert!(w.next_byte().unwrap() == b'H');
 
         assert!(w.is_next_char(b'A'));
     }
@@ -352,7 +257,7 @@ mod tests {
 
         let mut w = Walker::new(text);
 
-        assert!(w.next().unwrap() == b'A');
+        assert!(w.next_byte().unwrap() == b'A');
         assert!(w.is_next_pred(|char| char == b'B'));
     }
 
@@ -371,7 +276,7 @@ mod tests {
 
         let mut w = Walker::new(text);
 
-        let string = w.till(b'!').unwrap().resolve(text.as_bytes());
+        let string = w.till(b'!').unwrap().resolve(text).unwrap();
 
         assert!(string == "i like cake");
     }
@@ -383,7 +288,7 @@ mod tests {
         let mut w = Walker::new(text);
 
         assert!(w.till_not(b'*') == 2);
-        assert!(w.next() == Some(b'W'))
+        assert!(w.next_byte() == Some(b'W'))
     }
 
     #[test]
@@ -398,7 +303,7 @@ mod tests {
         assert!(did_it_find_pattern, "pattern wasn't found but it should be");
 
         let leftover = core::str::from_utf8(
-            &w.data()
+            w.data()
                 .get(w.position()..)
                 .expect("out of bounds access in `find_string`"),
         )